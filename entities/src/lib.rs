@@ -5,10 +5,19 @@ pub mod prelude;
 pub mod active_persona;
 pub mod channel_policy;
 pub mod channel_settings;
+pub mod conversation;
+pub mod conversation_message;
+pub mod conversation_summary;
+pub mod global_policy;
 pub mod guild_policy;
 pub mod guild_settings;
 pub mod member_policy;
 pub mod member_settings;
+pub mod model;
 pub mod persona;
+pub mod rate_limit_bucket;
+pub mod role_channel_policy;
 pub mod role_policy;
+pub mod role_settings;
 pub mod sea_orm_active_enums;
+pub mod token_usage;
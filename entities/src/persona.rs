@@ -1,6 +1,5 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.12.1
 
-use super::sea_orm_active_enums::LlmModel;
 use sea_orm::entity::prelude::*;
 
 #[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
@@ -13,13 +12,26 @@ pub struct Model {
     pub description: Option<String>,
     pub prompt: String,
     pub builtin: bool,
-    pub model: LlmModel,
+    pub model: String,
+    pub tools: Json,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
     #[sea_orm(has_many = "super::active_persona::Entity")]
     ActivePersona,
+    #[sea_orm(has_many = "super::conversation::Entity")]
+    Conversation,
+    #[sea_orm(
+        belongs_to = "super::model::Entity",
+        from = "Column::Model",
+        to = "super::model::Column::Name",
+        on_update = "Cascade",
+        on_delete = "Restrict"
+    )]
+    Model,
+    #[sea_orm(has_many = "super::token_usage::Entity")]
+    TokenUsage,
 }
 
 impl Related<super::active_persona::Entity> for Entity {
@@ -28,4 +40,22 @@ impl Related<super::active_persona::Entity> for Entity {
     }
 }
 
+impl Related<super::conversation::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Conversation.def()
+    }
+}
+
+impl Related<super::model::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Model.def()
+    }
+}
+
+impl Related<super::token_usage::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::TokenUsage.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
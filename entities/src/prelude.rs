@@ -3,9 +3,18 @@
 pub use super::active_persona::Entity as ActivePersona;
 pub use super::channel_policy::Entity as ChannelPolicy;
 pub use super::channel_settings::Entity as ChannelSettings;
+pub use super::conversation::Entity as Conversation;
+pub use super::conversation_message::Entity as ConversationMessage;
+pub use super::conversation_summary::Entity as ConversationSummary;
+pub use super::global_policy::Entity as GlobalPolicy;
 pub use super::guild_policy::Entity as GuildPolicy;
 pub use super::guild_settings::Entity as GuildSettings;
 pub use super::member_policy::Entity as MemberPolicy;
 pub use super::member_settings::Entity as MemberSettings;
+pub use super::model::Entity as Model;
 pub use super::persona::Entity as Persona;
+pub use super::rate_limit_bucket::Entity as RateLimitBucket;
+pub use super::role_channel_policy::Entity as RoleChannelPolicy;
 pub use super::role_policy::Entity as RolePolicy;
+pub use super::role_settings::Entity as RoleSettings;
+pub use super::token_usage::Entity as TokenUsage;
@@ -5,6 +5,18 @@ mod m20230806_020929_create_personas;
 mod m20230808_030829_seed_default_personas;
 mod m20230830_031030_gpt_4;
 mod m20240705_062830_gpt_4o;
+mod m20261017_010000_create_models;
+mod m20261017_020000_create_conversation_summary;
+mod m20261017_030000_persona_tools;
+mod m20261017_040000_create_conversations;
+mod m20261017_050000_create_token_usage;
+mod m20261017_060000_token_usage_persona;
+mod m20261017_070000_create_rate_limit_buckets;
+mod m20261017_080000_create_role_settings;
+mod m20261017_090000_create_global_policy;
+mod m20261017_094000_model_use_names;
+mod m20261017_095000_policy_action_patterns;
+mod m20261017_100000_create_role_channel_policy;
 
 pub use sea_orm_migration::prelude::*;
 
@@ -21,6 +33,18 @@ impl MigratorTrait for Migrator {
             Box::new(m20230808_030829_seed_default_personas::Migration),
             Box::new(m20230830_031030_gpt_4::Migration),
             Box::new(m20240705_062830_gpt_4o::Migration),
+            Box::new(m20261017_010000_create_models::Migration),
+            Box::new(m20261017_020000_create_conversation_summary::Migration),
+            Box::new(m20261017_030000_persona_tools::Migration),
+            Box::new(m20261017_040000_create_conversations::Migration),
+            Box::new(m20261017_050000_create_token_usage::Migration),
+            Box::new(m20261017_060000_token_usage_persona::Migration),
+            Box::new(m20261017_070000_create_rate_limit_buckets::Migration),
+            Box::new(m20261017_080000_create_role_settings::Migration),
+            Box::new(m20261017_090000_create_global_policy::Migration),
+            Box::new(m20261017_094000_model_use_names::Migration),
+            Box::new(m20261017_095000_policy_action_patterns::Migration),
+            Box::new(m20261017_100000_create_role_channel_policy::Migration),
         ]
     }
 }
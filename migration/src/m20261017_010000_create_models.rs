@@ -0,0 +1,150 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::prelude::extension::postgres::Type;
+use sea_orm_migration::sea_orm::{EnumIter, Iterable};
+use crate::m20230806_020929_create_personas::{LLMModel, Persona};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const DEFAULT_MODEL: &str = "gpt-3.5-turbo";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Model::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Model::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Model::Name).string().not_null().unique_key())
+                    .col(ColumnDef::new(Model::Provider).string().not_null())
+                    .col(ColumnDef::new(Model::ContextWindow).integer().not_null())
+                    .col(ColumnDef::new(Model::PromptPrice).double().not_null().default(0.0))
+                    .col(ColumnDef::new(Model::CompletionPrice).double().not_null().default(0.0))
+                    .col(ColumnDef::new(Model::Vision).boolean().not_null().default(false))
+                    .col(ColumnDef::new(Model::Tools).boolean().not_null().default(false))
+                    .to_owned(),
+            )
+            .await?;
+
+        // Seed the models previously available through the `llm_model` enum
+        // so existing personas keep working
+        manager.exec_stmt(
+            Query::insert()
+                .into_table(Model::Table)
+                .columns([
+                    Model::Name,
+                    Model::Provider,
+                    Model::ContextWindow,
+                    Model::PromptPrice,
+                    Model::CompletionPrice,
+                    Model::Vision,
+                    Model::Tools,
+                ])
+                .values_panic([
+                    DEFAULT_MODEL.into(), "openai".into(), 16385.into(), 0.5.into(), 1.5.into(), false.into(), true.into(),
+                ])
+                .values_panic([
+                    "gpt-4o".into(), "openai".into(), 128000.into(), 5.0.into(), 15.0.into(), true.into(), true.into(),
+                ])
+                .to_owned()
+        ).await?;
+
+        let db = manager.get_connection();
+        // Can't use SeaQuery to change the column type since it can't express the `USING` clause
+        db.execute_unprepared(
+            r#"ALTER TABLE persona
+                   ALTER COLUMN model DROP DEFAULT,
+                   ALTER COLUMN model TYPE varchar USING model::text,
+                   ALTER COLUMN model SET DEFAULT 'gpt-3.5-turbo';"#
+        ).await?;
+
+        manager.create_foreign_key(
+            ForeignKey::create()
+                .name(PERSONA_MODEL_FK)
+                .from(Persona::Table, Persona::Model)
+                .to(Model::Table, Model::Name)
+                .on_update(ForeignKeyAction::Cascade)
+                .on_delete(ForeignKeyAction::Restrict)
+                .to_owned()
+        ).await?;
+
+        manager.drop_type(
+            Type::drop()
+                .name(LLMModel::Table)
+                .to_owned()
+        ).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager.drop_foreign_key(
+            ForeignKey::drop()
+                .name(PERSONA_MODEL_FK)
+                .table(Persona::Table)
+                .to_owned()
+        ).await?;
+
+        // Anything not representable by the old enum falls back to the default model
+        manager.exec_stmt(
+            Query::update()
+                .table(Persona::Table)
+                .value(Persona::Model, DEFAULT_MODEL)
+                .and_where(Expr::col(Persona::Model).is_not_in(LegacyModel::iter().skip(1).map(|m| m.to_string())))
+                .to_owned()
+        ).await?;
+
+        manager.create_type(
+            Type::create()
+                .as_enum(LLMModel::Table)
+                .values(LegacyModel::iter().skip(1))
+                .to_owned(),
+        ).await?;
+
+        manager.get_connection().execute_unprepared(
+            r#"ALTER TABLE persona
+                   ALTER COLUMN model DROP DEFAULT,
+                   ALTER COLUMN model TYPE llm_model USING model::llm_model,
+                   ALTER COLUMN model SET DEFAULT 'gpt-3.5-turbo';"#
+        ).await?;
+
+        manager
+            .drop_table(Table::drop().table(Model::Table).to_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+const PERSONA_MODEL_FK: &str = "FK_PersonaModel";
+
+#[derive(DeriveIden)]
+pub(crate) enum Model {
+    Table,
+    Id,
+    Name,
+    Provider,
+    ContextWindow,
+    PromptPrice,
+    CompletionPrice,
+    Vision,
+    Tools,
+}
+
+/// Values of the `llm_model` enum as of [crate::m20240705_062830_gpt_4o]
+#[derive(DeriveIden, EnumIter)]
+enum LegacyModel {
+    Table,
+    #[sea_orm(iden = "gpt-3.5-turbo")]
+    Gpt35Turbo,
+    #[sea_orm(iden = "gpt-4o")]
+    Gpt4o,
+}
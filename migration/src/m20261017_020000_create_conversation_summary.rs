@@ -0,0 +1,58 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ConversationSummary::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ConversationSummary::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(ConversationSummary::ChannelId)
+                            .big_unsigned()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ConversationSummary::LastMessageId)
+                            .big_unsigned()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(ConversationSummary::Summary).text().not_null())
+                    .index(
+                        Index::create()
+                            .name("ChannelLastMessage")
+                            .unique()
+                            .col(ConversationSummary::ChannelId)
+                            .col(ConversationSummary::LastMessageId),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ConversationSummary::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum ConversationSummary {
+    Table,
+    Id,
+    ChannelId,
+    LastMessageId,
+    Summary,
+}
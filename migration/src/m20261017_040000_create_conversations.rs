@@ -0,0 +1,148 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Conversation::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Conversation::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(Conversation::GuildId).big_unsigned().null())
+                    .col(ColumnDef::new(Conversation::ChannelId).big_unsigned().not_null())
+                    .col(ColumnDef::new(Conversation::UserId).big_unsigned().not_null())
+                    .col(ColumnDef::new(Conversation::MessageId).big_unsigned().not_null())
+                    .col(ColumnDef::new(Conversation::PersonaId).integer().null())
+                    .col(ColumnDef::new(Conversation::Model).string().not_null())
+                    .col(ColumnDef::new(Conversation::Response).text().not_null())
+                    .col(ColumnDef::new(Conversation::FinishReason).string().null())
+                    .col(ColumnDef::new(Conversation::PromptTokens).integer().null())
+                    .col(ColumnDef::new(Conversation::CompletionTokens).integer().null())
+                    .col(ColumnDef::new(Conversation::LatencyMs).integer().not_null())
+                    .col(
+                        ColumnDef::new(Conversation::CreatedAt)
+                            .timestamp_with_time_zone()
+                            .not_null()
+                            .default(Expr::current_timestamp()),
+                    )
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from_col(Conversation::PersonaId)
+                            .to(Persona::Table, Persona::Id)
+                            .on_delete(ForeignKeyAction::SetNull))
+                    .to_owned(),
+            )
+            .await?;
+
+        // Conversations are pruned per guild (or DM channel) by age
+        manager
+            .create_index(
+                Index::create()
+                    .name("ConversationGuildCreated")
+                    .table(Conversation::Table)
+                    .col(Conversation::GuildId)
+                    .col(Conversation::CreatedAt)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .name("ConversationChannelCreated")
+                    .table(Conversation::Table)
+                    .col(Conversation::ChannelId)
+                    .col(Conversation::CreatedAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(ConversationMessage::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ConversationMessage::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(ConversationMessage::ConversationId).integer().not_null())
+                    .col(ColumnDef::new(ConversationMessage::Position).integer().not_null())
+                    .col(ColumnDef::new(ConversationMessage::Role).string().not_null())
+                    .col(ColumnDef::new(ConversationMessage::Name).string().null())
+                    .col(ColumnDef::new(ConversationMessage::Content).text().null())
+                    .col(ColumnDef::new(ConversationMessage::ContentHash).string().null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .from_col(ConversationMessage::ConversationId)
+                            .to(Conversation::Table, Conversation::Id)
+                            .on_delete(ForeignKeyAction::Cascade))
+                    .index(
+                        Index::create()
+                            .name("ConversationMessagePosition")
+                            .unique()
+                            .col(ConversationMessage::ConversationId)
+                            .col(ConversationMessage::Position),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ConversationMessage::Table).to_owned())
+            .await?;
+        manager
+            .drop_table(Table::drop().table(Conversation::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Conversation {
+    Table,
+    Id,
+    GuildId,
+    ChannelId,
+    UserId,
+    MessageId,
+    PersonaId,
+    Model,
+    Response,
+    FinishReason,
+    PromptTokens,
+    CompletionTokens,
+    LatencyMs,
+    CreatedAt,
+}
+
+#[derive(DeriveIden)]
+enum ConversationMessage {
+    Table,
+    Id,
+    ConversationId,
+    Position,
+    Role,
+    Name,
+    Content,
+    ContentHash,
+}
+
+#[derive(DeriveIden)]
+enum Persona {
+    Table,
+    Id,
+}
@@ -0,0 +1,75 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(TokenUsage::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(TokenUsage::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(TokenUsage::GuildId).big_unsigned().null())
+                    .col(ColumnDef::new(TokenUsage::UserId).big_unsigned().not_null())
+                    .col(ColumnDef::new(TokenUsage::Model).string().not_null())
+                    .col(ColumnDef::new(TokenUsage::Day).date().not_null())
+                    .col(ColumnDef::new(TokenUsage::Requests).integer().not_null().default(0))
+                    .col(ColumnDef::new(TokenUsage::PromptTokens).big_integer().not_null().default(0))
+                    .col(ColumnDef::new(TokenUsage::CompletionTokens).big_integer().not_null().default(0))
+                    .col(ColumnDef::new(TokenUsage::Cost).double().not_null().default(0.0))
+                    // One row of totals per guild, user, model and day
+                    .index(
+                        Index::create()
+                            .unique()
+                            .name("TokenUsageGuildUserModelDay")
+                            .col(TokenUsage::GuildId)
+                            .col(TokenUsage::UserId)
+                            .col(TokenUsage::Model)
+                            .col(TokenUsage::Day)
+                            .nulls_not_distinct(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("TokenUsageGuildDay")
+                    .table(TokenUsage::Table)
+                    .col(TokenUsage::GuildId)
+                    .col(TokenUsage::Day)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(TokenUsage::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum TokenUsage {
+    Table,
+    Id,
+    GuildId,
+    UserId,
+    Model,
+    Day,
+    Requests,
+    PromptTokens,
+    CompletionTokens,
+    Cost,
+}
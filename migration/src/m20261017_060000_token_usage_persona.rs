@@ -0,0 +1,136 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TokenUsage::Table)
+                    .add_column(ColumnDef::new(TokenUsage::PersonaId).integer().null())
+                    .add_foreign_key(
+                        TableForeignKey::new()
+                            .name("TokenUsagePersona")
+                            .from_tbl(TokenUsage::Table)
+                            .from_col(TokenUsage::PersonaId)
+                            .to_tbl(Persona::Table)
+                            .to_col(Persona::Id)
+                            // Usage is merged into the deleted persona bucket by a trigger instead,
+                            // setting it to null could collide with the bucket's existing rows
+                            .on_delete(ForeignKeyAction::NoAction),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        // Usage is now also totalled per persona
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("TokenUsageGuildUserModelDay")
+                    .table(TokenUsage::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .unique()
+                    .name("TokenUsageGuildUserPersonaModelDay")
+                    .table(TokenUsage::Table)
+                    .col(TokenUsage::GuildId)
+                    .col(TokenUsage::UserId)
+                    .col(TokenUsage::PersonaId)
+                    .col(TokenUsage::Model)
+                    .col(TokenUsage::Day)
+                    .nulls_not_distinct()
+                    .to_owned(),
+            )
+            .await?;
+
+        let db = manager.get_connection();
+        db.execute_unprepared(MERGE_DELETED_PERSONA_USAGE).await?;
+        db.execute_unprepared(
+            "CREATE TRIGGER merge_deleted_persona_usage BEFORE DELETE ON persona \
+             FOR EACH ROW EXECUTE FUNCTION merge_deleted_persona_usage()",
+        ).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        db.execute_unprepared("DROP TRIGGER merge_deleted_persona_usage ON persona").await?;
+        db.execute_unprepared("DROP FUNCTION merge_deleted_persona_usage()").await?;
+
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("TokenUsageGuildUserPersonaModelDay")
+                    .table(TokenUsage::Table)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(TokenUsage::Table)
+                    .drop_foreign_key(Alias::new("TokenUsagePersona"))
+                    .drop_column(TokenUsage::PersonaId)
+                    .to_owned(),
+            )
+            .await?;
+        manager
+            .create_index(
+                Index::create()
+                    .unique()
+                    .name("TokenUsageGuildUserModelDay")
+                    .table(TokenUsage::Table)
+                    .col(TokenUsage::GuildId)
+                    .col(TokenUsage::UserId)
+                    .col(TokenUsage::Model)
+                    .col(TokenUsage::Day)
+                    .nulls_not_distinct()
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+/// Adds a deleted persona's usage to the `persona_id IS NULL` rows, so totals survive the delete
+const MERGE_DELETED_PERSONA_USAGE: &str = r#"
+CREATE FUNCTION merge_deleted_persona_usage() RETURNS trigger AS $$
+BEGIN
+    INSERT INTO token_usage (guild_id, user_id, persona_id, model, day, requests, prompt_tokens, completion_tokens, cost)
+    SELECT guild_id, user_id, NULL, model, day, requests, prompt_tokens, completion_tokens, cost
+    FROM token_usage
+    WHERE persona_id = OLD.id
+    ON CONFLICT (guild_id, user_id, persona_id, model, day) DO UPDATE SET
+        requests = token_usage.requests + EXCLUDED.requests,
+        prompt_tokens = token_usage.prompt_tokens + EXCLUDED.prompt_tokens,
+        completion_tokens = token_usage.completion_tokens + EXCLUDED.completion_tokens,
+        cost = token_usage.cost + EXCLUDED.cost;
+    DELETE FROM token_usage WHERE persona_id = OLD.id;
+    RETURN OLD;
+END
+$$ LANGUAGE plpgsql
+"#;
+
+#[derive(DeriveIden)]
+enum TokenUsage {
+    Table,
+    GuildId,
+    UserId,
+    PersonaId,
+    Model,
+    Day,
+}
+
+#[derive(DeriveIden)]
+enum Persona {
+    Table,
+    Id,
+}
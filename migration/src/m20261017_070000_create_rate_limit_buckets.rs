@@ -0,0 +1,53 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(RateLimitBucket::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(RateLimitBucket::Key)
+                            .string()
+                            .not_null()
+                            .primary_key(),
+                    )
+                    .col(ColumnDef::new(RateLimitBucket::Tokens).double().not_null())
+                    .col(ColumnDef::new(RateLimitBucket::UpdatedAt).timestamp_with_time_zone().not_null())
+                    // When the bucket will have refilled completely, after which the row can be dropped
+                    .col(ColumnDef::new(RateLimitBucket::ExpiresAt).timestamp_with_time_zone().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("RateLimitBucketExpiresAt")
+                    .table(RateLimitBucket::Table)
+                    .col(RateLimitBucket::ExpiresAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RateLimitBucket::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum RateLimitBucket {
+    Table,
+    Key,
+    Tokens,
+    UpdatedAt,
+    ExpiresAt,
+}
@@ -0,0 +1,54 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(RoleSettings::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(RoleSettings::Id)
+                            .integer()
+                            .not_null()
+                            .auto_increment()
+                            .primary_key(),
+                    )
+                    .col(
+                        ColumnDef::new(RoleSettings::RoleId)
+                            .big_unsigned()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(RoleSettings::Key).string().not_null())
+                    .col(ColumnDef::new(RoleSettings::Value).json().not_null())
+                    .index(
+                        Index::create()
+                            .name("RoleKey")
+                            .unique()
+                            .col(RoleSettings::RoleId)
+                            .col(RoleSettings::Key),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RoleSettings::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum RoleSettings {
+    Table,
+    Id,
+    RoleId,
+    Key,
+    Value,
+}
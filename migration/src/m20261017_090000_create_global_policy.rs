@@ -0,0 +1,55 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::Iterable;
+use crate::m20230710_001739_create_permissions::Effect;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(GlobalPolicy::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(GlobalPolicy::Id)
+                            .integer()
+                            .auto_increment()
+                            .primary_key()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(GlobalPolicy::Action).string().not_null())
+                    .col(
+                        ColumnDef::new(GlobalPolicy::Effect)
+                            .enumeration(Effect::Table, Effect::iter().skip(1))
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(GlobalPolicy::Until).timestamp_with_time_zone())
+                    .index(
+                        Index::create()
+                            .unique()
+                            .name("GlobalAction")
+                            .col(GlobalPolicy::Action),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(GlobalPolicy::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum GlobalPolicy {
+    Table,
+    Id,
+    Action,
+    Effect,
+    Until,
+}
@@ -0,0 +1,53 @@
+use sea_orm_migration::prelude::*;
+
+/// Policy tables with `model.use` policies, and the columns identifying a policy's principle
+const TABLES: [(&str, &[&str]); 5] = [
+    ("guild_policy", &["guild_id"]),
+    ("channel_policy", &["channel_id"]),
+    ("role_policy", &["role_id"]),
+    ("member_policy", &["guild_id", "user_id"]),
+    ("global_policy", &[]),
+];
+
+/// `model.use` specifiers of the old model choices, and the registry name of the model each one was
+const RENAMED: [(&str, &str); 2] = [
+    ("model.use:GPT 4", "model.use:gpt-4o"),
+    ("model.use:GPT 3.5", "model.use:gpt-3.5-turbo"),
+];
+
+/// `model.use` is now specified by a model's name in the registry, rather than the display name of its choice
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        rename(manager, RENAMED).await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        rename(manager, RENAMED.map(|(old, new)| (new, old))).await
+    }
+}
+
+async fn rename(manager: &SchemaManager<'_>, renamed: [(&str, &str); 2]) -> Result<(), DbErr> {
+    let db = manager.get_connection();
+
+    for (table, keys) in TABLES {
+        // Skip rows whose new action is already saved for the same principle
+        let same_principle = keys
+            .iter()
+            .map(|key| format!(" AND existing.{key} = {table}.{key}"))
+            .collect::<String>();
+
+        for (from, to) in renamed {
+            db.execute_unprepared(&format!(
+                "UPDATE {table} SET action = '{to}' \
+                 WHERE action = '{from}' \
+                 AND NOT EXISTS (SELECT 1 FROM {table} existing WHERE existing.action = '{to}'{same_principle})"
+            )).await?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,80 @@
+use sea_orm_migration::prelude::*;
+
+/// Policy tables saved before actions became patterns, and the columns identifying a policy's principle
+const TABLES: [(&str, &[&str]); 5] = [
+    ("guild_policy", &["guild_id"]),
+    ("channel_policy", &["channel_id"]),
+    ("role_policy", &["role_id"]),
+    ("member_policy", &["guild_id", "user_id"]),
+    ("global_policy", &[]),
+];
+
+/// Specified actions which are already exact, having been renamed from the old `model.use` choices
+const RENAMED_MODELS: [&str; 2] = ["model.use:gpt-4o", "model.use:gpt-3.5-turbo"];
+
+/// Every action at the time of this migration. Anything else without a specifier was a prefix of these
+const ACTIONS: [&str; 15] = [
+    "chat",
+    "permissions.set",
+    "permissions.get",
+    "settings.set",
+    "settings.get",
+    "feedback.send",
+    "persona.create",
+    "persona.list",
+    "persona.edit",
+    "persona.use",
+    "persona.delete",
+    "model.use",
+    "tool.use",
+    "image.generate",
+    "usage.view",
+];
+
+/// Policy actions used to match any action they were a prefix of, and are now patterns.
+///
+/// Rewrites saved actions to the pattern which matches the same actions, eg `settings.set:chat`
+/// becomes `settings.set:chat*` and `persona` becomes `persona.**`.
+/// Prefixes ending part way through a segment, eg `persona.us`, have no equivalent and are left as is.
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+        let actions = ACTIONS.map(|action| format!("'{}'", action)).join(", ");
+        let renamed_models = RENAMED_MODELS.map(|action| format!("'{}'", action)).join(", ");
+
+        for (table, keys) in TABLES {
+            // Skip rows whose rewritten action is already saved for the same principle
+            let same_principle = keys
+                .iter()
+                .map(|key| format!(" AND existing.{key} = {table}.{key}"))
+                .collect::<String>();
+            let unless_saved = |rewritten: &str| {
+                format!(
+                    "NOT EXISTS (SELECT 1 FROM {table} existing WHERE existing.action = {rewritten}{same_principle})"
+                )
+            };
+
+            db.execute_unprepared(&format!(
+                "UPDATE {table} SET action = action || '*' \
+                 WHERE action LIKE '%:%' AND action NOT LIKE '%*' AND action NOT IN ({renamed_models}) AND {}",
+                unless_saved("action || '*'"),
+            )).await?;
+            db.execute_unprepared(&format!(
+                "UPDATE {table} SET action = action || '.**' \
+                 WHERE action NOT LIKE '%:%' AND action NOT LIKE '%*%' AND action NOT IN ({actions}) AND {}",
+                unless_saved("action || '.**'"),
+            )).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        // Rewritten actions can't be told apart from patterns saved since
+        Ok(())
+    }
+}
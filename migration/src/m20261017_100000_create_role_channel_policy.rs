@@ -0,0 +1,61 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::Iterable;
+use crate::m20230710_001739_create_permissions::Effect;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(RoleChannelPolicy::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(RoleChannelPolicy::Id)
+                            .integer()
+                            .auto_increment()
+                            .primary_key()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(RoleChannelPolicy::RoleId).big_unsigned().not_null())
+                    .col(ColumnDef::new(RoleChannelPolicy::ChannelId).big_unsigned().not_null())
+                    .col(ColumnDef::new(RoleChannelPolicy::Action).string().not_null())
+                    .col(
+                        ColumnDef::new(RoleChannelPolicy::Effect)
+                            .enumeration(Effect::Table, Effect::iter().skip(1))
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(RoleChannelPolicy::Until).timestamp_with_time_zone())
+                    .index(
+                        Index::create()
+                            .unique()
+                            .name("RoleChannelAction")
+                            .col(RoleChannelPolicy::RoleId)
+                            .col(RoleChannelPolicy::ChannelId)
+                            .col(RoleChannelPolicy::Action),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RoleChannelPolicy::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum RoleChannelPolicy {
+    Table,
+    Id,
+    RoleId,
+    ChannelId,
+    Action,
+    Effect,
+    Until,
+}
@@ -0,0 +1,32 @@
+use poise::serenity_prelude as serenity;
+use crate::{Context, Error};
+use crate::permissions::{Permission, validate_access};
+
+/// Generate an image from a description
+#[poise::command(slash_command)]
+pub async fn imagine(
+    ctx: Context<'_>,
+    #[description = "Description of the image to generate"]
+    #[max_length = 1000]
+    prompt: String,
+) -> Result<(), Error> {
+    validate_access(&ctx, Permission::GenerateImage).await?;
+
+    // Command is only registered when image generation is configured
+    let generator = ctx.data().image_generator.as_ref().unwrap();
+
+    // Generating an image easily takes longer than Discord's 3 second deadline
+    ctx.defer().await?;
+
+    let image = generator
+        .generate(ctx.data(), ctx.author().id, ctx.channel_id(), ctx.guild_id(), &prompt)
+        .await?;
+
+    ctx.send(poise::CreateReply::default()
+        .content(format!("> {}", prompt))
+        .attachment(serenity::CreateAttachment::bytes(image.data, "image.png"))
+        .allowed_mentions(serenity::CreateAllowedMentions::default()))
+        .await?;
+
+    Ok(())
+}
@@ -2,6 +2,8 @@ mod permissions;
 mod persona;
 mod settings;
 mod feedback;
+mod imagine;
+mod usage;
 
 use crate::{Context, Data, Error};
 use crate::settings::config::FaultybotConfig;
@@ -11,13 +13,18 @@ pub fn commands_vec(config: &FaultybotConfig) -> Vec<poise::Command<Data, Error>
         help(),
         permissions::permissions(),
         persona::persona(),
-        settings::settings()
+        settings::settings(),
+        usage::usage(),
     ];
 
     if config.github.is_some() {
         commands.push(feedback::feedback());
     }
 
+    if config.images.is_some() {
+        commands.push(imagine::imagine());
+    }
+
     commands
 }
 
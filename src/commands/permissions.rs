@@ -1,17 +1,21 @@
 use crate::error::UserError;
-use crate::permissions::policy::{Effect, Policy, PolicyContext, PolicyProvider, Principle};
-use crate::permissions::{validate_access, Permission};
+use crate::permissions::policy::{
+    cmp_roles, Effect, Outcome, Policy, PolicyContext, PolicyProvider, Principle,
+};
+use crate::permissions::{validate_access, validate_owner, Permission};
 use crate::{Context, Error};
-use poise::serenity_prelude::{ChannelId, RoleId, UserId};
+use poise::serenity_prelude::{ChannelId, Mentionable, RoleId, UserId};
 use crate::util::say_ephemeral;
 
 /// Manage permissions for a given principle
-#[poise::command(slash_command, subcommands("get", "set"))]
+#[poise::command(slash_command, subcommands("get", "set", "explain"))]
 pub async fn permissions(_ctx: Context<'_>) -> Result<(), Error> {
     Ok(())
 }
 
 /// Set permissions for a given principle
+///
+/// Set both `channel` and `role` to only apply to members of the role while in that channel
 #[allow(clippy::too_many_arguments)]
 #[poise::command(slash_command)]
 async fn set(
@@ -19,8 +23,9 @@ async fn set(
     #[description = "Channel permission will be scoped to"] channel: Option<ChannelId>,
     #[description = "User permission will be scoped to"] user: Option<UserId>,
     #[description = "Role permission will be scoped to"] role: Option<RoleId>,
+    #[description = "Apply to every server and DM. Bot owner only"] global: Option<bool>,
     #[description = "Permission to manage permissions for"] permission: PermissionChoice,
-    #[description = "Extra specifier to limit permission (ie the name of a setting to grant manage for)"]
+    #[description = "Extra specifier to limit permission (ie the name of a setting to grant manage for). `*` matches anything"]
     specifier: Option<String>,
     #[description = "Effect to apply or `Unset` to revert to default permissions"]
     effect: EffectChoice,
@@ -30,13 +35,16 @@ async fn set(
     #[description = "UTC Timestamp until the granted permissions expire (eg '2018-01-01 12:53:00'). Exclusive with `for`"]
     until: Option<humantime::Timestamp>,
 ) -> Result<(), Error> {
-    let action = permission.into_permission(specifier).to_string();
+    let action = permission.into_action(specifier);
     validate_access(&ctx, Permission::SetPermission(Some(action.clone()))).await?;
 
     let perm_manager = &ctx.data().permissions_manager;
     let policy_manager = perm_manager.as_ref();
 
-    let principle = get_principle(&ctx, channel, user, role)?;
+    let principle = get_principle(&ctx, channel, user, role, global.unwrap_or(false))?;
+    if principle == Principle::Global {
+        validate_owner(&ctx)?;
+    }
 
     let effect = if let Some(effect) = effect.into_effect() {
         effect
@@ -90,7 +98,11 @@ async fn get(
     #[description = "Extra specifier to limit permission (ie the name of a setting to grant manage for)"]
     specifier: Option<String>,
 ) -> Result<(), Error> {
-    let action = permission.into_permission(specifier).to_string();
+    if matches!(permission, PermissionChoice::Any) {
+        let msg = "Please choose a specific permission to get";
+        return Err(UserError::invalid_input(msg).into());
+    }
+    let action = permission.into_action(specifier);
     validate_access(&ctx, Permission::GetPermission(Some(action.clone()))).await?;
     let policy_ctx = PolicyContext {
         guild_id: ctx.guild_id(),
@@ -99,14 +111,93 @@ async fn get(
         roles: role.map(|v| vec![v]).unwrap_or_default(),
     };
 
-    let policy = ctx
+    let candidates = ctx
         .data()
         .permissions_manager
         .as_ref()
-        .effective_policy(ctx.serenity_context(), policy_ctx, action)
+        .candidate_policies(policy_ctx, action)
         .await?;
+    let (policy, explained) = Policy::explain(candidates, |lhs, rhs| {
+        cmp_roles(&ctx.serenity_context().cache, ctx.guild_id(), lhs, rhs)
+    });
+
+    let mut msg = format!("Effective policy: {:?}", policy);
+    for (candidate, outcome) in explained {
+        if let Outcome::Lost(precedence) = outcome {
+            if precedence.is_conflict() && candidate.effect != policy.effect {
+                msg.push_str(&format!(
+                    "\nConflicts with {:?}, resolved by {:?}",
+                    candidate, precedence
+                ));
+            }
+        }
+    }
+    say_ephemeral(ctx, msg, true).await?;
+    Ok(())
+}
+
+/// Explain which policies decide whether a user can do something in a channel
+#[poise::command(slash_command)]
+async fn explain(
+    ctx: Context<'_>,
+    #[description = "User to explain permissions for. Defaults to you"] user: Option<UserId>,
+    #[description = "Channel to explain permissions in. Defaults to this one"] channel: Option<ChannelId>,
+    #[description = "Permission to explain"] permission: PermissionChoice,
+    #[description = "Extra specifier to limit permission (ie the name of a setting to grant manage for)"]
+    specifier: Option<String>,
+) -> Result<(), Error> {
+    if matches!(permission, PermissionChoice::Any) {
+        let msg = "Please choose a specific permission to explain";
+        return Err(UserError::invalid_input(msg).into());
+    }
+    let action = permission.into_action(specifier);
+    validate_access(&ctx, Permission::GetPermission(Some(action.clone()))).await?;
+
+    let user_id = user.unwrap_or(ctx.author().id);
+    let guild_id = ctx.guild_id();
+    let roles = match guild_id {
+        Some(guild_id) => guild_id.member(ctx.serenity_context(), user_id).await?.roles.into_vec(),
+        None => vec![],
+    };
+    let policy_ctx = PolicyContext {
+        guild_id,
+        channel_id: Some(channel.unwrap_or(ctx.channel_id())),
+        roles,
+        user_id: Some(user_id),
+    };
+
+    let candidates = ctx
+        .data()
+        .permissions_manager
+        .as_ref()
+        .candidate_policies(policy_ctx, action.clone())
+        .await?;
+    let (policy, explained) = Policy::explain(candidates, |lhs, rhs| {
+        cmp_roles(&ctx.serenity_context().cache, guild_id, lhs, rhs)
+    });
+
+    let mut msg = format!(
+        "{:?} for {} to do `{}`\n",
+        policy.effect,
+        user_id.mention(),
+        action
+    );
+    for (candidate, outcome) in explained {
+        let action = if candidate.action.is_empty() {
+            "default".to_string()
+        } else {
+            format!("`{}`", candidate.action)
+        };
+        let until = candidate
+            .until
+            .map(|until| format!(" until <t:{}:f>", until.timestamp()))
+            .unwrap_or_default();
+        msg.push_str(&format!(
+            "- {} for {}: {:?}{}, {}\n",
+            action, candidate.principle, candidate.effect, until, outcome
+        ));
+    }
 
-    let msg = format!("Effective policy: {:?}", policy);
     say_ephemeral(ctx, msg, true).await?;
     Ok(())
 }
@@ -116,7 +207,15 @@ fn get_principle(
     channel: Option<ChannelId>,
     user: Option<UserId>,
     role: Option<RoleId>,
+    global: bool,
 ) -> Result<Principle, UserError> {
+    if global {
+        return match (channel, user, role) {
+            (None, None, None) => Ok(Principle::Global),
+            _ => Err(UserError::invalid_input("Please specify only one scope")),
+        };
+    }
+
     match (channel, user, role) {
         (Some(channel_id), None, None) => Ok(Principle::Channel(channel_id)),
         (None, Some(user_id), None) => {
@@ -128,6 +227,7 @@ fn get_principle(
             }
         }
         (None, None, Some(role_id)) => Ok(Principle::Role(role_id)),
+        (Some(channel_id), None, Some(role_id)) => Ok(Principle::RoleInChannel(role_id, channel_id)),
         // Guild-wide if in a guild or for the "channel" if in a DM
         (None, None, None) => {
             if let Some(guild_id) = ctx.guild_id() {
@@ -164,6 +264,8 @@ impl EffectChoice {
 /// Names are slightly different here to be more end-user friendly
 #[derive(poise::ChoiceParameter, derive_more::Display)]
 pub enum PermissionChoice {
+    /// Every permission, eg to grant all permissions for a persona with specifier `Sassy`
+    Any,
     Chat,
     ManagePermissions,
     GetPermissions,
@@ -175,11 +277,22 @@ pub enum PermissionChoice {
     EditPersona,
     UsePersona,
     DeletePersona,
+    UseModel,
+    UseTool,
+    GenerateImage,
+    ViewUsage,
 }
 
 impl PermissionChoice {
-    pub fn into_permission(self, specifier: Option<String>) -> Permission {
-        match self {
+    /// Action pattern for this permission. See [crate::permissions::pattern]
+    pub fn into_action(self, specifier: Option<String>) -> String {
+        let permission = match self {
+            PermissionChoice::Any => {
+                return match specifier {
+                    Some(specifier) => format!("**:{}", specifier),
+                    None => "**".to_string(),
+                }
+            }
             PermissionChoice::Chat => Permission::Chat(specifier),
             PermissionChoice::ManagePermissions => Permission::SetPermission(specifier),
             PermissionChoice::GetPermissions => Permission::GetPermission(specifier),
@@ -191,6 +304,12 @@ impl PermissionChoice {
             PermissionChoice::EditPersona => Permission::EditPersona(specifier),
             PermissionChoice::UsePersona => Permission::UsePersona(specifier),
             PermissionChoice::DeletePersona => Permission::DeletePersona(specifier),
-        }
+            PermissionChoice::UseModel => Permission::UseModel(specifier),
+            PermissionChoice::UseTool => Permission::UseTool(specifier),
+            PermissionChoice::GenerateImage => Permission::GenerateImage,
+            PermissionChoice::ViewUsage => Permission::ViewUsage(specifier),
+        };
+
+        permission.to_string()
     }
 }
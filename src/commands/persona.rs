@@ -1,10 +1,10 @@
-use std::fmt::{Display, Formatter};
 use crate::{Context, Error};
 
 use poise::Modal as _;
 use std::fmt::Write as _;
+use poise::serenity_prelude as serenity;
 use poise::serenity_prelude::{ChannelId, Mentionable};
-use entities::sea_orm_active_enums::LlmModel;
+use crate::gpt::ModelRegistry;
 use crate::permissions::{Permission, validate_access, validate_owner};
 use crate::util::say_ephemeral;
 
@@ -29,14 +29,17 @@ pub async fn persona(_ctx: Context<'_>) -> Result<(), Error> { Ok(()) }
 #[poise::command(slash_command, guild_only)]
 async fn create(
     ctx: Context<'_>,
-    #[rename = "model"]
-    #[description = "Which LLM Model to use for this persona (default GPT 3.5)"]
-    model_choice: Option<ModelChoice>
+    #[description = "Which LLM Model to use for this persona (default gpt-3.5-turbo)"]
+    #[autocomplete = "autocomplete_model"]
+    model: Option<String>,
+    #[description = "Comma separated list of tools this persona can use"]
+    tools: Option<String>,
 ) -> Result<(), Error> {
     let guild_id = ctx.guild_id().unwrap(); // guild_only command
 
     validate_access(&ctx, Permission::CreatePersona).await?;
-    validate_model_access(&ctx, &model_choice).await?;
+    validate_model_access(&ctx, &model).await?;
+    let tools = tools.map(|t| parse_tools(&ctx, &t)).transpose()?;
 
     let ctx = match ctx {
         Context::Application(ctx) => ctx,
@@ -44,7 +47,7 @@ async fn create(
     };
 
     let persona_manager = &ctx.data().persona_manager;
-    let model_choice = model_choice.unwrap_or_default();
+    let model = model.unwrap_or_else(|| ModelRegistry::DEFAULT_MODEL.to_string());
 
     let persona_data = PersonaModal::execute(ctx).await?;
 
@@ -56,7 +59,8 @@ async fn create(
         persona_data.description,
         guild_id,
         persona_data.prompt,
-        model_choice.into(),
+        model,
+        tools.unwrap_or_default(),
     ).await?;
 
     let msg = format!("Successfully created new persona: {}", persona_data.name);
@@ -71,14 +75,17 @@ async fn edit(
     ctx: Context<'_>,
     #[description = "Name of the persona to edit"]
     name: String,
-    #[rename = "model"]
-    #[description = "Change the LLM Model to use for this persona (default GPT 3.5)"]
-    model_choice: Option<ModelChoice>
+    #[description = "Change the LLM Model to use for this persona"]
+    #[autocomplete = "autocomplete_model"]
+    model: Option<String>,
+    #[description = "Change the tools this persona can use (comma separated, or `none`)"]
+    tools: Option<String>,
 ) -> Result<(), Error> {
     let guild_id = ctx.guild_id().unwrap(); // guild_only command
 
     validate_access(&ctx, Permission::EditPersona(Some(name.clone()))).await?;
-    validate_model_access(&ctx, &model_choice).await?;
+    validate_model_access(&ctx, &model).await?;
+    let tools = tools.map(|t| parse_tools(&ctx, &t)).transpose()?;
 
     let persona_manager = &ctx.data().persona_manager;
 
@@ -107,8 +114,11 @@ async fn edit(
     let persona_data = persona_data.unwrap();
 
     let mut new_persona = existing_persona.clone();
-    if let Some(model) = model_choice {
-        new_persona.model = model.into();
+    if let Some(model) = model {
+        new_persona.model = model;
+    }
+    if let Some(tools) = tools {
+        new_persona.tools = tools;
     }
     new_persona.name.clone_from(&persona_data.name);
     new_persona.prompt = persona_data.prompt;
@@ -187,41 +197,39 @@ async fn get(ctx: Context<'_>, #[description = "Name of the persona to fetch det
     Ok(())
 }
 
-#[derive(PartialEq, poise::ChoiceParameter)]
-pub enum ModelChoice {
-    Gpt35,
-    Gpt4
-}
-
-impl Display for ModelChoice {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        let name = match self {
-            ModelChoice::Gpt35 => "GPT 3.5",
-            ModelChoice::Gpt4 => "GPT 4"
-        };
-        write!(f, "{}", name)
-    }
+async fn autocomplete_model<'a>(
+    ctx: Context<'_>,
+    partial: &'a str,
+) -> serenity::CreateAutocompleteResponse<'a> {
+    let choices = ctx.data()
+        .model_registry
+        .search(partial)
+        .take(25) // Discord limit
+        .map(|m| serenity::AutocompleteChoice::from(m.name.clone()))
+        .collect::<Vec<_>>();
+
+    serenity::CreateAutocompleteResponse::new().set_choices(choices)
 }
 
-impl Default for ModelChoice {
-    fn default() -> Self {
-        Self::Gpt35
+/// Parse a comma separated list of tool names, ensuring each tool exists
+fn parse_tools(ctx: &Context<'_>, tools: &str) -> Result<Vec<String>, Error> {
+    if tools.trim().eq_ignore_ascii_case("none") {
+        return Ok(vec![]);
     }
-}
 
-impl From<ModelChoice> for LlmModel {
-    fn from(choice: ModelChoice) -> Self {
-        match choice {
-            ModelChoice::Gpt35 => LlmModel::Gpt35Turbo,
-            ModelChoice::Gpt4 => LlmModel::Gpt4,
-        }
-    }
+    tools.split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| Ok(ctx.data().tools.get(name)?.name().to_string()))
+        .collect()
 }
 
-async fn validate_model_access(ctx: &Context<'_>, model_choice: &Option<ModelChoice>) -> Result<(), Error> {
-    if let Some(choice) = &model_choice {
-        if choice != &ModelChoice::default() {
-            validate_access(ctx, Permission::UseModel(Some(choice.to_string()))).await?;
+async fn validate_model_access(ctx: &Context<'_>, model: &Option<String>) -> Result<(), Error> {
+    if let Some(model) = &model {
+        // Ensure the model actually exists since autocomplete doesn't restrict input
+        let model = ctx.data().model_registry.get(model)?;
+        if model.name != ModelRegistry::DEFAULT_MODEL {
+            validate_access(ctx, Permission::UseModel(Some(model.name.clone()))).await?;
         }
     }
     Ok(())
@@ -2,13 +2,29 @@ use crate::error::UserError;
 use crate::permissions::{validate_access, Permission};
 use crate::settings::{SettingsContext, SettingsScopeKind, SettingsValue};
 use crate::{Context, Error};
-use poise::serenity_prelude::{ChannelId, UserId};
+use poise::serenity_prelude::{ChannelId, RoleId, UserId};
 use crate::util::say_ephemeral;
 
+/// Settings read for a member's roles. Anything else set for a role would be ignored
+const ROLE_KEYS: [&str; 1] = ["chat.cooldown"];
+
 /// Manage settings for a specific scope
 ///
 /// Available settings:
-/// - `chat.cooldown`: Cooldown between chat responses from FaultyBot
+/// - `chat.cooldown`: Seconds between chat responses from FaultyBot, or a token bucket allowing bursts of messages, eg `{"burst": 3, "refill_seconds": 20}`. Limits set in each scope apply together
+/// - `chat.cooldown` for a role: Tier applied to each member with the role, eg a shorter cooldown for supporters. The member's highest role with a tier applies, unless they have their own. `0` exempts them from the server and channel cooldowns
+/// - `chat.backend`: Name of the configured LLM backend to chat with, overriding the persona model's `provider`
+/// - `chat.queue_size`: Number of messages per channel which wait (marked with ⏳) for the cooldown to expire instead of being rejected, default `0`. Deleting a waiting message cancels it
+/// - `chat.stream_mode`: `"chunks"` (default) to send the response a message at a time, or `"edit"` to edit a single message as the response arrives
+/// - `chat.overflow`: How to post responses longer than `max_length`: `"messages"` (default), `"file"` or `"thread"`, eg `{"mode": "thread", "max_length": 3900}`
+/// - `chat.context_tokens`: Maximum number of tokens of conversation history sent with each request
+/// - `chat.vision`: Send images to vision-capable models, eg `{"enabled": true, "max_image_bytes": 5242880, "max_images": 4}`
+/// - `chat.attachments`: Limits for text files included in the conversation, eg `{"max_bytes": 32768, "max_tokens": 2000}`
+/// - `imagine.cooldown`: Seconds between generated images, shared by the whole server. Also accepts a token bucket like `chat.cooldown`
+/// - `quota.user_daily_tokens`, `quota.user_monthly_tokens`: Tokens each user may use per UTC day or month. The smallest value set in any scope applies
+/// - `quota.user_daily_cost`, `quota.user_monthly_cost`: Same as above, in USD based on model pricing
+/// - `quota.guild_daily_tokens`, `quota.guild_monthly_tokens`, `quota.guild_daily_cost`, `quota.guild_monthly_cost`: Limits on the whole server's usage
+/// - `conversation.retention_days`: Days conversations with FaultyBot are kept for replay and analytics, `0` to stop recording them and delete those kept (default 30). Set per server, or per channel in DMs
 #[poise::command(slash_command, subcommands("get", "set", "unset"))]
 pub async fn settings(_ctx: Context<'_>) -> Result<(), Error> {
     Ok(())
@@ -16,7 +32,7 @@ pub async fn settings(_ctx: Context<'_>) -> Result<(), Error> {
 
 /// Set settings for a specific scope
 ///
-/// If `channel` and `user` are both unset, will manage guild-wide setting
+/// If `channel`, `user` and `role` are all unset, will manage guild-wide setting
 ///
 /// Bot-wide per-user settings are currently not supported.
 /// To change settings in your DMs, use per-channel settings
@@ -25,6 +41,7 @@ async fn set(
     ctx: Context<'_>,
     #[description = "Channel setting will be scoped to"] channel: Option<ChannelId>,
     #[description = "User setting will be scoped to"] user: Option<UserId>,
+    #[description = "Role setting will be scoped to"] role: Option<RoleId>,
     key: String,
     #[description = "JSON encoded value. (text must be wrapped in quotes)"]
     value: serde_json::Value,
@@ -32,18 +49,25 @@ async fn set(
     validate_access(&ctx, Permission::SetSetting(Some(key.clone()))).await?;
 
     let settings_manager = &ctx.data().settings_manager;
-    let updated_scope = match (channel, user) {
-        (Some(_), Some(_)) => {
-            let msg = "Per-user-per-channel settings not supported. Please specify only one scope";
-            return Err(UserError::invalid_input(msg).into());
-        }
-        (Some(channel_id), None) => {
+    let updated_scope = match (channel, user, role) {
+        (Some(channel_id), None, None) => {
             settings_manager
                 .set_channel(channel_id, key.clone(), Some(value.clone()))
                 .await?;
             SettingsScopeKind::Channel(channel_id)
         }
-        (None, Some(user_id)) => {
+        (None, None, Some(role_id)) => {
+            if !ROLE_KEYS.contains(&key.as_str()) {
+                let keys = ROLE_KEYS.map(|key| format!("`{}`", key)).join(", ");
+                let msg = format!("`{}` can't be set for a role, only {}", key, keys);
+                return Err(UserError::invalid_input(msg).into());
+            }
+            settings_manager
+                .set_role(role_id, key.clone(), Some(value.clone()))
+                .await?;
+            SettingsScopeKind::Role(role_id)
+        }
+        (None, Some(user_id), None) => {
             let guild_id = ctx.guild_id().ok_or_else(|| {
                 let msg = "Per-user settings not support outside a server. Please user per-channel settings for DMs";
                 UserError::invalid_input(msg)
@@ -53,7 +77,7 @@ async fn set(
                 .await?;
             SettingsScopeKind::Member(guild_id, user_id)
         }
-        (None, None) => {
+        (None, None, None) => {
             if let Some(guild_id) = ctx.guild_id() {
                 settings_manager
                     .set_guild(guild_id, key.clone(), Some(value.clone()))
@@ -67,6 +91,10 @@ async fn set(
                 SettingsScopeKind::Channel(channel_id)
             }
         }
+        (_, _, _) => {
+            let msg = "Please specify only one scope (channel, user, or role)";
+            return Err(UserError::invalid_input(msg).into());
+        }
     };
 
     let msg = format!(
@@ -80,7 +108,7 @@ async fn set(
 
 /// Unset settings for a specific scope
 ///
-/// If `channel` and `user` are both unset, will manage guild-wide setting
+/// If `channel`, `user` and `role` are all unset, will manage guild-wide setting
 ///
 /// Bot-wide per-user settings are currently not supported.
 /// To change settings in your DMs, use per-channel settings
@@ -89,23 +117,26 @@ async fn unset(
     ctx: Context<'_>,
     #[description = "Channel setting will be scoped to"] channel: Option<ChannelId>,
     #[description = "User setting will be scoped to"] user: Option<UserId>,
+    #[description = "Role setting will be scoped to"] role: Option<RoleId>,
     key: String,
 ) -> Result<(), Error> {
     validate_access(&ctx, Permission::SetSetting(Some(key.clone()))).await?;
     let settings_manager = &ctx.data().settings_manager;
 
-    let updated_scope = match (channel, user) {
-        (Some(_), Some(_)) => {
-            let msg = "Per-user-per-channel settings not supported. Please specify only one scope";
-            return Err(UserError::invalid_input(msg).into());
-        }
-        (Some(channel_id), None) => {
+    let updated_scope = match (channel, user, role) {
+        (Some(channel_id), None, None) => {
             settings_manager
                 .set_channel::<serde_json::Value>(channel_id, key.clone(), None)
                 .await?;
             SettingsScopeKind::Channel(channel_id)
         }
-        (None, Some(user_id)) => {
+        (None, None, Some(role_id)) => {
+            settings_manager
+                .set_role::<serde_json::Value>(role_id, key.clone(), None)
+                .await?;
+            SettingsScopeKind::Role(role_id)
+        }
+        (None, Some(user_id), None) => {
             let guild_id = ctx.guild_id().ok_or_else(|| {
                 let msg = "Per-user settings not support outside a server. Please user per-channel settings for DMs";
                 UserError::invalid_input(msg)
@@ -115,7 +146,7 @@ async fn unset(
                 .await?;
             SettingsScopeKind::Member(guild_id, user_id)
         }
-        (None, None) => {
+        (None, None, None) => {
             if let Some(guild_id) = ctx.guild_id() {
                 settings_manager
                     .set_guild::<serde_json::Value>(guild_id, key.clone(), None)
@@ -129,6 +160,10 @@ async fn unset(
                 SettingsScopeKind::Channel(channel_id)
             }
         }
+        (_, _, _) => {
+            let msg = "Please specify only one scope (channel, user, or role)";
+            return Err(UserError::invalid_input(msg).into());
+        }
     };
 
     let msg = format!("Successfully unset `{}` for {}", key, updated_scope);
@@ -148,18 +183,23 @@ async fn get(
     ctx: Context<'_>,
     channel: Option<ChannelId>,
     user: Option<UserId>,
+    role: Option<RoleId>,
     guild: Option<bool>,
     key: String,
 ) -> Result<(), Error> {
     validate_access(&ctx, Permission::GetSetting(Some(key.clone()))).await?;
     let settings_manager = &ctx.data().settings_manager;
     let key = key.as_str();
-    let setting: SettingsValue<serde_json::Value> = match (channel, user, guild.unwrap_or(false)) {
-        (Some(channel_id), None, false) => {
+    let setting: SettingsValue<serde_json::Value> = match (channel, user, role, guild.unwrap_or(false)) {
+        (Some(channel_id), None, None, false) => {
             let value = settings_manager.get_channel(channel_id, key).await?;
             SettingsValue::new(value, SettingsScopeKind::Channel(channel_id))
         }
-        (None, Some(user_id), false) => {
+        (None, None, Some(role_id), false) => {
+            let value = settings_manager.get_role(role_id, key).await?;
+            SettingsValue::new(value, SettingsScopeKind::Role(role_id))
+        }
+        (None, Some(user_id), None, false) => {
             let guild_id = ctx.guild_id().ok_or_else(|| {
                 let msg = "Per-user settings not support outside a server. Please user per-channel settings for DMs";
                 UserError::invalid_input(msg)
@@ -167,7 +207,7 @@ async fn get(
             let value = settings_manager.get_member(guild_id, user_id, key).await?;
             SettingsValue::new(value, SettingsScopeKind::Member(guild_id, user_id))
         }
-        (None, None, true) => {
+        (None, None, None, true) => {
             let guild_id = ctx.guild_id().ok_or_else(|| {
                 let msg = "Cannot set guild-wide settings outside a guild";
                 UserError::invalid_input(msg)
@@ -175,7 +215,7 @@ async fn get(
             let value = settings_manager.get_guild(guild_id, key).await?;
             SettingsValue::new(value, SettingsScopeKind::Guild(guild_id))
         }
-        (None, None, false) => {
+        (None, None, None, false) => {
             let ctx = SettingsContext {
                 guild_id: ctx.guild_id(),
                 channel_id: Some(ctx.channel_id()),
@@ -183,8 +223,8 @@ async fn get(
             };
             settings_manager.get_value(ctx, key).await?
         }
-        (_, _, _) => {
-            let msg = "Please specify only one scope (channel, user, or guild)";
+        (_, _, _, _) => {
+            let msg = "Please specify only one scope (channel, user, role, or guild)";
             return Err(UserError::invalid_input(msg).into());
         }
     };
@@ -0,0 +1,265 @@
+use std::borrow::Cow;
+use std::fmt::Write as _;
+use chrono::{Days, NaiveDate, Utc};
+use poise::serenity_prelude as serenity;
+use serenity::Mentionable;
+use crate::{Context, Error};
+use crate::gpt::UsageTotals;
+use crate::permissions::{Permission, validate_access};
+
+/// Default number of days covered by usage reports
+const DEFAULT_DAYS: u32 = 30;
+/// Keep well clear of Discord's 4096 character limit on embed descriptions
+const MAX_DESCRIPTION_LENGTH: usize = 3500;
+
+/// View token usage and its cost
+#[poise::command(slash_command, subcommands("me", "guild", "top"))]
+pub async fn usage(_ctx: Context<'_>) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Show how many tokens you've used here
+#[poise::command(slash_command)]
+async fn me(
+    ctx: Context<'_>,
+    #[description = "Number of days to include, counting today (default 30)"]
+    #[min = 1]
+    #[max = 365]
+    days: Option<u32>,
+    #[description = "Attach the usage as a CSV file"]
+    csv: Option<bool>,
+) -> Result<(), Error> {
+    validate_access(&ctx, Permission::ViewUsage(Some("self".to_string()))).await?;
+
+    let since = window_start(days);
+    let totals = ctx.data()
+        .usage_manager
+        .totals(ctx.guild_id(), Some(ctx.author().id), since)
+        .await?;
+
+    let embed = serenity::CreateEmbed::default()
+        .title("Your usage")
+        .fields(totals_fields(&totals))
+        .description(format!("Since {} (UTC)", since));
+
+    let mut reply = poise::CreateReply::default().embed(embed).ephemeral(true);
+    if csv.unwrap_or(false) {
+        let mut file = format!("since,{}\n", TOTALS_HEADER);
+        writeln!(&mut file, "{},{}", since, totals_row(&totals))?;
+        reply = reply.attachment(serenity::CreateAttachment::bytes(file.into_bytes(), "usage.csv"));
+    }
+    ctx.send(reply).await?;
+
+    Ok(())
+}
+
+/// Show this server's usage split by persona and model
+#[poise::command(slash_command, guild_only)]
+async fn guild(
+    ctx: Context<'_>,
+    #[description = "Number of days to include, counting today (default 30)"]
+    #[min = 1]
+    #[max = 365]
+    days: Option<u32>,
+    #[description = "Attach the usage as a CSV file"]
+    csv: Option<bool>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap(); // guild_only command
+
+    validate_access(&ctx, Permission::ViewUsage(Some("guild".to_string()))).await?;
+
+    let since = window_start(days);
+    let usage_manager = &ctx.data().usage_manager;
+    let totals = usage_manager.totals(Some(guild_id), None, since).await?;
+    let breakdown = usage_manager.by_persona(guild_id, since).await?;
+
+    let lines = breakdown.iter().map(|usage| {
+        format!(
+            "**{}** `{}`: {}",
+            persona_name(&usage.persona),
+            usage.model,
+            summarize(&usage.totals()),
+        )
+    });
+    let embed = serenity::CreateEmbed::default()
+        .title("Server usage")
+        .fields(totals_fields(&totals))
+        .description(describe(since, lines));
+
+    let mut reply = poise::CreateReply::default().embed(embed).ephemeral(true);
+    if csv.unwrap_or(false) {
+        let mut file = format!("persona,model,{}\n", TOTALS_HEADER);
+        for usage in &breakdown {
+            writeln!(
+                &mut file,
+                "{},{},{}",
+                csv_field(persona_name(&usage.persona)),
+                csv_field(&usage.model),
+                totals_row(&usage.totals()),
+            )?;
+        }
+        reply = reply.attachment(serenity::CreateAttachment::bytes(file.into_bytes(), "usage.csv"));
+    }
+    ctx.send(reply).await?;
+
+    Ok(())
+}
+
+/// Show the members of this server who have used the most
+#[poise::command(slash_command, guild_only)]
+async fn top(
+    ctx: Context<'_>,
+    #[description = "Number of days to include, counting today (default 30)"]
+    #[min = 1]
+    #[max = 365]
+    days: Option<u32>,
+    #[description = "Number of users to show (default 10)"]
+    #[min = 1]
+    #[max = 50]
+    limit: Option<u8>,
+    #[description = "Attach the usage as a CSV file"]
+    csv: Option<bool>,
+) -> Result<(), Error> {
+    let guild_id = ctx.guild_id().unwrap(); // guild_only command
+
+    validate_access(&ctx, Permission::ViewUsage(Some("guild".to_string()))).await?;
+
+    let since = window_start(days);
+    let users = ctx.data()
+        .usage_manager
+        .top_users(guild_id, since, limit.unwrap_or(10) as u64)
+        .await?;
+
+    let lines = users.iter().enumerate().map(|(i, usage)| {
+        format!("{}. {}: {}", i + 1, usage.user_id().mention(), summarize(&usage.totals()))
+    });
+    let embed = serenity::CreateEmbed::default()
+        .title("Top users")
+        .description(describe(since, lines));
+
+    let mut reply = poise::CreateReply::default().embed(embed).ephemeral(true);
+    if csv.unwrap_or(false) {
+        let mut file = format!("user_id,{}\n", TOTALS_HEADER);
+        for usage in &users {
+            writeln!(&mut file, "{},{}", usage.user_id(), totals_row(&usage.totals()))?;
+        }
+        reply = reply.attachment(serenity::CreateAttachment::bytes(file.into_bytes(), "usage.csv"));
+    }
+    ctx.send(reply).await?;
+
+    Ok(())
+}
+
+const TOTALS_HEADER: &str = "requests,prompt_tokens,completion_tokens,cost_usd";
+
+fn totals_row(totals: &UsageTotals) -> String {
+    format!(
+        "{},{},{},{:.6}",
+        totals.requests, totals.prompt_tokens, totals.completion_tokens, totals.cost
+    )
+}
+
+/// First day included in a report covering the last `days` days
+fn window_start(days: Option<u32>) -> NaiveDate {
+    let days = days.unwrap_or(DEFAULT_DAYS).max(1);
+    let today = Utc::now().date_naive();
+    today.checked_sub_days(Days::new(days as u64 - 1)).unwrap_or(today)
+}
+
+/// Embed fields showing `totals`
+fn totals_fields(totals: &UsageTotals) -> [(&'static str, String, bool); 3] {
+    let tokens = format!(
+        "{} ({} prompt, {} completion)",
+        format_tokens(totals.tokens()),
+        format_tokens(totals.prompt_tokens),
+        format_tokens(totals.completion_tokens),
+    );
+
+    [
+        ("Requests", totals.requests.to_string(), true),
+        ("Tokens", tokens, true),
+        ("Cost", format_cost(totals.cost), true),
+    ]
+}
+
+/// Embed description listing `lines`, dropping any that don't fit
+fn describe(since: NaiveDate, lines: impl ExactSizeIterator<Item = String>) -> String {
+    let total = lines.len();
+    let mut description = format!("Since {} (UTC)\n", since);
+    if total == 0 {
+        description.push_str("\nNo usage recorded");
+    }
+
+    for (shown, line) in lines.enumerate() {
+        if description.len() + line.len() > MAX_DESCRIPTION_LENGTH {
+            let _ = write!(&mut description, "\n…and {} more", total - shown);
+            break;
+        }
+        description.push('\n');
+        description.push_str(&line);
+    }
+
+    description
+}
+
+fn summarize(totals: &UsageTotals) -> String {
+    format!(
+        "{} tokens, {} ({} requests)",
+        format_tokens(totals.tokens()),
+        format_cost(totals.cost),
+        totals.requests,
+    )
+}
+
+fn persona_name(name: &Option<String>) -> &str {
+    name.as_deref().unwrap_or("deleted persona")
+}
+
+fn format_cost(cost: f64) -> String {
+    format!("${:.4}", cost)
+}
+
+/// Format with thousands separators, eg `12,345`
+fn format_tokens(tokens: i64) -> String {
+    let digits = tokens.unsigned_abs().to_string();
+    let mut formatted = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            formatted.push(',');
+        }
+        formatted.push(digit);
+    }
+
+    if tokens < 0 {
+        formatted.insert(0, '-');
+    }
+    formatted
+}
+
+/// Quote a CSV field if it contains anything that would break the row
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_tokens_groups_thousands() {
+        assert_eq!(format_tokens(0), "0");
+        assert_eq!(format_tokens(999), "999");
+        assert_eq!(format_tokens(1000), "1,000");
+        assert_eq!(format_tokens(1234567), "1,234,567");
+    }
+
+    #[test]
+    fn csv_field_quotes_when_needed() {
+        assert_eq!(csv_field("gpt-4o"), "gpt-4o");
+        assert_eq!(csv_field("Bob, the \"builder\""), "\"Bob, the \"\"builder\"\"\"");
+    }
+}
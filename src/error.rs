@@ -8,8 +8,12 @@ pub enum UserError {
     AccessDenied { reason: String },
     #[error("You're too fast. Please wait {:.1} seconds before retrying", .remaining.as_secs_f32())]
     CooldownHit { remaining: Duration },
+    #[error("Too many messages are already waiting for a reply here. Please try again later")]
+    QueueFull,
     #[error("Not Found: {message}")]
     NotFound { message: String },
+    #[error("You've reached the {quota}. It resets <t:{}:R>", .resets_at.timestamp())]
+    QuotaExceeded { quota: String, resets_at: chrono::DateTime<chrono::Utc> },
 }
 
 impl UserError {
@@ -29,22 +33,45 @@ impl UserError {
         Self::CooldownHit { remaining }
     }
 
+    pub fn queue_full() -> Self {
+        Self::QueueFull
+    }
+
     pub fn not_found<T: ToString>(message: T) -> Self {
         let message = message.to_string();
         Self::NotFound { message }
     }
+
+    pub fn quota_exceeded<T: Into<String>>(quota: T, resets_at: chrono::DateTime<chrono::Utc>) -> Self {
+        Self::QuotaExceeded {
+            quota: quota.into(),
+            resets_at,
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum InternalError {
     #[error("Unknown persona: {0}")]
-    UnknownPersona(String)
+    UnknownPersona(String),
+    #[error("No chat backend named `{0}` is configured, check `backends` and the model's `provider`")]
+    UnknownBackend(String),
+    #[error("Chat backend error: {0}")]
+    Backend(String),
 }
 
 impl InternalError {
     pub fn unknown_persona<T: Into<String>>(msg: T) -> Self {
         Self::UnknownPersona(msg.into())
     }
+
+    pub fn unknown_backend<T: Into<String>>(name: T) -> Self {
+        Self::UnknownBackend(name.into())
+    }
+
+    pub fn backend<T: Into<String>>(msg: T) -> Self {
+        Self::Backend(msg.into())
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -59,6 +86,7 @@ pub enum FaultyBotError {
     Json(#[from] serde_json::Error),
     Config(#[from] config::ConfigError),
     Octocrab(#[from] octocrab::Error),
+    Reqwest(#[from] reqwest::Error),
     Boxed(Box<dyn std::error::Error + Send + Sync>),
 }
 
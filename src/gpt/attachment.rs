@@ -0,0 +1,253 @@
+use std::sync::Arc;
+use std::time::Duration;
+use moka::future::Cache;
+use poise::serenity_prelude::{Attachment, AttachmentId};
+use serde::Deserialize;
+use tracing::warn;
+use crate::gpt::history::{count_tokens, truncate_tokens};
+
+/// Attachments larger than this are never downloaded, regardless of settings
+const MAX_DOWNLOAD_BYTES: u32 = 1024 * 1024;
+/// Upper bound on the total size of downloaded attachments kept around
+const MAX_CACHED_BYTES: u64 = 64 * 1024 * 1024;
+/// Cached attachments are dropped once they haven't been part of a conversation for this long
+const CACHE_IDLE: Duration = Duration::from_secs(60 * 60);
+/// Upper bound on the tokens [format_text] adds around the contents and file name
+const FORMAT_TOKENS: usize = 32;
+
+/// Extensions treated as text even when Discord doesn't report a `text/*` content type
+const TEXT_EXTENSIONS: &[&str] = &[
+    "c", "cfg", "conf", "cpp", "cs", "css", "csv", "diff", "go", "h", "hpp", "html", "ini", "java",
+    "js", "json", "kt", "log", "lua", "md", "nix", "patch", "py", "rb", "rs", "sh", "sql", "toml",
+    "ts", "txt", "xml", "yaml", "yml",
+];
+
+/// Value of the `chat.attachments` setting
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AttachmentOptions {
+    /// Text beyond this many bytes is cut off
+    pub max_bytes: usize,
+    /// Text beyond this many tokens is cut off
+    pub max_tokens: usize,
+}
+
+impl Default for AttachmentOptions {
+    fn default() -> Self {
+        Self {
+            max_bytes: 32 * 1024,
+            max_tokens: 2000,
+        }
+    }
+}
+
+fn extension(filename: &str) -> Option<&str> {
+    filename.rsplit_once('.').map(|(_, ext)| ext)
+}
+
+pub fn is_image(attachment: &Attachment) -> bool {
+    attachment.content_type
+        .as_deref()
+        .is_some_and(|t| t.starts_with("image/"))
+}
+
+fn is_text(content_type: Option<&str>, filename: &str) -> bool {
+    content_type.is_some_and(|t| t.starts_with("text/"))
+        || extension(filename).is_some_and(|ext| TEXT_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Cut `text` to at most `max_bytes` without splitting a character
+fn truncate_bytes(text: &str, max_bytes: usize) -> &str {
+    if text.len() <= max_bytes {
+        return text;
+    }
+
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// Inline the contents of a text file as a fenced code block, or describe why it can't be
+pub fn format_text(filename: &str, contents: &[u8], options: &AttachmentOptions) -> String {
+    let text = match std::str::from_utf8(contents) {
+        Ok(text) if !text.contains('\0') => text,
+        _ => return format!("[attachment: {} (binary file, not shown)]", filename),
+    };
+
+    let truncated = truncate_bytes(text, options.max_bytes);
+    let truncated = truncate_tokens(truncated, options.max_tokens);
+
+    // The fence must be longer than any run of backticks in the file
+    let longest_run = truncated
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    let language = extension(filename).unwrap_or_default();
+
+    let mut formatted = format!(
+        "Attached file `{}`:\n{}{}\n{}\n{}",
+        filename,
+        fence,
+        language,
+        truncated.trim_end(),
+        fence,
+    );
+    if truncated.len() < text.len() {
+        formatted.push_str("\n[attachment truncated]");
+    }
+
+    formatted
+}
+
+/// Description of a non-image attachment which doesn't need downloading, or `None` if its contents are shown
+fn describe_without_contents(attachment: &Attachment) -> Option<String> {
+    let filename: &str = &attachment.filename;
+
+    if !is_text(attachment.content_type.as_deref(), filename) {
+        return Some(format!("[attachment: {} (binary file, not shown)]", filename));
+    }
+
+    if attachment.size > MAX_DOWNLOAD_BYTES {
+        return Some(format!("[attachment: {} (too large to show, {} bytes)]", filename, attachment.size));
+    }
+
+    None
+}
+
+/// Most tokens [describe] can use for a non-image attachment, so it can be budgeted for before downloading
+pub fn max_tokens(attachment: &Attachment, options: &AttachmentOptions) -> usize {
+    match describe_without_contents(attachment) {
+        Some(description) => count_tokens(&description),
+        None => max_text_tokens(&attachment.filename, options),
+    }
+}
+
+/// Most tokens [format_text] can use for a file
+fn max_text_tokens(filename: &str, options: &AttachmentOptions) -> usize {
+    options.max_tokens + count_tokens(filename) + FORMAT_TOKENS
+}
+
+/// Content to include in the conversation for a non-image attachment
+pub async fn describe(attachment: &Attachment, options: &AttachmentOptions, cache: &AttachmentCache) -> String {
+    if let Some(description) = describe_without_contents(attachment) {
+        return description;
+    }
+
+    let filename: &str = &attachment.filename;
+    match cache.download(attachment).await {
+        Ok(contents) => format_text(filename, &contents, options),
+        Err(err) => {
+            warn!("Failed to download attachment {}: {}", attachment.url, err);
+            format!("[attachment: {} (could not be downloaded)]", filename)
+        }
+    }
+}
+
+/// Short placeholder for a non-image attachment, for when its contents aren't needed
+pub fn placeholder(attachment: &Attachment) -> String {
+    describe_without_contents(attachment)
+        .unwrap_or_else(|| format!("[attachment: {}]", &*attachment.filename))
+}
+
+/// Contents of downloaded text attachments by ID, since attachments never change once uploaded
+pub struct AttachmentCache {
+    contents: Cache<AttachmentId, Arc<[u8]>>,
+}
+
+impl AttachmentCache {
+    pub fn new() -> Self {
+        Self {
+            contents: Cache::builder()
+                .weigher(|_, contents: &Arc<[u8]>| contents.len().try_into().unwrap_or(u32::MAX))
+                .max_capacity(MAX_CACHED_BYTES)
+                .time_to_idle(CACHE_IDLE)
+                .build(),
+        }
+    }
+
+    async fn download(&self, attachment: &Attachment) -> Result<Arc<[u8]>, Arc<crate::Error>> {
+        self.contents
+            .try_get_with(attachment.id, async {
+                let contents = attachment.download().await?;
+                Ok::<_, crate::Error>(Arc::from(contents))
+            })
+            .await
+    }
+}
+
+impl Default for AttachmentCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_text_files() {
+        assert!(is_text(Some("text/plain; charset=utf-8"), "notes"));
+        assert!(is_text(None, "main.RS"));
+        assert!(is_text(Some("application/octet-stream"), "server.log"));
+        assert!(!is_text(Some("application/zip"), "archive.zip"));
+        assert!(!is_text(None, "README"));
+    }
+
+    #[test]
+    fn formats_text_as_fenced_block() {
+        let formatted = format_text("main.rs", b"fn main() {}\n", &AttachmentOptions::default());
+
+        assert_eq!(formatted, "Attached file `main.rs`:\n```rs\nfn main() {}\n```");
+    }
+
+    #[test]
+    fn fence_is_longer_than_contents() {
+        let formatted = format_text("notes.md", b"```\ncode\n```", &AttachmentOptions::default());
+
+        assert!(formatted.contains("````md\n```\ncode\n```\n````"));
+    }
+
+    #[test]
+    fn truncates_to_limits() {
+        let options = AttachmentOptions {
+            max_bytes: 7,
+            max_tokens: 100,
+        };
+        let formatted = format_text("a.txt", "héllo world".as_bytes(), &options);
+
+        assert_eq!(formatted, "Attached file `a.txt`:\n```txt\nhéllo\n```\n[attachment truncated]");
+        assert_eq!(truncate_bytes("héllo", 2), "h");
+
+        let options = AttachmentOptions {
+            max_bytes: 1000,
+            max_tokens: 2,
+        };
+        let formatted = format_text("a.txt", "one two three four five".as_bytes(), &options);
+        assert!(formatted.ends_with("[attachment truncated]"));
+        assert!(!formatted.contains("five"));
+    }
+
+    #[test]
+    fn formatted_text_fits_budget() {
+        let options = AttachmentOptions {
+            max_bytes: 1024 * 1024,
+            max_tokens: 50,
+        };
+        let contents = "``` fn main() { println!(\"hi\"); }\n".repeat(100);
+        let formatted = format_text("some file.rs", contents.as_bytes(), &options);
+
+        assert!(count_tokens(&formatted) <= max_text_tokens("some file.rs", &options));
+    }
+
+    #[test]
+    fn rejects_binary_contents() {
+        let formatted = format_text("data.txt", &[0xff, 0xfe, 0x00], &AttachmentOptions::default());
+
+        assert_eq!(formatted, "[attachment: data.txt (binary file, not shown)]");
+    }
+}
@@ -0,0 +1,514 @@
+use openai::chat::{ChatCompletionMessage, ChatCompletionMessageDelta, ChatCompletionMessageRole};
+use serde::{Deserialize, Serialize, Serializer};
+use serde::ser::SerializeStruct;
+use crate::Error;
+use crate::error::InternalError;
+use crate::gpt::backend::{sse, ChatBackend, Completion, CompletionEvent, CompletionStream, Usage};
+use crate::gpt::ChatMessage;
+
+pub const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+const API_VERSION: &str = "2023-06-01";
+/// The Messages API requires an explicit limit on the response length
+const MAX_TOKENS: u32 = 4096;
+/// Sent in place of a user turn when the conversation starts with the assistant,
+/// since the Messages API requires the first message be from the user
+const CONVERSATION_START: &str = "[conversation start]";
+
+/// [ChatBackend] for the Anthropic Messages API
+#[derive(Debug, Clone)]
+pub struct AnthropicBackend {
+    client: reqwest::Client,
+    base_url: String,
+    key: String,
+}
+
+impl AnthropicBackend {
+    pub fn new(base_url: Option<String>, key: String) -> Self {
+        let base_url = base_url.unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            key,
+        }
+    }
+
+    async fn send(&self, request: &MessagesRequest<'_>) -> Result<reqwest::Response, Error> {
+        let response = self.client
+            .post(format!("{}/v1/messages", self.base_url))
+            .header("x-api-key", &self.key)
+            .header("anthropic-version", API_VERSION)
+            .json(request)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(response)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct MessagesRequest<'a> {
+    model: &'a str,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<Message>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct Message {
+    role: &'static str,
+    content: String,
+    /// URLs of images to send before the text
+    images: Vec<String>,
+}
+
+impl Message {
+    fn text(role: &'static str, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+            images: vec![],
+        }
+    }
+}
+
+impl Serialize for Message {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Message", 2)?;
+        state.serialize_field("role", self.role)?;
+
+        if self.images.is_empty() {
+            state.serialize_field("content", &self.content)?;
+        } else {
+            let images = self.images
+                .iter()
+                .map(|url| serde_json::json!({ "type": "image", "source": { "type": "url", "url": url } }));
+            // Empty text blocks are rejected by the API
+            let text = Some(&self.content)
+                .filter(|c| !c.trim().is_empty())
+                .map(|text| serde_json::json!({ "type": "text", "text": text }));
+
+            state.serialize_field("content", &images.chain(text).collect::<Vec<_>>())?;
+        }
+
+        state.end()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct MessagesResponse {
+    content: Vec<ContentBlock>,
+    #[serde(default)]
+    usage: Option<StreamUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentBlock {
+    Text { text: String },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    MessageStart { message: StartMessage },
+    ContentBlockDelta { delta: Delta },
+    MessageDelta {
+        delta: MessageDelta,
+        #[serde(default)]
+        usage: StreamUsage,
+    },
+    MessageStop,
+    Error { error: serde_json::Value },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Debug, Deserialize)]
+struct StartMessage {
+    #[serde(default)]
+    usage: StreamUsage,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageDelta {
+    stop_reason: Option<String>,
+}
+
+/// Token counts of a response, or when streaming spread over the `message_start` and `message_delta` events
+#[derive(Debug, Default, Deserialize)]
+struct StreamUsage {
+    #[serde(default)]
+    input_tokens: u32,
+    #[serde(default)]
+    output_tokens: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Delta {
+    TextDelta { text: String },
+    #[serde(other)]
+    Other,
+}
+
+/// Convert an OpenAI-style conversation into the Messages API format.
+///
+/// System messages are moved into the separate `system` field and consecutive messages
+/// with the same role are merged, since user and assistant turns must alternate.
+/// User messages are prefixed with the author's name as there is no `name` field.
+fn convert_messages(messages: &[ChatMessage]) -> (Option<String>, Vec<Message>) {
+    let mut system = vec![];
+    let mut converted: Vec<Message> = vec![];
+
+    for message in messages {
+        let content = message.content.as_deref().unwrap_or_default();
+        if content.trim().is_empty() && message.images.is_empty() {
+            continue; // Empty text blocks are rejected by the API
+        }
+        let images = message.images.iter().map(|i| i.url.clone());
+
+        let (role, content) = match message.role {
+            ChatCompletionMessageRole::System => {
+                system.push(content.to_string());
+                continue;
+            }
+            ChatCompletionMessageRole::Assistant => ("assistant", content.to_string()),
+            _ => match &message.name {
+                Some(name) => ("user", format!("{}: {}", name, content)),
+                None => ("user", content.to_string()),
+            },
+        };
+
+        match converted.last_mut() {
+            Some(last) if last.role == role => {
+                last.content.push_str("\n\n");
+                last.content.push_str(&content);
+                last.images.extend(images);
+            }
+            _ => converted.push(Message {
+                role,
+                content,
+                images: images.collect(),
+            }),
+        }
+    }
+
+    if converted.first().is_some_and(|m| m.role == "assistant") {
+        converted.insert(0, Message::text("user", CONVERSATION_START));
+    }
+
+    let system = if system.is_empty() {
+        None
+    } else {
+        Some(system.join("\n\n"))
+    };
+
+    (system, converted)
+}
+
+#[poise::async_trait]
+impl ChatBackend for AnthropicBackend {
+    async fn completion(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+    ) -> Result<Completion, Error> {
+        let (system, messages) = convert_messages(messages);
+        let request = MessagesRequest {
+            model,
+            max_tokens: MAX_TOKENS,
+            system,
+            messages,
+            stream: false,
+        };
+
+        let response: MessagesResponse = self.send(&request)
+            .await?
+            .json()
+            .await?;
+
+        let content = response.content
+            .into_iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text } => Some(text),
+                ContentBlock::Other => None,
+            })
+            .collect::<Vec<_>>();
+
+        if content.is_empty() {
+            return Err(InternalError::backend("Response contained no text").into());
+        }
+
+        let message = ChatCompletionMessage {
+            role: ChatCompletionMessageRole::Assistant,
+            content: Some(content.concat()),
+            name: None,
+            function_call: None,
+        };
+        let usage = response.usage.map(|usage| Usage {
+            prompt_tokens: usage.input_tokens,
+            completion_tokens: usage.output_tokens,
+        });
+
+        Ok(Completion { message, usage })
+    }
+
+    async fn stream_completion(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+    ) -> Result<CompletionStream, Error> {
+        use tokio_stream::StreamExt as _;
+
+        let (system, messages) = convert_messages(messages);
+        let request = MessagesRequest {
+            model,
+            max_tokens: MAX_TOKENS,
+            system,
+            messages,
+            stream: true,
+        };
+        let response = self.send(&request).await?;
+
+        let stream = sse::event_stream(response)
+            .map(|event| match event {
+                Ok(event) => match serde_json::from_str::<StreamEvent>(&event.data) {
+                    Ok(event) => event,
+                    Err(err) => {
+                        tracing::warn!("Failed to parse message event `{}`: {}", event.data, err);
+                        StreamEvent::Other
+                    }
+                },
+                Err(err) => StreamEvent::Error { error: err.to_string().into() },
+            })
+            .take_while({
+                // An error ends the stream, but is still passed on so it can be reported
+                let mut failed = false;
+                move |event| match event {
+                    _ if failed => false,
+                    StreamEvent::MessageStop => false,
+                    StreamEvent::Error { .. } => {
+                        failed = true;
+                        true
+                    }
+                    _ => true,
+                }
+            })
+            .map({
+                let mut input_tokens = 0;
+                move |event| match event {
+                    StreamEvent::Error { error } => {
+                        tracing::error!("Anthropic stream returned an error: {}", error);
+                        vec![CompletionEvent::Finish { reason: "error".to_string() }]
+                    }
+                    StreamEvent::MessageStart { message } => {
+                        input_tokens = message.usage.input_tokens;
+                        vec![]
+                    }
+                    StreamEvent::ContentBlockDelta { delta: Delta::TextDelta { text } } => {
+                        vec![CompletionEvent::Delta(ChatCompletionMessageDelta {
+                            role: Some(ChatCompletionMessageRole::Assistant),
+                            content: Some(text),
+                            name: None,
+                            function_call: None,
+                        })]
+                    }
+                    StreamEvent::MessageDelta { delta, usage } => {
+                        let usage = CompletionEvent::Usage(Usage {
+                            prompt_tokens: input_tokens,
+                            completion_tokens: usage.output_tokens,
+                        });
+                        match delta.stop_reason {
+                            Some(reason) => vec![CompletionEvent::Finish { reason }, usage],
+                            None => vec![usage],
+                        }
+                    }
+                    _ => vec![],
+                }
+            });
+        let stream = futures::StreamExt::flat_map(stream, tokio_stream::iter);
+
+        Ok(Box::pin(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpt::ImageContent;
+    use tokio_stream::StreamExt as _;
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn message(role: ChatCompletionMessageRole, name: Option<&str>, content: &str) -> ChatMessage {
+        ChatCompletionMessage {
+            role,
+            content: Some(content.to_string()),
+            name: name.map(str::to_string),
+            function_call: None,
+        }.into()
+    }
+
+    #[test]
+    fn convert_merges_consecutive_users() {
+        let (system, messages) = convert_messages(&[
+            message(ChatCompletionMessageRole::System, None, "Be nice"),
+            message(ChatCompletionMessageRole::User, Some("Alice"), "Hi"),
+            message(ChatCompletionMessageRole::User, Some("Bob"), "Hello"),
+            message(ChatCompletionMessageRole::Assistant, None, "Hey both"),
+            message(ChatCompletionMessageRole::User, Some("Alice"), "Bye"),
+        ]);
+
+        assert_eq!(system.as_deref(), Some("Be nice"));
+        assert_eq!(messages, vec![
+            Message::text("user", "Alice: Hi\n\nBob: Hello"),
+            Message::text("assistant", "Hey both"),
+            Message::text("user", "Alice: Bye"),
+        ]);
+    }
+
+    #[test]
+    fn convert_starts_with_user_turn() {
+        let (system, messages) = convert_messages(&[
+            message(ChatCompletionMessageRole::Assistant, None, "I spoke first"),
+            message(ChatCompletionMessageRole::User, Some("Alice"), ""),
+            message(ChatCompletionMessageRole::User, Some("Alice"), "Reply"),
+        ]);
+
+        assert_eq!(system, None);
+        assert_eq!(messages, vec![
+            Message::text("user", CONVERSATION_START),
+            Message::text("assistant", "I spoke first"),
+            Message::text("user", "Alice: Reply"),
+        ]);
+    }
+
+    #[test]
+    fn convert_sends_images_before_text() {
+        let mut with_image = message(ChatCompletionMessageRole::User, Some("Alice"), "Look");
+        with_image.images.push(ImageContent { url: "https://example.com/cat.png".to_string() });
+
+        let (_, messages) = convert_messages(&[with_image]);
+        assert_eq!(serde_json::to_value(&messages[0]).unwrap(), serde_json::json!({
+            "role": "user",
+            "content": [
+                { "type": "image", "source": { "type": "url", "url": "https://example.com/cat.png" } },
+                { "type": "text", "text": "Alice: Look" },
+            ],
+        }));
+    }
+
+    #[tokio::test]
+    async fn completion_sends_system_separately() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(header("x-api-key", "test-key"))
+            .and(header("anthropic-version", API_VERSION))
+            .and(body_partial_json(serde_json::json!({
+                "system": "Be nice",
+                "messages": [{ "role": "user", "content": "Alice: Hi" }],
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "msg_1",
+                "type": "message",
+                "role": "assistant",
+                "content": [{ "type": "text", "text": "Hello Alice" }],
+                "stop_reason": "end_turn",
+                "usage": { "input_tokens": 10, "output_tokens": 3 },
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let backend = AnthropicBackend::new(Some(server.uri()), "test-key".to_string());
+        let response = backend
+            .completion("claude", &[
+                message(ChatCompletionMessageRole::System, None, "Be nice"),
+                message(ChatCompletionMessageRole::User, Some("Alice"), "Hi"),
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(response.message.content.as_deref(), Some("Hello Alice"));
+        assert_eq!(response.usage, Some(Usage { prompt_tokens: 10, completion_tokens: 3 }));
+    }
+
+    #[tokio::test]
+    async fn stream_completion_parses_events() {
+        let body = [
+            ("message_start", r#"{"type":"message_start","message":{"id":"msg_1","usage":{"input_tokens":12,"output_tokens":1}}}"#),
+            ("content_block_start", r#"{"type":"content_block_start","index":0,"content_block":{"type":"text","text":""}}"#),
+            ("ping", r#"{"type":"ping"}"#),
+            ("content_block_delta", r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hello"}}"#),
+            ("content_block_delta", r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":" Alice"}}"#),
+            ("content_block_stop", r#"{"type":"content_block_stop","index":0}"#),
+            ("message_delta", r#"{"type":"message_delta","delta":{"stop_reason":"end_turn","stop_sequence":null},"usage":{"output_tokens":2}}"#),
+            ("message_stop", r#"{"type":"message_stop"}"#),
+        ].map(|(event, data)| format!("event: {}\ndata: {}\n\n", event, data)).concat();
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(body_partial_json(serde_json::json!({ "stream": true })))
+            .respond_with(ResponseTemplate::new(200)
+                .insert_header("content-type", "text/event-stream")
+                .set_body_string(body))
+            .mount(&server)
+            .await;
+
+        let backend = AnthropicBackend::new(Some(server.uri()), "test-key".to_string());
+        let events = backend
+            .stream_completion("claude", &[message(ChatCompletionMessageRole::User, Some("Alice"), "Hi")])
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+
+        let content = events
+            .iter()
+            .filter_map(|event| event.clone().into_delta()?.content)
+            .collect::<String>();
+        assert_eq!(content, "Hello Alice");
+
+        assert!(matches!(&events[2], CompletionEvent::Finish { reason } if reason == "end_turn"));
+        assert!(matches!(events[3], CompletionEvent::Usage(Usage { prompt_tokens: 12, completion_tokens: 2 })));
+    }
+
+    #[tokio::test]
+    async fn stream_completion_finishes_on_error() {
+        let body = [
+            ("message_start", r#"{"type":"message_start","message":{"id":"msg_1","usage":{"input_tokens":12,"output_tokens":1}}}"#),
+            ("content_block_delta", r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hello"}}"#),
+            ("error", r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#),
+            ("content_block_delta", r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":" Alice"}}"#),
+        ].map(|(event, data)| format!("event: {}\ndata: {}\n\n", event, data)).concat();
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .respond_with(ResponseTemplate::new(200)
+                .insert_header("content-type", "text/event-stream")
+                .set_body_string(body))
+            .mount(&server)
+            .await;
+
+        let backend = AnthropicBackend::new(Some(server.uri()), "test-key".to_string());
+        let events = backend
+            .stream_completion("claude", &[message(ChatCompletionMessageRole::User, Some("Alice"), "Hi")])
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].clone().into_delta().unwrap().content.as_deref(), Some("Hello"));
+        assert!(matches!(&events[1], CompletionEvent::Finish { reason } if reason == "error"));
+    }
+}
@@ -0,0 +1,160 @@
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use crate::Error;
+use crate::error::InternalError;
+use crate::gpt::backend::open_ai::DEFAULT_BASE_URL;
+
+/// An image produced by an [ImageBackend]
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedImage {
+    /// Encoded image, usually a PNG
+    pub data: Vec<u8>,
+    /// Prompt the provider actually used, if it rewrote the original
+    pub revised_prompt: Option<String>,
+}
+
+/// A provider capable of generating images from a text prompt
+#[poise::async_trait]
+pub trait ImageBackend: Send + Sync {
+    async fn generate(&self, prompt: &str) -> Result<GeneratedImage, Error>;
+}
+
+/// [ImageBackend] for the OpenAI Images API, or any server implementing the same API
+#[derive(Debug, Clone)]
+pub struct OpenAiImageBackend {
+    client: reqwest::Client,
+    base_url: String,
+    key: Option<String>,
+    model: String,
+    size: String,
+}
+
+impl OpenAiImageBackend {
+    pub fn new(base_url: Option<String>, key: Option<String>, model: String, size: String) -> Self {
+        let base_url = base_url.unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            key: key.filter(|k| !k.is_empty()),
+            model,
+            size,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ImageRequest<'a> {
+    model: &'a str,
+    prompt: &'a str,
+    n: u8,
+    size: &'a str,
+    response_format: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImageResponse {
+    data: Vec<ImageData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImageData {
+    b64_json: Option<String>,
+    revised_prompt: Option<String>,
+}
+
+#[poise::async_trait]
+impl ImageBackend for OpenAiImageBackend {
+    async fn generate(&self, prompt: &str) -> Result<GeneratedImage, Error> {
+        let request = ImageRequest {
+            model: &self.model,
+            prompt,
+            n: 1,
+            size: &self.size,
+            response_format: "b64_json",
+        };
+
+        let mut builder = self.client
+            .post(format!("{}/images/generations", self.base_url))
+            .json(&request);
+        if let Some(key) = &self.key {
+            builder = builder.bearer_auth(key);
+        }
+
+        let response: ImageResponse = builder.send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let image = response.data
+            .into_iter()
+            .next()
+            .ok_or_else(|| InternalError::backend("No image was generated"))?;
+        let data = image.b64_json
+            .ok_or_else(|| InternalError::backend("Image response is missing `b64_json`"))?;
+        let data = base64::engine::general_purpose::STANDARD
+            .decode(data)
+            .map_err(|err| InternalError::backend(format!("Image is not valid base64: {}", err)))?;
+
+        Ok(GeneratedImage {
+            data,
+            revised_prompt: image.revised_prompt,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn generate_decodes_image() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/images/generations"))
+            .and(header("authorization", "Bearer test-key"))
+            .and(body_partial_json(serde_json::json!({
+                "model": "test-model",
+                "prompt": "a cat",
+                "size": "256x256",
+                "response_format": "b64_json",
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "created": 0,
+                "data": [{ "b64_json": "aGVsbG8=", "revised_prompt": "a fluffy cat" }],
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let backend = OpenAiImageBackend::new(
+            Some(format!("{}/v1/", server.uri())),
+            Some("test-key".to_string()),
+            "test-model".to_string(),
+            "256x256".to_string(),
+        );
+
+        let image = backend.generate("a cat").await.unwrap();
+        assert_eq!(image.data, b"hello");
+        assert_eq!(image.revised_prompt.as_deref(), Some("a fluffy cat"));
+    }
+
+    #[tokio::test]
+    async fn generate_fails_without_image() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/images/generations"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "created": 0,
+                "data": [],
+            })))
+            .mount(&server)
+            .await;
+
+        let backend = OpenAiImageBackend::new(Some(server.uri()), None, "test-model".to_string(), "256x256".to_string());
+
+        assert!(backend.generate("a cat").await.is_err());
+    }
+}
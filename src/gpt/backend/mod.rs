@@ -0,0 +1,238 @@
+mod anthropic;
+mod image;
+mod open_ai;
+mod sse;
+
+use std::collections::HashMap;
+use std::ops::{Add, AddAssign};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use openai::chat::{ChatCompletionMessage, ChatCompletionMessageDelta};
+use serde::Serialize;
+use crate::Error;
+use crate::gpt::ChatMessage;
+use crate::error::InternalError;
+use crate::settings::config::{BackendKind, FaultybotConfig};
+
+pub use anthropic::AnthropicBackend;
+pub use image::{GeneratedImage, ImageBackend, OpenAiImageBackend};
+pub use open_ai::OpenAiBackend;
+
+/// Stream of partial responses produced by [ChatBackend::stream_completion]
+pub type CompletionStream = Pin<Box<dyn tokio_stream::Stream<Item=CompletionEvent> + Send>>;
+
+/// An item of a [CompletionStream]
+#[derive(Debug, Clone)]
+pub enum CompletionEvent {
+    /// The next part of the response
+    Delta(ChatCompletionMessageDelta),
+    /// The model has stopped responding, and the provider's reason why (eg `stop` or `length`)
+    Finish { reason: String },
+    /// Tokens used to generate the response, if the provider reports them
+    Usage(Usage),
+}
+
+impl CompletionEvent {
+    pub fn into_delta(self) -> Option<ChatCompletionMessageDelta> {
+        match self {
+            CompletionEvent::Delta(delta) => Some(delta),
+            _ => None,
+        }
+    }
+}
+
+/// Number of tokens used by a single completion
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+impl Add for Usage {
+    type Output = Usage;
+
+    fn add(self, rhs: Usage) -> Usage {
+        Usage {
+            prompt_tokens: self.prompt_tokens + rhs.prompt_tokens,
+            completion_tokens: self.completion_tokens + rhs.completion_tokens,
+        }
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, rhs: Usage) {
+        *self = *self + rhs;
+    }
+}
+
+/// A whole response generated by [ChatBackend::completion]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Completion {
+    pub message: ChatCompletionMessage,
+    /// Tokens used to generate the response, if the provider reports them
+    pub usage: Option<Usage>,
+}
+
+/// A function the model may call, as described to the backend
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    /// JSON schema of the arguments
+    pub parameters: serde_json::Value,
+}
+
+/// A provider capable of generating chat completions.
+///
+/// [crate::gpt::Chat] dispatches all requests through this trait so that
+/// providers can be swapped without changing how replies are produced.
+#[poise::async_trait]
+pub trait ChatBackend: Send + Sync {
+    /// Generate a single, complete response for the given conversation
+    async fn completion(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+    ) -> Result<Completion, Error>;
+
+    /// Generate a single, complete response which may be a call to one of `tools`
+    /// instead of a reply, indicated by [ChatCompletionMessage::function_call].
+    ///
+    /// Backends without function calling support ignore `tools`.
+    async fn completion_with_tools(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+    ) -> Result<Completion, Error> {
+        let _ = tools;
+        self.completion(model, messages).await
+    }
+
+    /// Generate a response for the given conversation as a stream of deltas.
+    ///
+    /// The stream ends once the model has finished responding.
+    async fn stream_completion(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+    ) -> Result<CompletionStream, Error>;
+}
+
+/// [ChatBackend] which adds up the usage reported by every completion made through it
+pub struct MeteredBackend {
+    inner: Arc<dyn ChatBackend>,
+    usage: Arc<Mutex<Usage>>,
+}
+
+impl MeteredBackend {
+    pub fn new(inner: Arc<dyn ChatBackend>) -> Self {
+        Self {
+            inner,
+            usage: Default::default(),
+        }
+    }
+
+    /// Total usage so far. Streamed completions are only counted once their stream has ended
+    pub fn usage(&self) -> Usage {
+        *self.usage.lock().unwrap()
+    }
+
+    fn add(&self, usage: Option<Usage>) {
+        if let Some(usage) = usage {
+            *self.usage.lock().unwrap() += usage;
+        }
+    }
+}
+
+#[poise::async_trait]
+impl ChatBackend for MeteredBackend {
+    async fn completion(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+    ) -> Result<Completion, Error> {
+        let completion = self.inner.completion(model, messages).await?;
+        self.add(completion.usage);
+        Ok(completion)
+    }
+
+    async fn completion_with_tools(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+    ) -> Result<Completion, Error> {
+        let completion = self.inner.completion_with_tools(model, messages, tools).await?;
+        self.add(completion.usage);
+        Ok(completion)
+    }
+
+    async fn stream_completion(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+    ) -> Result<CompletionStream, Error> {
+        use tokio_stream::StreamExt as _;
+
+        let usage = self.usage.clone();
+        let stream = self.inner
+            .stream_completion(model, messages)
+            .await?
+            .map(move |event| {
+                if let CompletionEvent::Usage(used) = &event {
+                    *usage.lock().unwrap() += *used;
+                }
+                event
+            });
+
+        Ok(Box::pin(stream))
+    }
+}
+
+/// All the [ChatBackend]s configured for this bot, keyed by name.
+///
+/// The `openai` backend is always available and is configured by the `openai` config section.
+/// Any additional backends (OpenAI-compatible or Anthropic) are declared under `backends`.
+pub struct Backends {
+    backends: HashMap<String, Arc<dyn ChatBackend>>,
+}
+
+impl Backends {
+    pub const DEFAULT: &'static str = "openai";
+
+    pub fn from_config(config: &FaultybotConfig) -> Self {
+        let mut backends: HashMap<String, Arc<dyn ChatBackend>> = HashMap::new();
+
+        backends.insert(
+            Self::DEFAULT.to_string(),
+            Arc::new(OpenAiBackend::new(
+                config.openai.base_url.clone(),
+                Some(config.openai.key.clone()),
+            )),
+        );
+
+        for (name, backend) in &config.backends {
+            let base_url = backend.base_url.clone();
+            let instance: Arc<dyn ChatBackend> = match backend.kind {
+                BackendKind::OpenAI => Arc::new(OpenAiBackend::new(base_url, backend.key.clone())),
+                BackendKind::Anthropic => Arc::new(AnthropicBackend::new(
+                    base_url,
+                    backend.key.clone().unwrap_or_default(),
+                )),
+            };
+            backends.insert(name.clone(), instance);
+        }
+
+        Self { backends }
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<dyn ChatBackend>, Error> {
+        let backend = self.backends
+            .get(name)
+            .cloned()
+            .ok_or_else(|| InternalError::unknown_backend(name))?;
+
+        Ok(backend)
+    }
+}
@@ -0,0 +1,406 @@
+use openai::chat::{ChatCompletionMessage, ChatCompletionMessageDelta};
+use serde::{Deserialize, Serialize};
+use crate::Error;
+use crate::error::InternalError;
+use crate::gpt::backend::{sse, ChatBackend, Completion, CompletionEvent, CompletionStream, ToolDefinition, Usage};
+use crate::gpt::ChatMessage;
+
+pub const DEFAULT_BASE_URL: &str = "https://api.openai.com/v1";
+
+/// [ChatBackend] for the OpenAI Chat Completions API.
+///
+/// Works with any server implementing the same API (eg Ollama, llama.cpp or vLLM)
+/// by pointing `base_url` at it.
+#[derive(Debug, Clone)]
+pub struct OpenAiBackend {
+    client: reqwest::Client,
+    base_url: String,
+    key: Option<String>,
+}
+
+impl OpenAiBackend {
+    pub fn new(base_url: Option<String>, key: Option<String>) -> Self {
+        let base_url = base_url.unwrap_or_else(|| DEFAULT_BASE_URL.to_string());
+        Self {
+            client: reqwest::Client::new(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            // Local servers generally don't need a key
+            key: key.filter(|k| !k.is_empty()),
+        }
+    }
+
+    async fn send(&self, request: &ChatRequest<'_>) -> Result<reqwest::Response, Error> {
+        let mut builder = self.client
+            .post(format!("{}/chat/completions", self.base_url))
+            .json(request);
+        if let Some(key) = &self.key {
+            builder = builder.bearer_auth(key);
+        }
+
+        let response = builder.send()
+            .await?
+            .error_for_status()?;
+
+        Ok(response)
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<serde_json::Value>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    functions: &'a [ToolDefinition],
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+#[derive(Debug, Serialize)]
+struct StreamOptions {
+    include_usage: bool,
+}
+
+/// Serialize `message`, sending any images as content parts
+fn request_message(message: &ChatMessage) -> Result<serde_json::Value, Error> {
+    let mut value = serde_json::to_value(&message.message)?;
+
+    if !message.images.is_empty() {
+        let text = message.content
+            .as_ref()
+            .map(|text| serde_json::json!({ "type": "text", "text": text }));
+        let images = message.images
+            .iter()
+            .map(|image| serde_json::json!({ "type": "image_url", "image_url": { "url": image.url } }));
+
+        value["content"] = text.into_iter().chain(images).collect();
+    }
+
+    Ok(value)
+}
+
+fn request_messages(messages: &[ChatMessage]) -> Result<Vec<serde_json::Value>, Error> {
+    messages.iter().map(request_message).collect()
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatResponse {
+    choices: Vec<ChatChoice>,
+    usage: Option<ChatUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChoice {
+    message: ChatCompletionMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChunk {
+    choices: Vec<ChatChunkChoice>,
+    usage: Option<ChatUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatUsage {
+    prompt_tokens: u32,
+    completion_tokens: u32,
+}
+
+impl From<ChatUsage> for Usage {
+    fn from(usage: ChatUsage) -> Self {
+        Usage {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+        }
+    }
+}
+
+impl ChatChunk {
+    fn into_events(self) -> Vec<CompletionEvent> {
+        let mut events = vec![];
+        if let Some(choice) = self.choices.into_iter().next() {
+            // The final chunk generally has an empty delta
+            if choice.finish_reason.is_none() || choice.delta.content.is_some() {
+                events.push(CompletionEvent::Delta(choice.delta));
+            }
+            if let Some(reason) = choice.finish_reason {
+                events.push(CompletionEvent::Finish { reason });
+            }
+        }
+        if let Some(usage) = self.usage {
+            events.push(CompletionEvent::Usage(usage.into()));
+        }
+
+        events
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatChunkChoice {
+    delta: ChatCompletionMessageDelta,
+    finish_reason: Option<String>,
+}
+
+#[poise::async_trait]
+impl ChatBackend for OpenAiBackend {
+    async fn completion(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+    ) -> Result<Completion, Error> {
+        self.completion_with_tools(model, messages, &[]).await
+    }
+
+    async fn completion_with_tools(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+    ) -> Result<Completion, Error> {
+        let request = ChatRequest {
+            model,
+            messages: request_messages(messages)?,
+            functions: tools,
+            stream: false,
+            stream_options: None,
+        };
+
+        let response: ChatResponse = self.send(&request)
+            .await?
+            .json()
+            .await?;
+
+        let choice = response.choices
+            .into_iter()
+            .next()
+            .ok_or_else(|| InternalError::backend("Completion returned no choices"))?;
+
+        Ok(Completion {
+            message: choice.message,
+            usage: response.usage.map(Usage::from),
+        })
+    }
+
+    async fn stream_completion(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+    ) -> Result<CompletionStream, Error> {
+        use tokio_stream::StreamExt as _;
+
+        let request = ChatRequest {
+            model,
+            messages: request_messages(messages)?,
+            functions: &[],
+            stream: true,
+            // Usage is sent in one last chunk, after the finish reason
+            stream_options: Some(StreamOptions { include_usage: true }),
+        };
+        let response = self.send(&request).await?;
+
+        let stream = sse::event_stream(response)
+            .take_while(|event| !matches!(event, Ok(event) if event.data == "[DONE]"))
+            .map(|event| {
+                let event = event.map_err(|err| format!("Error reading completion stream: {}", err))?;
+                serde_json::from_str::<ChatChunk>(&event.data)
+                    .map_err(|err| format!("Failed to parse completion chunk `{}`: {}", event.data, err))
+            })
+            .take_while({
+                // An error ends the stream, but is still passed on so it can be reported
+                let mut failed = false;
+                move |chunk| {
+                    if failed {
+                        return false;
+                    }
+                    failed = chunk.is_err();
+                    true
+                }
+            })
+            .map(|chunk| match chunk {
+                Ok(chunk) => chunk.into_events(),
+                Err(err) => {
+                    tracing::error!("{}", err);
+                    vec![CompletionEvent::Finish { reason: "error".to_string() }]
+                }
+            });
+        let stream = futures::StreamExt::flat_map(stream, tokio_stream::iter);
+
+        Ok(Box::pin(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openai::chat::ChatCompletionMessageRole;
+    use crate::gpt::ImageContent;
+    use tokio_stream::StreamExt as _;
+    use wiremock::matchers::{body_partial_json, header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn user_message(content: &str) -> ChatMessage {
+        ChatCompletionMessage {
+            role: ChatCompletionMessageRole::User,
+            content: Some(content.to_string()),
+            name: None,
+            function_call: None,
+        }.into()
+    }
+
+    #[test]
+    fn request_message_sends_images_as_parts() {
+        let mut message = user_message("What is this?");
+        assert_eq!(request_message(&message).unwrap()["content"], "What is this?");
+
+        message.images.push(ImageContent { url: "https://example.com/cat.png".to_string() });
+        assert_eq!(request_message(&message).unwrap()["content"], serde_json::json!([
+            { "type": "text", "text": "What is this?" },
+            { "type": "image_url", "image_url": { "url": "https://example.com/cat.png" } },
+        ]));
+    }
+
+    #[tokio::test]
+    async fn completion_uses_configured_base_url() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/chat/completions"))
+            .and(header("authorization", "Bearer test-key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion",
+                "created": 0,
+                "model": "llama3",
+                "choices": [{
+                    "index": 0,
+                    "message": { "role": "assistant", "content": "Hi there" },
+                    "finish_reason": "stop"
+                }],
+                "usage": { "prompt_tokens": 5, "completion_tokens": 2, "total_tokens": 7 }
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let backend = OpenAiBackend::new(
+            Some(format!("{}/v1/", server.uri())),
+            Some("test-key".to_string()),
+        );
+        let response = backend
+            .completion("llama3", &[user_message("Hello")])
+            .await
+            .unwrap();
+
+        assert_eq!(response.message.content.as_deref(), Some("Hi there"));
+        assert_eq!(response.usage, Some(Usage { prompt_tokens: 5, completion_tokens: 2 }));
+    }
+
+    #[tokio::test]
+    async fn completion_with_tools_returns_function_call() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .and(body_partial_json(serde_json::json!({
+                "functions": [{ "name": "current_time", "description": "Get the time", "parameters": {} }],
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "choices": [{
+                    "index": 0,
+                    "message": {
+                        "role": "assistant",
+                        "content": null,
+                        "function_call": { "name": "current_time", "arguments": "{}" }
+                    },
+                    "finish_reason": "function_call"
+                }]
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let backend = OpenAiBackend::new(Some(server.uri()), None);
+        let tools = [ToolDefinition {
+            name: "current_time".to_string(),
+            description: "Get the time".to_string(),
+            parameters: serde_json::json!({}),
+        }];
+        let response = backend
+            .completion_with_tools("llama3", &[user_message("What time is it?")], &tools)
+            .await
+            .unwrap();
+
+        let call = response.message.function_call.unwrap();
+        assert_eq!(response.usage, None);
+        assert_eq!(call.name, "current_time");
+        assert_eq!(call.arguments, "{}");
+    }
+
+    #[tokio::test]
+    async fn stream_completion_parses_event_stream() {
+        let body = [
+            r#"data: {"choices":[{"index":0,"delta":{"role":"assistant","content":""},"finish_reason":null}]}"#,
+            r#"data: {"choices":[{"index":0,"delta":{"content":"Hello"},"finish_reason":null}]}"#,
+            r#"data: {"choices":[{"index":0,"delta":{"content":" world"},"finish_reason":null}]}"#,
+            r#"data: {"choices":[{"index":0,"delta":{},"finish_reason":"stop"}]}"#,
+            r#"data: {"choices":[],"usage":{"prompt_tokens":9,"completion_tokens":2,"total_tokens":11}}"#,
+            "data: [DONE]",
+        ].map(|line| format!("{}\n\n", line)).concat();
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200)
+                .insert_header("content-type", "text/event-stream")
+                .set_body_string(body))
+            .mount(&server)
+            .await;
+
+        let backend = OpenAiBackend::new(Some(server.uri()), None);
+        let events = backend
+            .stream_completion("llama3", &[user_message("Hello")])
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+
+        let content = events
+            .iter()
+            .filter_map(|event| event.clone().into_delta()?.content)
+            .collect::<String>();
+        assert_eq!(content, "Hello world");
+
+        assert!(matches!(&events[events.len() - 2], CompletionEvent::Finish { reason } if reason == "stop"));
+        assert!(matches!(events.last(), Some(CompletionEvent::Usage(Usage { prompt_tokens: 9, completion_tokens: 2 }))));
+    }
+
+    #[tokio::test]
+    async fn stream_completion_finishes_on_error() {
+        let body = [
+            r#"data: {"choices":[{"index":0,"delta":{"content":"Hello"},"finish_reason":null}]}"#,
+            r#"data: {"error":{"message":"The server had an error while processing your request","type":"server_error"}}"#,
+            r#"data: {"choices":[{"index":0,"delta":{"content":" world"},"finish_reason":null}]}"#,
+            "data: [DONE]",
+        ].map(|line| format!("{}\n\n", line)).concat();
+
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/chat/completions"))
+            .respond_with(ResponseTemplate::new(200)
+                .insert_header("content-type", "text/event-stream")
+                .set_body_string(body))
+            .mount(&server)
+            .await;
+
+        let backend = OpenAiBackend::new(Some(server.uri()), None);
+        let events = backend
+            .stream_completion("llama3", &[user_message("Hello")])
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].clone().into_delta().unwrap().content.as_deref(), Some("Hello"));
+        assert!(matches!(&events[1], CompletionEvent::Finish { reason } if reason == "error"));
+    }
+}
@@ -0,0 +1,124 @@
+use tokio_stream::wrappers::ReceiverStream;
+
+/// A single Server-Sent Event
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+}
+
+/// Incremental parser for `text/event-stream` bodies.
+///
+/// Chunks can be fed as they arrive and may split lines (or UTF-8 sequences) at any point.
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    /// Feed the next chunk of the body, returning all events completed by it
+    pub fn feed(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = vec![];
+        while let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+            let line = self.buffer.drain(..=pos).collect::<Vec<_>>();
+            let line = String::from_utf8_lossy(&line).into_owned();
+            let line = line.trim_end_matches(['\n', '\r']);
+
+            if line.is_empty() {
+                // Blank line dispatches the pending event
+                if !self.data.is_empty() || self.event.is_some() {
+                    events.push(SseEvent {
+                        event: self.event.take(),
+                        data: self.data.join("\n"),
+                    });
+                    self.data.clear();
+                }
+                continue;
+            }
+
+            if line.starts_with(':') {
+                continue; // comment
+            }
+
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+                None => (line, ""),
+            };
+
+            match field {
+                "event" => self.event = Some(value.to_string()),
+                "data" => self.data.push(value.to_string()),
+                _ => (), // `id` and `retry` aren't needed by any backend
+            }
+        }
+
+        events
+    }
+}
+
+/// Read a streamed response body in the background, yielding each [SseEvent] as it arrives.
+///
+/// If reading the body fails, the error is yielded as the last item.
+pub fn event_stream(mut response: reqwest::Response) -> ReceiverStream<Result<SseEvent, reqwest::Error>> {
+    let (tx, rx) = tokio::sync::mpsc::channel(16);
+
+    tokio::spawn(async move {
+        let mut parser = SseParser::default();
+        loop {
+            let chunk = match response.chunk().await {
+                Ok(Some(chunk)) => chunk,
+                Ok(None) => break,
+                Err(err) => {
+                    let _ = tx.send(Err(err)).await;
+                    break;
+                }
+            };
+
+            for event in parser.feed(&chunk) {
+                if tx.send(Ok(event)).await.is_err() {
+                    return; // receiver dropped, nobody cares anymore
+                }
+            }
+        }
+    });
+
+    ReceiverStream::new(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_events_split_across_chunks() {
+        let mut parser = SseParser::default();
+
+        assert!(parser.feed(b"event: ping\nda").is_empty());
+        let events = parser.feed(b"ta: {\"a\":1}\r\n\r\ndata: second\n");
+        assert_eq!(events, vec![SseEvent {
+            event: Some("ping".to_string()),
+            data: "{\"a\":1}".to_string(),
+        }]);
+
+        let events = parser.feed(b"\n");
+        assert_eq!(events, vec![SseEvent {
+            event: None,
+            data: "second".to_string(),
+        }]);
+    }
+
+    #[test]
+    fn joins_multiline_data_and_skips_comments() {
+        let mut parser = SseParser::default();
+
+        let events = parser.feed(b": keep-alive\n\ndata: one\ndata:two\n\n");
+        assert_eq!(events, vec![SseEvent {
+            event: None,
+            data: "one\ntwo".to_string(),
+        }]);
+    }
+}
@@ -1,31 +1,125 @@
-use openai::chat::{ChatCompletion, ChatCompletionMessage, ChatCompletionMessageRole};
+use std::sync::Arc;
+use openai::chat::{ChatCompletionMessage, ChatCompletionMessageDelta, ChatCompletionMessageRole};
 use poise::serenity_prelude as serenity;
-use poise::serenity_prelude::{Context, GuildId, Message};
-use tracing::debug;
-use async_recursion::async_recursion;
+use poise::serenity_prelude::{ChannelId, Context, GuildId, Message, MessageId};
+use serde::Deserialize;
+use tracing::{debug, warn};
 use crate::Error;
-use crate::error::FaultyBotError;
+use crate::gpt::attachment::{self, AttachmentCache, AttachmentOptions};
+use crate::gpt::backend::{ChatBackend, CompletionEvent, CompletionStream, MeteredBackend, Usage};
+use crate::gpt::history::{message_tokens, HistoryBuilder};
+use crate::gpt::message::{ChatMessage, ImageContent};
 use crate::gpt::persona::Persona;
+use crate::gpt::summary::{Summarizer, SummaryManager};
+use crate::gpt::tools::ToolRunner;
+
+const HISTORY_PAGE_SIZE: u8 = 50;
+/// Upper bound on how far back to look for channel history, regardless of token budget
+const MAX_HISTORY_PAGES: usize = 10;
+/// Upper bound on the part of the budget set aside for summarizing older messages
+const MAX_SUMMARY_TOKENS: usize = 1024;
+/// Upper bound on summarization requests made to catch up on dropped messages before responding
+const MAX_SUMMARY_PASSES: usize = 3;
+/// Maximum number of tool calls the model can make before it has to give an answer
+const MAX_TOOL_CALLS: usize = 5;
+
+/// Per-request settings used when building a [Chat]
+#[derive(Debug, Clone)]
+pub struct ChatOptions {
+    /// Maximum number of tokens of history (including the system prompt) to send
+    pub context_tokens: usize,
+    /// How to send images, or `None` if the model can't see them
+    pub vision: Option<VisionOptions>,
+    /// Limits for inlining text file attachments
+    pub attachments: AttachmentOptions,
+}
+
+/// Value of the `chat.vision` setting
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct VisionOptions {
+    pub enabled: bool,
+    /// Larger attachments are replaced by a placeholder
+    pub max_image_bytes: u32,
+    /// Maximum number of images sent per message
+    pub max_images: usize,
+}
+
+impl Default for VisionOptions {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_image_bytes: 5 * 1024 * 1024,
+            max_images: 4,
+        }
+    }
+}
+
+/// An image attached to or embedded in a Discord message
+#[derive(Debug, Clone)]
+struct ImageSource {
+    name: String,
+    url: String,
+    /// Size in bytes, if known
+    size: Option<u32>,
+}
+
+/// Choose which images to send to the model. Any others are described with a placeholder instead
+fn select_images(sources: Vec<ImageSource>, vision: Option<&VisionOptions>) -> (Vec<ImageContent>, Vec<String>) {
+    let mut images = vec![];
+    let mut placeholders = vec![];
+
+    for source in sources {
+        let allowed = vision.is_some_and(|vision| {
+            images.len() < vision.max_images
+                && source.size.map_or(true, |size| size <= vision.max_image_bytes)
+        });
+
+        if allowed {
+            images.push(ImageContent { url: source.url });
+        } else {
+            placeholders.push(format!("[image: {}]", source.name));
+        }
+    }
+
+    (images, placeholders)
+}
 
 pub struct Chat {
+    backend: Arc<MeteredBackend>,
     model: String,
-    messages: Vec<ChatCompletionMessage>,
+    messages: Vec<ChatMessage>,
 }
 
 impl Chat {
-    pub async fn from(ctx: &Context, persona: Persona, message: &Message) -> Result<Self, crate::Error> {
-        let bot_name = bot_name(ctx, message.guild_id).await;
-        let system_prompt = persona.prompt(bot_name.as_str());
-
-        let mut instance = Self {
-            model: persona.model(),
+    /// Create a new [Chat] with only a system prompt
+    pub fn new(backend: Arc<dyn ChatBackend>, model: String, system_prompt: String) -> Self {
+        Self {
+            backend: Arc::new(MeteredBackend::new(backend)),
+            model,
             messages: vec![ChatCompletionMessage {
                 role: ChatCompletionMessageRole::System,
                 content: Some(system_prompt),
                 function_call: None,
                 name: None,
-            }],
-        };
+            }.into()],
+        }
+    }
+
+    pub async fn from(
+        ctx: &Context,
+        backend: Arc<dyn ChatBackend>,
+        persona: Persona,
+        message: &Message,
+        summaries: &SummaryManager,
+        attachments: &AttachmentCache,
+        options: ChatOptions,
+    ) -> Result<Self, crate::Error> {
+        let bot_name = bot_name(ctx, message.guild_id).await;
+        let system_prompt = persona.prompt(bot_name.as_str());
+
+        let mut instance = Self::new(backend, persona.model(), system_prompt);
+        let mut history = HistoryBuilder::new(instance.messages.remove(0), options.context_tokens);
 
         let is_thread = message
             .channel(&ctx)
@@ -35,11 +129,17 @@ impl Chat {
             .unwrap_or(false);
 
         if is_thread {
-            instance.add_channel_messages(ctx, message).await?;
+            history.reserve(MAX_SUMMARY_TOKENS.min(options.context_tokens / 8));
+            instance.add_thread_messages(ctx, message, &options, &mut history, summaries, attachments).await?;
         } else {
-            instance.add_message_chain(ctx, message).await;
+            add_message_chain(ctx, message, &options, &mut history, attachments).await;
         }
 
+        if history.is_full() {
+            debug!("Chat history truncated to {} tokens", options.context_tokens);
+        }
+        instance.messages = history.build();
+
         debug!(
             "Starting chat as {}. With history: {:?}",
             persona.name(), instance.messages
@@ -48,95 +148,312 @@ impl Chat {
         Ok(instance)
     }
 
-    pub async fn completion(&mut self) -> Result<ChatCompletionMessage, Error> {
-        let completion = ChatCompletion::builder(&self.model, self.messages.clone())
-            .create()
-            .await?;
+    /// Add `message` and the messages preceding it in its thread, summarizing any which don't fit in `history`.
+    ///
+    /// Messages that were already summarized are never fetched. Once messages have to be dropped, the summary
+    /// is brought up to date and also moved ahead over the older half of the history,
+    /// so it doesn't have to be regenerated for every new message.
+    async fn add_thread_messages(
+        &self,
+        ctx: &Context,
+        message: &Message,
+        options: &ChatOptions,
+        history: &mut HistoryBuilder,
+        summaries: &SummaryManager,
+        attachments: &AttachmentCache,
+    ) -> Result<(), Error> {
+        let channel_id = message.channel_id;
+        let previous = summaries.latest(channel_id).await?;
+        let summarized_until = previous.as_ref().map(|s| s.last_message_id);
+
+        // Work out which messages fit before touching `history`, since that depends on the summary
+        let mut fitting = history.clone();
+        let mut kept = vec![];
+        let mut newest_dropped = None;
+        let mut pages = HistoryPages::new(message, summarized_until);
+        let mut next = Some(message.clone());
+        while let Some(message) = next {
+            let id = message.id;
+            // The message being responded to is always kept, even if it has to be cut short
+            let required = kept.is_empty();
+            let chat_message = convert_if_fits(ctx, thread_message(message), options, attachments, &fitting, required).await;
+            let Some(chat_message) = chat_message else {
+                newest_dropped = Some(id);
+                break;
+            };
+            if !fitting.push_older(chat_message.clone()) && !required {
+                newest_dropped = Some(id);
+                break;
+            }
+            kept.push((id, chat_message));
+            next = pages.next(ctx).await?;
+        }
+
+        let mut summary = previous;
+        if let Some(newest_dropped) = newest_dropped {
+            // Keep the newer half of the history as it is
+            let summarize_before = kept[kept.len() / 2].0;
+
+            for _ in 0..MAX_SUMMARY_PASSES {
+                if summary.as_ref().is_some_and(|s| s.last_message_id >= newest_dropped) {
+                    break;
+                }
+
+                let mut summarizer = Summarizer::new(summary.clone(), options.context_tokens);
+                let collected = collect_unsummarized(ctx, channel_id, options, &kept, summarize_before, &mut summarizer).await;
+                if let Err(err) = collected {
+                    warn!("Failed to fetch messages to summarize in {}: {}", channel_id, err);
+                    break;
+                }
+                if !summarizer.has_dropped() {
+                    break;
+                }
 
-        let choice = completion.choices.first().unwrap().message.clone();
+                let updated = match summarizer.summarize(self.backend.as_ref(), &self.model).await {
+                    Ok(Some(updated)) => updated,
+                    Ok(None) => break,
+                    Err(err) => {
+                        warn!("Failed to summarize conversation in {}: {}", channel_id, err);
+                        break;
+                    }
+                };
+                if let Err(err) = summaries.save(channel_id, &updated).await {
+                    warn!("Failed to save conversation summary for {}: {}", channel_id, err);
+                }
+                summary = Some(updated);
+            }
+        }
+
+        let summarized_until = summary.as_ref().map(|s| s.last_message_id);
+        for (_, chat_message) in kept
+            .into_iter()
+            .filter(|(id, _)| summarized_until.map_or(true, |until| *id > until))
+        {
+            history.push_older(chat_message);
+        }
+        if let Some(summary) = summary {
+            history.set_summary(summary.to_message());
+        }
+
+        Ok(())
+    }
+
+    pub async fn completion(&mut self) -> Result<ChatCompletionMessage, Error> {
+        let choice = self.backend
+            .completion(&self.model, &self.messages)
+            .await?
+            .message;
 
-        self.messages.push(choice.clone());
+        self.messages.push(choice.clone().into());
 
         Ok(choice)
     }
 
+    /// Messages sent with the next request
+    pub fn messages(&self) -> &[ChatMessage] {
+        &self.messages
+    }
+
+    /// Tokens used by every completion this [Chat] has made, including summaries and tool calls.
+    ///
+    /// Streamed responses are only included once the stream has ended.
+    pub fn usage(&self) -> Usage {
+        self.backend.usage()
+    }
+
+    /// Returns a stream of completions for this [Chat], after first running any tool calls the model makes.
+    ///
+    /// When tools are available the model's answer is generated before it can be streamed,
+    /// so the stream yields the whole response at once.
+    /// Tool calls and their results are kept in the history, but the final response is not.
+    pub async fn stream_with_tools(&mut self, tools: &dyn ToolRunner) -> Result<CompletionStream, Error> {
+        let definitions = tools.definitions();
+        if definitions.is_empty() {
+            return self.backend.stream_completion(&self.model, &self.messages).await;
+        }
+
+        for _ in 0..MAX_TOOL_CALLS {
+            let response = self.backend
+                .completion_with_tools(&self.model, &self.messages, &definitions)
+                .await?
+                .message;
+
+            let Some(call) = response.function_call.clone() else {
+                let delta = ChatCompletionMessageDelta {
+                    role: Some(ChatCompletionMessageRole::Assistant),
+                    content: response.content,
+                    name: None,
+                    function_call: None,
+                };
+                return Ok(Box::pin(tokio_stream::once(CompletionEvent::Delta(delta))));
+            };
+
+            debug!("Calling tool {} with {}", call.name, call.arguments);
+            let result = tools.call(&call).await;
+
+            self.messages.push(response.into());
+            self.messages.push(ChatCompletionMessage {
+                role: ChatCompletionMessageRole::Function,
+                name: Some(call.name),
+                content: Some(result),
+                function_call: None,
+            }.into());
+        }
+
+        // Force an answer from whatever the tools have returned so far
+        self.backend.stream_completion(&self.model, &self.messages).await
+    }
+
     /// Returns a stream of completions for this [Chat].
     ///
     /// Does NOT update internal state and so take ownership of [Chat] to prevent accidental misuse
-    pub async fn stream_completion(self) -> Result<impl tokio_stream::Stream<Item=openai::chat::ChatCompletionMessageDelta>, Error> {
-        use tokio_stream::StreamExt as _;
-
-        let rx = ChatCompletion::builder(&self.model, self.messages.clone())
-            .create_stream()
+    pub async fn stream_completion(self) -> Result<CompletionStream, Error> {
+        self.backend
+            .stream_completion(&self.model, &self.messages)
             .await
-            .map_err( FaultyBotError::boxed)?;
-
-        let stream = tokio_stream::wrappers::ReceiverStream::new(rx)
-            .filter(|c| c.choices.first().unwrap().finish_reason.is_none())
-            .map(|c| c.choices.first().unwrap().delta.clone());
-
-        Ok(stream)
-    }
-
-    #[async_recursion]
-    async fn add_message_chain(&mut self, ctx: &Context, message: &Message) {
-        if let Some(message_reference) = &message.message_reference {
-            let referenced = ctx
-                .http
-                .get_message(
-                    message_reference.channel_id,
-                    message_reference.message_id.unwrap(),
-                )
-                .await;
-            if let Ok(referenced) = referenced {
-                self.add_message_chain(ctx, &referenced).await;
-            }
-        }
+    }
+}
 
-        let bot_id = ctx.cache.current_user().id;
+/// Add `message` and the chain of messages it replies to, stopping once the history is full
+async fn add_message_chain(
+    ctx: &Context,
+    message: &Message,
+    options: &ChatOptions,
+    history: &mut HistoryBuilder,
+    attachments: &AttachmentCache,
+) {
+    let mut next = Some(message.clone());
+    let mut required = true;
 
-        let (role, name) = if message.author.id == bot_id {
-            (ChatCompletionMessageRole::Assistant, None)
-        } else {
-            let author_nick = message
-                .author_nick(ctx)
-                .await
-                .unwrap_or_else(|| message.author.name.clone().into_string());
+    while let Some(message) = next.take() {
+        let reference = message
+            .message_reference
+            .as_ref()
+            .and_then(|r| r.message_id.map(|id| (r.channel_id, id)));
 
-            (ChatCompletionMessageRole::User, Some(author_nick))
+        let Some(chat_message) = convert_if_fits(ctx, message, options, attachments, history, required).await else {
+            break;
         };
+        if !history.push_older(chat_message) {
+            break;
+        }
+        required = false;
 
-        let chat_message = ChatCompletionMessage {
-            role,
-            name,
-            content: Some(message.content.clone().into_string()),
-            function_call: None,
-        };
+        if let Some((channel_id, message_id)) = reference {
+            next = ctx.http
+                .get_message(channel_id, message_id)
+                .await
+                .ok();
+        }
+    }
+}
 
-        self.messages.push(chat_message);
+/// Pages through the messages preceding a message in its channel, newest first,
+/// stopping at the start of the channel or at the end of the previous summary
+struct HistoryPages {
+    channel_id: ChannelId,
+    before: MessageId,
+    summarized_until: Option<MessageId>,
+    page: std::vec::IntoIter<Message>,
+    fetched: usize,
+}
+
+impl HistoryPages {
+    fn new(message: &Message, summarized_until: Option<MessageId>) -> Self {
+        Self {
+            channel_id: message.channel_id,
+            before: message.id,
+            summarized_until,
+            page: vec![].into_iter(),
+            fetched: 0,
+        }
     }
 
-    async fn add_channel_messages(
-        &mut self,
-        ctx: &Context,
-        message: &Message,
-    ) -> Result<(), Error> {
-        // TODO use .messages_iter with a Stream instead
-        let messages_fut = message
-            .channel_id
-            .messages(ctx, serenity::GetMessages::default().limit(25).before(message.id))
-            .await?
-            .into_iter()
-            .rev()
-            .map(|m| async { m.into_chat_message(ctx).await });
+    async fn next(&mut self, ctx: &Context) -> Result<Option<Message>, Error> {
+        if self.page.as_slice().is_empty() {
+            if self.fetched == MAX_HISTORY_PAGES {
+                return Ok(None);
+            }
+            self.fetched += 1;
 
-        let mut messages = futures::future::join_all(messages_fut).await;
-        self.messages.append(&mut messages);
+            // Messages are returned newest first
+            let page = self.channel_id
+                .messages(ctx, serenity::GetMessages::default().limit(HISTORY_PAGE_SIZE).before(self.before))
+                .await?;
+            if let Some(oldest) = page.last() {
+                self.before = oldest.id;
+            }
+            self.page = page.into_iter();
+        }
 
-        Ok(())
+        // An empty page means the start of the channel was reached
+        let message = self.page
+            .next()
+            .filter(|message| self.summarized_until.map_or(true, |id| message.id > id));
+        if message.is_none() {
+            self.fetched = MAX_HISTORY_PAGES;
+            self.page = vec![].into_iter();
+        }
+
+        Ok(message)
+    }
+}
+
+/// Threads started from a message begin with a placeholder pointing at it
+fn thread_message(message: Message) -> Message {
+    match message.referenced_message {
+        Some(starter) if message.kind == serenity::MessageType::ThreadStarterMessage => *starter,
+        _ => message,
     }
 }
 
+/// Add the oldest messages in a thread which `summarizer` doesn't cover yet, up to but excluding `until`.
+///
+/// Messages which were already converted for the history are taken from `converted` instead of converting them again.
+/// Text attachments of the others aren't downloaded, only their names are needed for the summary.
+async fn collect_unsummarized(
+    ctx: &Context,
+    channel_id: ChannelId,
+    options: &ChatOptions,
+    converted: &[(MessageId, ChatMessage)],
+    until: MessageId,
+    summarizer: &mut Summarizer,
+) -> Result<(), Error> {
+    // A thread can't contain messages older than itself
+    let mut after = summarizer
+        .summarized_until()
+        .unwrap_or_else(|| MessageId::new(channel_id.get()));
+
+    for _ in 0..MAX_HISTORY_PAGES {
+        let mut page = channel_id
+            .messages(ctx, serenity::GetMessages::default().limit(HISTORY_PAGE_SIZE).after(after))
+            .await?;
+        page.sort_by_key(|message| message.id);
+
+        let Some(newest) = page.last() else {
+            break; // Reached the end of the channel
+        };
+        after = newest.id;
+
+        for message in page {
+            let id = message.id;
+            if id >= until {
+                return Ok(());
+            }
+
+            let chat_message = match converted.iter().find(|(converted_id, _)| *converted_id == id) {
+                Some((_, chat_message)) => chat_message.clone(),
+                None => thread_message(message).into_chat_message(ctx, options).await,
+            };
+            if !summarizer.push_newer(id, chat_message) {
+                return Ok(());
+            }
+        }
+    }
+
+    Ok(())
+}
+
 async fn bot_name(ctx: &Context, guild_id: Option<GuildId>) -> String {
     let user = ctx.cache.current_user().clone();
 
@@ -149,14 +466,71 @@ async fn bot_name(ctx: &Context, guild_id: Option<GuildId>) -> String {
     }
 }
 
+/// Convert `message` if it fits in `history`, and only then download its text attachments.
+///
+/// Attachments are budgeted for at the most they could use. `required` messages are always converted.
+async fn convert_if_fits(
+    ctx: &Context,
+    message: Message,
+    options: &ChatOptions,
+    attachments: &AttachmentCache,
+    history: &HistoryBuilder,
+    required: bool,
+) -> Option<ChatMessage> {
+    let files = non_image_attachments(&message);
+    let mut chat_message = message.into_chat_message_without_files(ctx, options).await;
+
+    let max_tokens = message_tokens(&chat_message) + files
+        .iter()
+        .map(|file| attachment::max_tokens(file, &options.attachments))
+        .sum::<usize>();
+    if !required && !history.fits(max_tokens) {
+        return None;
+    }
+
+    for file in &files {
+        append_line(&mut chat_message, &attachment::describe(file, &options.attachments, attachments).await);
+    }
+    Some(chat_message)
+}
+
+fn non_image_attachments(message: &Message) -> Vec<serenity::Attachment> {
+    message.attachments
+        .iter()
+        .filter(|a| !attachment::is_image(a))
+        .cloned()
+        .collect()
+}
+
+fn append_line(message: &mut ChatMessage, line: &str) {
+    let content = message.content.get_or_insert_with(String::new);
+    if !content.is_empty() {
+        content.push('\n');
+    }
+    content.push_str(line);
+}
+
 #[poise::async_trait]
-trait IntoChatCompletionMessage {
-    async fn into_chat_message(self, ctx: &Context) -> ChatCompletionMessage;
+trait IntoChatMessage {
+    /// Convert without downloading text attachments, which are described by name instead
+    async fn into_chat_message(self, ctx: &Context, options: &ChatOptions) -> ChatMessage;
+
+    /// Convert leaving out any attachments other than images
+    async fn into_chat_message_without_files(self, ctx: &Context, options: &ChatOptions) -> ChatMessage;
 }
 
 #[poise::async_trait]
-impl IntoChatCompletionMessage for Message {
-    async fn into_chat_message(self, ctx: &Context) -> ChatCompletionMessage {
+impl IntoChatMessage for Message {
+    async fn into_chat_message(self, ctx: &Context, options: &ChatOptions) -> ChatMessage {
+        let files = non_image_attachments(&self);
+        let mut chat_message = self.into_chat_message_without_files(ctx, options).await;
+        for file in &files {
+            append_line(&mut chat_message, &attachment::placeholder(file));
+        }
+        chat_message
+    }
+
+    async fn into_chat_message_without_files(self, ctx: &Context, options: &ChatOptions) -> ChatMessage {
         let (role, name) = if self.author.id == ctx.cache.current_user().id {
             (ChatCompletionMessageRole::Assistant, None)
         } else {
@@ -168,11 +542,187 @@ impl IntoChatCompletionMessage for Message {
             (ChatCompletionMessageRole::User, Some(author_nick))
         };
 
-        ChatCompletionMessage {
-            role,
-            name,
-            content: Some(self.content.clone().into_string()),
-            function_call: None,
+        let attachments = self.attachments
+            .iter()
+            .filter(|a| attachment::is_image(a))
+            .map(|a| ImageSource {
+                name: a.filename.to_string(),
+                url: a.url.to_string(),
+                size: Some(a.size),
+            });
+        // Link previews usually only have a thumbnail
+        let embeds = self.embeds
+            .iter()
+            .filter_map(|e| e.image.as_ref().map(|i| i.url.to_string())
+                .or_else(|| e.thumbnail.as_ref().map(|t| t.url.to_string())))
+            .map(|url| ImageSource {
+                name: url.rsplit('/').next().unwrap_or_default().to_string(),
+                url,
+                size: None,
+            });
+
+        let vision = options.vision.as_ref().filter(|v| v.enabled);
+        let (images, placeholders) = select_images(attachments.chain(embeds).collect(), vision);
+
+        let mut content = self.content.clone().into_string();
+        for placeholder in placeholders {
+            if !content.is_empty() {
+                content.push('\n');
+            }
+            content.push_str(&placeholder);
+        }
+
+        ChatMessage {
+            message: ChatCompletionMessage {
+                role,
+                name,
+                content: Some(content),
+                function_call: None,
+            },
+            images,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gpt::backend::ToolDefinition;
+    use crate::test_util::ScriptedBackend;
+    use openai::chat::ChatCompletionFunctionCall;
+    use tokio_stream::StreamExt as _;
+
+    #[tokio::test]
+    async fn completion_appends_response_to_history() {
+        let backend = Arc::new(ScriptedBackend::new(["first", "second"]));
+        let mut chat = Chat::new(backend.clone(), "test-model".to_string(), "prompt".to_string());
+
+        let response = chat.completion().await.unwrap();
+        assert_eq!(response.content.as_deref(), Some("first"));
+
+        chat.completion().await.unwrap();
+
+        let requests = backend.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].len(), 1);
+        // Second request must include the first response
+        assert_eq!(requests[1].len(), 2);
+        assert_eq!(requests[1][1].content.as_deref(), Some("first"));
+    }
+
+    #[tokio::test]
+    async fn stream_completion_uses_backend() {
+        let backend = Arc::new(ScriptedBackend::new(["hello there world"]));
+        let chat = Chat::new(backend.clone(), "test-model".to_string(), "prompt".to_string());
+
+        let content = chat
+            .stream_completion()
+            .await
+            .unwrap()
+            .filter_map(|e| e.into_delta()?.content)
+            .collect::<Vec<_>>()
+            .await
+            .concat();
+
+        assert_eq!(content, "hello there world");
+        assert_eq!(backend.requests()[0][0].content.as_deref(), Some("prompt"));
+    }
+
+    /// Answers every call with the name of the tool
+    struct EchoTools;
+
+    #[poise::async_trait]
+    impl ToolRunner for EchoTools {
+        fn definitions(&self) -> Vec<ToolDefinition> {
+            vec![ToolDefinition {
+                name: "echo".to_string(),
+                description: "Echo".to_string(),
+                parameters: serde_json::json!({}),
+            }]
         }
+
+        async fn call(&self, call: &ChatCompletionFunctionCall) -> String {
+            format!("called {}", call.name)
+        }
+    }
+
+    #[tokio::test]
+    async fn stream_with_tools_feeds_results_back() {
+        let backend = Arc::new(ScriptedBackend::from_messages([
+            ChatCompletionMessage {
+                role: ChatCompletionMessageRole::Assistant,
+                content: None,
+                name: None,
+                function_call: Some(ChatCompletionFunctionCall {
+                    name: "echo".to_string(),
+                    arguments: "{}".to_string(),
+                }),
+            },
+            ChatCompletionMessage {
+                role: ChatCompletionMessageRole::Assistant,
+                content: Some("final answer".to_string()),
+                name: None,
+                function_call: None,
+            },
+        ]));
+        let mut chat = Chat::new(backend.clone(), "test-model".to_string(), "prompt".to_string());
+
+        let content = chat
+            .stream_with_tools(&EchoTools)
+            .await
+            .unwrap()
+            .filter_map(|e| e.into_delta()?.content)
+            .collect::<Vec<_>>()
+            .await
+            .concat();
+
+        assert_eq!(content, "final answer");
+
+        let requests = backend.requests();
+        assert_eq!(requests.len(), 2);
+        let result = &requests[1][2];
+        assert_eq!(result.role, ChatCompletionMessageRole::Function);
+        assert_eq!(result.name.as_deref(), Some("echo"));
+        assert_eq!(result.content.as_deref(), Some("called echo"));
+        assert_eq!(chat.messages(), &requests[1][..]);
+        // One prompt token per message and one completion token per response
+        assert_eq!(chat.usage(), Usage { prompt_tokens: 1 + 3, completion_tokens: 2 });
+    }
+
+    fn image(name: &str, size: Option<u32>) -> ImageSource {
+        ImageSource {
+            name: name.to_string(),
+            url: format!("https://example.com/{}", name),
+            size,
+        }
+    }
+
+    #[test]
+    fn select_images_uses_placeholders_without_vision() {
+        let (images, placeholders) = select_images(vec![image("cat.png", Some(10))], None);
+
+        assert!(images.is_empty());
+        assert_eq!(placeholders, vec!["[image: cat.png]"]);
+    }
+
+    #[test]
+    fn select_images_respects_limits() {
+        let vision = VisionOptions {
+            enabled: true,
+            max_image_bytes: 100,
+            max_images: 2,
+        };
+        let (images, placeholders) = select_images(vec![
+            image("small.png", Some(10)),
+            image("huge.png", Some(1000)),
+            image("embed.png", None),
+            image("extra.png", Some(10)),
+        ], Some(&vision));
+
+        assert_eq!(images, vec![
+            ImageContent { url: "https://example.com/small.png".to_string() },
+            ImageContent { url: "https://example.com/embed.png".to_string() },
+        ]);
+        assert_eq!(placeholders, vec!["[image: huge.png]", "[image: extra.png]"]);
     }
 }
@@ -0,0 +1,221 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use openai::chat::ChatCompletionMessageRole;
+use poise::serenity_prelude::{ChannelId, GuildId, MessageId, UserId};
+use sea_orm::{ColumnTrait, EntityTrait, IntoActiveValue, QueryFilter, TransactionTrait};
+use sha2::{Digest, Sha256};
+use tokio_stream::StreamExt as _;
+use entities::{conversation, conversation_message};
+use crate::Error;
+use crate::gpt::backend::{CompletionEvent, CompletionStream, Usage};
+use crate::gpt::ChatMessage;
+use crate::util::Toi64;
+
+/// What the model sent back while a response was streamed
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResponseLog {
+    pub content: String,
+    pub finish_reason: Option<String>,
+    pub usage: Option<Usage>,
+}
+
+impl ResponseLog {
+    /// Record everything passing through `stream`. The log is complete once the stream has ended
+    pub fn track(stream: CompletionStream) -> (CompletionStream, Arc<Mutex<ResponseLog>>) {
+        let log = Arc::new(Mutex::new(ResponseLog::default()));
+
+        let stream = stream.map({
+            let log = log.clone();
+            move |event| {
+                log.lock().unwrap().observe(&event);
+                event
+            }
+        });
+
+        (Box::pin(stream), log)
+    }
+
+    fn observe(&mut self, event: &CompletionEvent) {
+        match event {
+            CompletionEvent::Delta(delta) => {
+                let is_assistant = matches!(delta.role, None | Some(ChatCompletionMessageRole::Assistant));
+                if let Some(content) = delta.content.as_ref().filter(|_| is_assistant) {
+                    self.content.push_str(content);
+                }
+            }
+            CompletionEvent::Finish { reason } => self.finish_reason = Some(reason.clone()),
+            CompletionEvent::Usage(usage) => self.usage = Some(*usage),
+        }
+    }
+}
+
+/// A completed exchange with the model, in response to a Discord message
+pub struct ConversationRecord<'a> {
+    pub guild_id: Option<GuildId>,
+    pub channel_id: ChannelId,
+    pub user_id: UserId,
+    pub message_id: MessageId,
+    pub persona_id: i32,
+    pub model: &'a str,
+    /// Builtin prompts are stored as a hash since they can be looked up from the source
+    pub builtin_prompt: bool,
+    pub messages: &'a [ChatMessage],
+    pub response: ResponseLog,
+    pub latency: Duration,
+}
+
+pub struct ConversationManager {
+    db: crate::Database,
+}
+
+impl ConversationManager {
+    pub fn new(db: crate::Database) -> Self {
+        Self { db }
+    }
+
+    /// Save an exchange along with every message sent to the model
+    pub async fn record(&self, record: ConversationRecord<'_>) -> Result<(), Error> {
+        let txn = self.db.connection().begin().await?;
+
+        let conversation = conversation::Entity::insert(conversation::ActiveModel {
+            guild_id: record.guild_id.map(|id| id.to_i64()).into_active_value(),
+            channel_id: record.channel_id.to_i64().into_active_value(),
+            user_id: record.user_id.to_i64().into_active_value(),
+            message_id: record.message_id.to_i64().into_active_value(),
+            persona_id: Some(record.persona_id).into_active_value(),
+            model: record.model.to_string().into_active_value(),
+            response: record.response.content.into_active_value(),
+            finish_reason: record.response.finish_reason.into_active_value(),
+            prompt_tokens: record.response.usage.map(|u| u.prompt_tokens as i32).into_active_value(),
+            completion_tokens: record.response.usage.map(|u| u.completion_tokens as i32).into_active_value(),
+            latency_ms: (record.latency.as_millis() as i32).into_active_value(),
+            ..Default::default()
+        })
+        .exec(&txn)
+        .await?;
+
+        let messages = record.messages
+            .iter()
+            .enumerate()
+            .map(|(position, message)| {
+                let hash_prompt = record.builtin_prompt
+                    && position == 0
+                    && message.role == ChatCompletionMessageRole::System;
+                let (content, content_hash) = match &message.content {
+                    Some(content) if hash_prompt => (None, Some(hash(content))),
+                    content => (content.clone(), None),
+                };
+
+                conversation_message::ActiveModel {
+                    conversation_id: conversation.last_insert_id.into_active_value(),
+                    position: (position as i32).into_active_value(),
+                    role: role_name(&message.role).into_active_value(),
+                    name: message.name.clone().into_active_value(),
+                    content: content.into_active_value(),
+                    content_hash: content_hash.into_active_value(),
+                    ..Default::default()
+                }
+            })
+            .collect::<Vec<_>>();
+
+        if !messages.is_empty() {
+            conversation_message::Entity::insert_many(messages)
+                .exec(&txn)
+                .await?;
+        }
+
+        txn.commit().await?;
+
+        Ok(())
+    }
+
+    /// Delete conversations older than `retention` from a guild, or from a DM channel if `guild_id` is `None`
+    pub async fn prune(
+        &self,
+        guild_id: Option<GuildId>,
+        channel_id: ChannelId,
+        retention: Duration,
+    ) -> Result<u64, Error> {
+        let cutoff = chrono::Duration::from_std(retention)
+            .ok()
+            .and_then(|retention| chrono::Utc::now().checked_sub_signed(retention));
+        let Some(cutoff) = cutoff else {
+            return Ok(0); // Nothing can be that old
+        };
+
+        let scope = match guild_id {
+            Some(guild_id) => conversation::Column::GuildId.eq(guild_id.to_i64()),
+            None => conversation::Column::GuildId.is_null()
+                .and(conversation::Column::ChannelId.eq(channel_id.to_i64())),
+        };
+
+        // Messages are removed by the cascading foreign key
+        let result = conversation::Entity::delete_many()
+            .filter(scope)
+            .filter(conversation::Column::CreatedAt.lt(cutoff))
+            .exec(self.db.connection())
+            .await?;
+
+        Ok(result.rows_affected)
+    }
+}
+
+/// Role as it's named in the OpenAI API, eg `assistant`
+fn role_name(role: &ChatCompletionMessageRole) -> String {
+    serde_json::to_value(role)
+        .ok()
+        .and_then(|role| role.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+fn hash(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openai::chat::ChatCompletionMessageDelta;
+
+    fn delta(role: Option<ChatCompletionMessageRole>, content: &str) -> CompletionEvent {
+        CompletionEvent::Delta(ChatCompletionMessageDelta {
+            role,
+            content: Some(content.to_string()),
+            name: None,
+            function_call: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn track_records_response() {
+        let events = vec![
+            delta(Some(ChatCompletionMessageRole::Assistant), "Hello"),
+            delta(Some(ChatCompletionMessageRole::Function), "ignored"),
+            delta(None, " world"),
+            CompletionEvent::Finish { reason: "length".to_string() },
+            CompletionEvent::Usage(Usage { prompt_tokens: 10, completion_tokens: 2 }),
+        ];
+
+        let (stream, log) = ResponseLog::track(Box::pin(tokio_stream::iter(events)));
+        assert_eq!(stream.collect::<Vec<_>>().await.len(), 5);
+
+        assert_eq!(*log.lock().unwrap(), ResponseLog {
+            content: "Hello world".to_string(),
+            finish_reason: Some("length".to_string()),
+            usage: Some(Usage { prompt_tokens: 10, completion_tokens: 2 }),
+        });
+    }
+
+    #[test]
+    fn role_name_matches_api() {
+        assert_eq!(role_name(&ChatCompletionMessageRole::Assistant), "assistant");
+    }
+
+    #[test]
+    fn hash_is_hex_sha256() {
+        assert_eq!(hash("abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::VecDeque;
+use lazy_static::lazy_static;
+use tiktoken_rs::CoreBPE;
+use crate::gpt::message::ChatMessage;
+
+/// Approximate number of tokens used by the chat format for every message
+const TOKENS_PER_MESSAGE: usize = 4;
+/// Rough cost of a single image. Varies a lot with resolution and provider
+const TOKENS_PER_IMAGE: usize = 1000;
+
+lazy_static! {
+    // Exact counts vary by model/provider, but cl100k is close enough for budgeting
+    static ref TOKENIZER: CoreBPE = tiktoken_rs::cl100k_base().expect("Failed to load tokenizer");
+}
+
+pub fn count_tokens(text: &str) -> usize {
+    TOKENIZER.encode_with_special_tokens(text).len()
+}
+
+/// Approximate number of tokens `message` will consume in a request
+pub fn message_tokens(message: &ChatMessage) -> usize {
+    TOKENS_PER_MESSAGE
+        + message.content.as_deref().map(count_tokens).unwrap_or(0)
+        + message.name.as_deref().map(count_tokens).unwrap_or(0)
+        + message.images.len() * TOKENS_PER_IMAGE
+}
+
+/// Truncate `text` to at most `max_tokens` tokens
+pub fn truncate_tokens(text: &str, max_tokens: usize) -> String {
+    let tokens = TOKENIZER.encode_with_special_tokens(text);
+    if tokens.len() <= max_tokens {
+        return text.to_string();
+    }
+
+    // A cut part way through a multi-byte character doesn't decode, so back off until it does
+    (0..=max_tokens)
+        .rev()
+        .find_map(|end| TOKENIZER.decode(tokens[..end].to_vec()).ok())
+        .unwrap_or_default()
+}
+
+/// Cut the content of `message` short so the whole message uses at most `max_tokens` tokens
+pub fn truncate_message(mut message: ChatMessage, max_tokens: usize) -> ChatMessage {
+    let tokens = message_tokens(&message);
+    if tokens <= max_tokens {
+        return message;
+    }
+
+    let overhead = tokens - message.content.as_deref().map(count_tokens).unwrap_or(0);
+    let available = max_tokens.saturating_sub(overhead);
+    message.content = message.content.take().map(|c| truncate_tokens(&c, available));
+    message
+}
+
+/// Assembles conversation history from newest to oldest until a token budget is filled.
+///
+/// The system prompt is always kept intact and the newest message is always included
+/// (truncated if it alone exceeds the budget). Older messages are dropped once the budget is reached.
+/// Part of the budget can be [reserved](HistoryBuilder::reserve) for a summary of the dropped messages.
+#[derive(Debug, Clone)]
+pub struct HistoryBuilder {
+    system: ChatMessage,
+    summary: Option<ChatMessage>,
+    history: VecDeque<ChatMessage>,
+    budget: usize,
+    used: usize,
+    reserved: usize,
+    full: bool,
+}
+
+impl HistoryBuilder {
+    pub fn new(system_prompt: ChatMessage, budget: usize) -> Self {
+        Self {
+            used: message_tokens(&system_prompt),
+            system: system_prompt,
+            summary: None,
+            history: VecDeque::new(),
+            budget,
+            reserved: 0,
+            full: false,
+        }
+    }
+
+    /// Set aside `tokens` of the budget for a summary added later with [HistoryBuilder::set_summary]
+    pub fn reserve(&mut self, tokens: usize) {
+        self.used = self.used - self.reserved + tokens;
+        self.reserved = tokens;
+    }
+
+    /// Add a summary of older messages right after the system prompt.
+    ///
+    /// The summary is truncated to fit in the [reserved](HistoryBuilder::reserve) tokens.
+    pub fn set_summary(&mut self, summary: ChatMessage) {
+        self.summary = Some(truncate_message(summary, self.reserved));
+    }
+
+    /// Add the next older message to the history.
+    ///
+    /// Returns `false` if the message didn't fit, after which the history is considered full
+    /// and no further messages will be accepted.
+    pub fn push_older(&mut self, message: ChatMessage) -> bool {
+        if self.full {
+            return false;
+        }
+
+        let tokens = message_tokens(&message);
+        if self.used + tokens <= self.budget {
+            self.used += tokens;
+            self.history.push_front(message);
+            return true;
+        }
+
+        if self.history.is_empty() {
+            // Never drop the message being responded to, just cut it short
+            let message = truncate_message(message, self.budget.saturating_sub(self.used));
+            self.used += message_tokens(&message);
+            self.history.push_front(message);
+        }
+
+        self.full = true;
+        false
+    }
+
+    /// Whether the budget has been reached and older messages are being dropped
+    pub fn is_full(&self) -> bool {
+        self.full
+    }
+
+    /// Whether a message using `tokens` would still be accepted
+    pub fn fits(&self, tokens: usize) -> bool {
+        !self.full && self.used + tokens <= self.budget
+    }
+
+    /// Final list of messages, oldest first, starting with the system prompt and summary
+    pub fn build(self) -> Vec<ChatMessage> {
+        std::iter::once(self.system)
+            .chain(self.summary)
+            .chain(self.history)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openai::chat::{ChatCompletionMessage, ChatCompletionMessageRole};
+    use crate::gpt::message::ImageContent;
+
+    fn message(role: ChatCompletionMessageRole, content: &str) -> ChatMessage {
+        ChatCompletionMessage {
+            role,
+            content: Some(content.to_string()),
+            name: None,
+            function_call: None,
+        }.into()
+    }
+
+    #[test]
+    fn drops_oldest_messages_over_budget() {
+        let system = message(ChatCompletionMessageRole::System, "You are a bot");
+        let newest = message(ChatCompletionMessageRole::User, "newest message");
+        let middle = message(ChatCompletionMessageRole::Assistant, "middle message");
+        let oldest = message(ChatCompletionMessageRole::User, "oldest message");
+
+        let budget = message_tokens(&system) + message_tokens(&newest) + message_tokens(&middle);
+        let mut builder = HistoryBuilder::new(system.clone(), budget);
+
+        assert!(builder.push_older(newest.clone()));
+        assert!(builder.push_older(middle.clone()));
+        assert!(!builder.is_full());
+        assert!(!builder.push_older(oldest));
+        assert!(builder.is_full());
+
+        assert_eq!(builder.build(), vec![system, middle, newest]);
+    }
+
+    #[test]
+    fn stops_accepting_once_full() {
+        let system = message(ChatCompletionMessageRole::System, "You are a bot");
+        let short = message(ChatCompletionMessageRole::User, "hi");
+        let long = message(ChatCompletionMessageRole::User, &"word ".repeat(50));
+
+        let budget = message_tokens(&system) + 2 * message_tokens(&short);
+        let mut builder = HistoryBuilder::new(system, budget);
+
+        assert!(builder.push_older(short.clone()));
+        assert!(!builder.push_older(long));
+        // Would fit, but history must stay contiguous
+        assert!(!builder.push_older(short));
+        assert_eq!(builder.build().len(), 2);
+    }
+
+    #[test]
+    fn truncates_newest_message_and_keeps_system_prompt() {
+        let system = message(ChatCompletionMessageRole::System, &"prompt ".repeat(20));
+        let huge = message(ChatCompletionMessageRole::User, &"word ".repeat(1000));
+
+        let budget = message_tokens(&system) + 20;
+        let mut builder = HistoryBuilder::new(system.clone(), budget);
+
+        assert!(!builder.push_older(huge));
+        let messages = builder.build();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0], system);
+        assert!(message_tokens(&messages[1]) <= 20);
+        assert!(messages[1].content.as_ref().unwrap().starts_with("word"));
+    }
+
+    #[test]
+    fn reserves_space_for_summary() {
+        let system = message(ChatCompletionMessageRole::System, "You are a bot");
+        let newest = message(ChatCompletionMessageRole::User, "newest message");
+        let older = message(ChatCompletionMessageRole::User, "older message");
+        let summary = message(ChatCompletionMessageRole::System, &"summary ".repeat(100));
+
+        let reserved = 20;
+        let budget = message_tokens(&system) + message_tokens(&newest) + message_tokens(&older) + reserved - 1;
+        let mut builder = HistoryBuilder::new(system.clone(), budget);
+        builder.reserve(reserved);
+
+        assert!(builder.push_older(newest.clone()));
+        assert!(!builder.push_older(older));
+        builder.set_summary(summary);
+
+        let messages = builder.build();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0], system);
+        assert!(message_tokens(&messages[1]) <= reserved);
+        assert_eq!(messages[2], newest);
+    }
+
+    #[test]
+    fn truncates_without_splitting_characters() {
+        // Each crab is three tokens
+        let text = "🦀".repeat(20);
+        assert_eq!(truncate_tokens(&text, 3), "🦀");
+        assert_eq!(truncate_tokens(&text, 4), "🦀");
+        assert_eq!(truncate_tokens(&text, 8), "🦀🦀");
+        assert_eq!(truncate_tokens(&text, 2), "");
+    }
+
+    #[test]
+    fn counts_images() {
+        let mut with_image = message(ChatCompletionMessageRole::User, "look at this");
+        let without_image = with_image.clone();
+        with_image.images.push(ImageContent { url: "https://example.com/cat.png".to_string() });
+
+        assert_eq!(message_tokens(&with_image), message_tokens(&without_image) + TOKENS_PER_IMAGE);
+    }
+}
@@ -0,0 +1,57 @@
+use std::sync::Arc;
+use poise::serenity_prelude::{ChannelId, GuildId, UserId};
+use crate::{Data, Error};
+use crate::gpt::backend::{GeneratedImage, ImageBackend};
+use crate::rate_limit::{Bucket, RateLimit, RateLimiter};
+use crate::settings::{SettingsContext, SettingsScopeKind, SettingsValue};
+
+/// [RateLimit] on image generations, shared by everyone in a guild
+const COOLDOWN_KEY: &str = "imagine.cooldown";
+
+/// Generates images for `/imagine` and the `generate_image` tool
+pub struct ImageGenerator {
+    backend: Arc<dyn ImageBackend>,
+    rate_limiter: RateLimiter,
+}
+
+impl ImageGenerator {
+    pub fn new(backend: Arc<dyn ImageBackend>, rate_limiter: RateLimiter) -> Self {
+        Self {
+            backend,
+            rate_limiter,
+        }
+    }
+
+    /// Generate an image on behalf of a user, enforcing the `imagine.cooldown` setting.
+    ///
+    /// Callers are responsible for checking [Permission::GenerateImage](crate::permissions::Permission::GenerateImage).
+    pub async fn generate(
+        &self,
+        data: &Data,
+        user_id: UserId,
+        channel_id: ChannelId,
+        guild_id: Option<GuildId>,
+        prompt: &str,
+    ) -> Result<GeneratedImage, Error> {
+        let cooldown: SettingsValue<RateLimit> = data
+            .settings_manager
+            .get_value(SettingsContext {
+                guild_id,
+                channel_id: Some(channel_id),
+                user_id: Some(user_id),
+            }, COOLDOWN_KEY)
+            .await?;
+
+        if let Some(limit) = *cooldown.value() {
+            // DMs have no guild, so their cooldown applies to the channel instead
+            let scope = match guild_id {
+                Some(guild_id) => SettingsScopeKind::Guild(guild_id),
+                None => SettingsScopeKind::Channel(channel_id),
+            };
+            // Taken right away so slow generations can't be stacked up in parallel
+            self.rate_limiter.acquire(&[Bucket::new("imagine", scope, limit)]).await?;
+        }
+
+        self.backend.generate(prompt).await
+    }
+}
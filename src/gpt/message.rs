@@ -0,0 +1,41 @@
+use std::ops::{Deref, DerefMut};
+use openai::chat::ChatCompletionMessage;
+
+/// An image sent to vision-capable models alongside a message's text
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ImageContent {
+    /// Publicly accessible URL of the image
+    pub url: String,
+}
+
+/// A [ChatCompletionMessage] along with any images attached to it.
+///
+/// Derefs to the inner message so it can be used anywhere a plain text message is expected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatMessage {
+    pub message: ChatCompletionMessage,
+    pub images: Vec<ImageContent>,
+}
+
+impl From<ChatCompletionMessage> for ChatMessage {
+    fn from(message: ChatCompletionMessage) -> Self {
+        Self {
+            message,
+            images: vec![],
+        }
+    }
+}
+
+impl Deref for ChatMessage {
+    type Target = ChatCompletionMessage;
+
+    fn deref(&self) -> &Self::Target {
+        &self.message
+    }
+}
+
+impl DerefMut for ChatMessage {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.message
+    }
+}
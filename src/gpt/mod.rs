@@ -1,6 +1,22 @@
-
+mod attachment;
+pub mod backend;
 mod chat;
+mod conversation;
+mod history;
+mod image;
+mod message;
+mod model;
 mod persona;
+mod summary;
+mod usage;
+pub mod tools;
 
-pub use chat::Chat;
-pub use persona::{Persona, PersonaManager};
\ No newline at end of file
+pub use attachment::{AttachmentCache, AttachmentOptions};
+pub use chat::{Chat, ChatOptions, VisionOptions};
+pub use conversation::{ConversationManager, ConversationRecord, ResponseLog};
+pub use image::ImageGenerator;
+pub use message::{ChatMessage, ImageContent};
+pub use model::{ModelInfo, ModelRegistry};
+pub use persona::{Persona, PersonaManager};
+pub use summary::SummaryManager;
+pub use usage::{QuotaPeriod, UsageManager, UsageTotals};
@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use entities::model;
+use sea_orm::{EntityTrait, IntoActiveValue};
+use sea_orm::sea_query::OnConflict;
+use crate::Error;
+use crate::error::UserError;
+use crate::settings::config::{self, ModelCapabilities, ModelPricing};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelInfo {
+    pub name: String,
+    /// Name of the [Backend](crate::gpt::backend::Backends) serving this model
+    pub provider: String,
+    pub context_window: u32,
+    pub pricing: ModelPricing,
+    pub capabilities: ModelCapabilities,
+}
+
+impl ModelInfo {
+    /// Tokens kept free in the context window for the model's response
+    const MAX_RESPONSE_TOKENS: u32 = 4096;
+
+    /// Number of tokens of prompt and history that can be sent to this model
+    /// while leaving room for its response
+    pub fn history_budget(&self) -> usize {
+        let reserved = Self::MAX_RESPONSE_TOKENS.min(self.context_window / 4);
+        (self.context_window - reserved) as usize
+    }
+}
+
+/// All LLM models available to personas.
+///
+/// Models declared in the `models` config section are saved to the database on startup,
+/// so adding a model only requires a config change.
+pub struct ModelRegistry {
+    models: BTreeMap<String, ModelInfo>,
+}
+
+impl ModelRegistry {
+    pub const DEFAULT_MODEL: &'static str = "gpt-3.5-turbo";
+
+    /// Save the config-declared models and load every known model
+    pub async fn load(db: &crate::Database, declared: &[config::Model]) -> Result<Self, Error> {
+        for model in declared {
+            model::Entity::insert(model::ActiveModel {
+                name: model.name.clone().into_active_value(),
+                provider: model.provider.clone().into_active_value(),
+                context_window: (model.context_window as i32).into_active_value(),
+                prompt_price: model.pricing.prompt.into_active_value(),
+                completion_price: model.pricing.completion.into_active_value(),
+                vision: model.capabilities.vision.into_active_value(),
+                tools: model.capabilities.tools.into_active_value(),
+                ..Default::default()
+            })
+            .on_conflict(
+                OnConflict::column(model::Column::Name)
+                    .update_columns([
+                        model::Column::Provider,
+                        model::Column::ContextWindow,
+                        model::Column::PromptPrice,
+                        model::Column::CompletionPrice,
+                        model::Column::Vision,
+                        model::Column::Tools,
+                    ])
+                    .to_owned(),
+            )
+            .exec(db.connection())
+            .await?;
+        }
+
+        let models = model::Entity::find()
+            .all(db.connection())
+            .await?
+            .into_iter()
+            .map(ModelInfo::from)
+            .map(|m| (m.name.clone(), m))
+            .collect();
+
+        Ok(Self { models })
+    }
+
+    pub fn get(&self, name: &str) -> Result<&ModelInfo, Error> {
+        let model = self.models
+            .get(name)
+            .ok_or_else(|| UserError::not_found(format!("Model `{}` does not exist", name)))?;
+
+        Ok(model)
+    }
+
+    /// All models whose name contains `partial`, sorted by name
+    pub fn search<'a>(&'a self, partial: &'a str) -> impl Iterator<Item = &'a ModelInfo> + 'a {
+        let partial = partial.to_lowercase();
+        self.models
+            .values()
+            .filter(move |m| m.name.to_lowercase().contains(&partial))
+    }
+}
+
+impl From<model::Model> for ModelInfo {
+    fn from(model: model::Model) -> Self {
+        Self {
+            name: model.name,
+            provider: model.provider,
+            context_window: model.context_window as u32,
+            pricing: ModelPricing {
+                prompt: model.prompt_price,
+                completion: model.completion_price,
+            },
+            capabilities: ModelCapabilities {
+                vision: model.vision,
+                tools: model.tools,
+            },
+        }
+    }
+}
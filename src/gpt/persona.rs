@@ -1,7 +1,6 @@
 use std::fmt::{Display, Formatter};
-use entities::sea_orm_active_enums::LlmModel;
 use poise::serenity_prelude::{ChannelId, GuildId, Mentionable};
-use sea_orm::{EntityTrait, QueryFilter, ColumnTrait, ActiveEnum, IntoActiveValue, ActiveValue, ModelTrait, ActiveModelTrait, QueryOrder};
+use sea_orm::{EntityTrait, QueryFilter, ColumnTrait, IntoActiveValue, ModelTrait, ActiveModelTrait, QueryOrder};
 use sea_orm::sea_query::OnConflict;
 use entities::{active_persona, persona};
 use crate::Error;
@@ -75,7 +74,8 @@ impl PersonaManager {
         description: Option<String>,
         guild_id: GuildId,
         prompt: String,
-        model: LlmModel,
+        model: String,
+        tools: Vec<String>,
     ) -> Result<(), Error> {
         let existing = self.find_model_by_name(&name, Some(guild_id)).await?;
         if existing.is_some() {
@@ -87,7 +87,8 @@ impl PersonaManager {
             description: description.into_active_value(),
             guild_id: Some(guild_id.to_i64()).into_active_value(),
             prompt: prompt.into_active_value(),
-            model: ActiveValue::Set(model),
+            model: model.into_active_value(),
+            tools: serde_json::to_value(tools)?.into_active_value(),
             builtin: false.into_active_value(),
             ..Default::default()
         };
@@ -104,7 +105,8 @@ impl PersonaManager {
             id: persona.id.into_active_value(),
             name: persona.name().into_active_value(),
             prompt: persona.prompt.into_active_value(),
-            model: ActiveValue::Set(persona.model),
+            model: persona.model.into_active_value(),
+            tools: serde_json::to_value(persona.tools)?.into_active_value(),
             ..Default::default()
         };
 
@@ -195,6 +197,9 @@ impl Display for PersonaMeta {
         if let Some(desc) = &self.persona.description {
             write!(f, "\nDescription: {}", desc)?;
         }
+        if !self.persona.tools.is_empty() {
+            write!(f, "\nTools: {}", self.persona.tools.join(", "))?;
+        }
 
         if self.persona.builtin {
             writeln!(f, "\nPrompt: `[REDACTED]`")?;
@@ -228,8 +233,10 @@ impl Display for PersonaMeta {
 pub struct Persona {
     pub(crate) name: String,
     pub(crate) prompt: String,
-    pub(crate) model: LlmModel,
+    pub(crate) model: String,
     pub(crate) description: Option<String>,
+    /// Names of the [Tool](crate::gpt::tools::Tool)s this persona may call
+    pub(crate) tools: Vec<String>,
     id: i32,
     builtin: bool,
     // pub(super) guild_id: serenity::GuildId,
@@ -241,7 +248,7 @@ impl Persona {
     }
 
     pub fn model(&self) -> String {
-        self.model.to_value()
+        self.model.clone()
     }
 
     pub fn description(&self) -> Option<String> { self.description.as_ref().cloned() }
@@ -255,6 +262,10 @@ impl Persona {
     pub fn is_builtin(&self) -> bool {
         self.builtin
     }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
 }
 
 impl From<persona::Model> for Persona {
@@ -264,6 +275,8 @@ impl From<persona::Model> for Persona {
             description: persona.description,
             prompt: persona.prompt,
             model: persona.model,
+            // Tools are only ever written as a list of names
+            tools: serde_json::from_value(persona.tools).unwrap_or_default(),
             id: persona.id,
             builtin: persona.builtin,
             // guild_id: serenity::GuildId::from_i64(model.guild_id)
@@ -0,0 +1,274 @@
+use openai::chat::{ChatCompletionMessage, ChatCompletionMessageRole};
+use poise::serenity_prelude::{ChannelId, MessageId};
+use sea_orm::{ColumnTrait, EntityTrait, IntoActiveValue, QueryFilter, QueryOrder};
+use entities::conversation_summary;
+use crate::Error;
+use crate::error::InternalError;
+use crate::gpt::backend::ChatBackend;
+use crate::gpt::ChatMessage;
+use crate::gpt::history::{message_tokens, truncate_message};
+use crate::util::{Fromi64, Toi64};
+
+const SUMMARY_PROMPT: &str = "You maintain a running summary of a Discord conversation. \
+Keep track of who said what, any decisions made and open questions. \
+Be concise and never exceed 300 words.";
+const SUMMARY_REQUEST: &str = "Write an updated summary of the conversation so far. \
+Only respond with the summary.";
+
+/// Model-generated summary of a channel's messages up to and including `last_message_id`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversationSummary {
+    pub last_message_id: MessageId,
+    pub summary: String,
+}
+
+impl ConversationSummary {
+    /// Message sent to the model in place of the summarized messages
+    pub fn to_message(&self) -> ChatMessage {
+        ChatCompletionMessage {
+            role: ChatCompletionMessageRole::System,
+            content: Some(format!("Summary of the earlier conversation:\n{}", self.summary)),
+            name: None,
+            function_call: None,
+        }.into()
+    }
+}
+
+impl From<conversation_summary::Model> for ConversationSummary {
+    fn from(model: conversation_summary::Model) -> Self {
+        Self {
+            last_message_id: MessageId::from_i64(model.last_message_id),
+            summary: model.summary,
+        }
+    }
+}
+
+pub struct SummaryManager {
+    db: crate::Database,
+}
+
+impl SummaryManager {
+    pub fn new(db: crate::Database) -> Self {
+        Self { db }
+    }
+
+    /// Get the most recent summary of a channel
+    pub async fn latest(&self, channel_id: ChannelId) -> Result<Option<ConversationSummary>, Error> {
+        let summary = conversation_summary::Entity::find()
+            .filter(conversation_summary::Column::ChannelId.eq(channel_id.to_i64()))
+            .order_by_desc(conversation_summary::Column::LastMessageId)
+            .one(self.db.connection())
+            .await?
+            .map(ConversationSummary::from);
+
+        Ok(summary)
+    }
+
+    /// Save a new summary for a channel, replacing any older summaries
+    pub async fn save(&self, channel_id: ChannelId, summary: &ConversationSummary) -> Result<(), Error> {
+        conversation_summary::Entity::insert(conversation_summary::ActiveModel {
+            channel_id: channel_id.to_i64().into_active_value(),
+            last_message_id: summary.last_message_id.to_i64().into_active_value(),
+            summary: summary.summary.clone().into_active_value(),
+            ..Default::default()
+        })
+        .exec(self.db.connection())
+        .await?;
+
+        conversation_summary::Entity::delete_many()
+            .filter(conversation_summary::Column::ChannelId.eq(channel_id.to_i64()))
+            .filter(conversation_summary::Column::LastMessageId.lt(summary.last_message_id.to_i64()))
+            .exec(self.db.connection())
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// Collects the oldest messages which aren't part of the previous [ConversationSummary] yet, and folds them into it.
+///
+/// Messages must be added oldest first, starting right after the previous summary, so that the new summary
+/// always covers a contiguous run of messages.
+pub struct Summarizer {
+    previous: Option<ConversationSummary>,
+    prompt: ChatMessage,
+    messages: Vec<ChatMessage>,
+    budget: usize,
+    used: usize,
+    full: bool,
+    last_message_id: Option<MessageId>,
+}
+
+impl Summarizer {
+    /// `budget` limits the size of the summarization request itself
+    pub fn new(previous: Option<ConversationSummary>, budget: usize) -> Self {
+        let prompt = match &previous {
+            Some(previous) => format!("{}\n\nSummary so far:\n{}", SUMMARY_PROMPT, previous.summary),
+            None => SUMMARY_PROMPT.to_string(),
+        };
+        let prompt: ChatMessage = ChatCompletionMessage {
+            role: ChatCompletionMessageRole::System,
+            content: Some(prompt),
+            name: None,
+            function_call: None,
+        }.into();
+
+        Self {
+            previous,
+            used: message_tokens(&prompt),
+            prompt,
+            messages: vec![],
+            budget,
+            full: false,
+            last_message_id: None,
+        }
+    }
+
+    /// Messages up to and including this ID are already part of the summary
+    pub fn summarized_until(&self) -> Option<MessageId> {
+        self.previous.as_ref().map(|s| s.last_message_id)
+    }
+
+    /// Add the next newer message which isn't summarized yet.
+    ///
+    /// Returns `false` once no more messages fit in a single summarization request,
+    /// in which case the message isn't part of the summary.
+    pub fn push_newer(&mut self, id: MessageId, mut message: ChatMessage) -> bool {
+        if self.full {
+            return false;
+        }
+
+        // Images aren't worth their cost for a summary
+        message.images.clear();
+
+        let tokens = message_tokens(&message);
+        if self.used + tokens > self.budget {
+            self.full = true;
+            if !self.messages.is_empty() {
+                return false;
+            }
+            // Make progress even if a single message is larger than the whole budget
+            message = truncate_message(message, self.budget.saturating_sub(self.used));
+        }
+
+        self.used += message_tokens(&message);
+        self.messages.push(message);
+        self.last_message_id = Some(id);
+        !self.full
+    }
+
+    /// Whether any new messages need to be summarized
+    pub fn has_dropped(&self) -> bool {
+        self.last_message_id.is_some()
+    }
+
+    /// Generate a summary covering the previous summary and every message added to this [Summarizer]
+    pub async fn summarize(self, backend: &dyn ChatBackend, model: &str) -> Result<Option<ConversationSummary>, Error> {
+        let Some(last_message_id) = self.last_message_id else {
+            return Ok(self.previous);
+        };
+
+        let mut messages = std::iter::once(self.prompt)
+            .chain(self.messages)
+            .collect::<Vec<_>>();
+        messages.push(ChatCompletionMessage {
+            role: ChatCompletionMessageRole::User,
+            content: Some(SUMMARY_REQUEST.to_string()),
+            name: None,
+            function_call: None,
+        }.into());
+
+        let summary = backend
+            .completion(model, &messages)
+            .await?
+            .message
+            .content
+            .filter(|c| !c.trim().is_empty())
+            .ok_or_else(|| InternalError::backend("Summary was empty"))?;
+
+        Ok(Some(ConversationSummary {
+            last_message_id,
+            summary,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::ScriptedBackend;
+
+    fn user_message(content: &str) -> ChatMessage {
+        ChatCompletionMessage {
+            role: ChatCompletionMessageRole::User,
+            content: Some(content.to_string()),
+            name: Some("Alice".to_string()),
+            function_call: None,
+        }.into()
+    }
+
+    #[tokio::test]
+    async fn summarize_without_dropped_messages_keeps_previous() {
+        let backend = ScriptedBackend::new(Vec::<String>::new());
+        let previous = ConversationSummary {
+            last_message_id: MessageId::new(10),
+            summary: "Alice said hi".to_string(),
+        };
+
+        let summarizer = Summarizer::new(Some(previous.clone()), 1000);
+        assert!(!summarizer.has_dropped());
+
+        let summary = summarizer.summarize(&backend, "test-model").await.unwrap();
+        assert_eq!(summary, Some(previous));
+        assert!(backend.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn summarize_folds_in_previous_summary() {
+        let backend = ScriptedBackend::new(["Alice said hi, then asked about Rust"]);
+        let previous = ConversationSummary {
+            last_message_id: MessageId::new(10),
+            summary: "Alice said hi".to_string(),
+        };
+
+        let mut summarizer = Summarizer::new(Some(previous), 1000);
+        assert_eq!(summarizer.summarized_until(), Some(MessageId::new(10)));
+        assert!(summarizer.push_newer(MessageId::new(11), user_message("Question time")));
+        assert!(summarizer.push_newer(MessageId::new(12), user_message("Do you like Rust?")));
+
+        let summary = summarizer.summarize(&backend, "test-model").await.unwrap().unwrap();
+        assert_eq!(summary.last_message_id, MessageId::new(12));
+        assert_eq!(summary.summary, "Alice said hi, then asked about Rust");
+
+        let request = &backend.requests()[0];
+        assert!(request[0].content.as_ref().unwrap().contains("Alice said hi"));
+        assert_eq!(request[1].content.as_deref(), Some("Question time"));
+        assert_eq!(request[2].content.as_deref(), Some("Do you like Rust?"));
+        assert_eq!(request[3].content.as_deref(), Some(SUMMARY_REQUEST));
+    }
+
+    #[tokio::test]
+    async fn summary_only_covers_messages_which_fit() {
+        let backend = ScriptedBackend::new(["Alice asked two questions"]);
+        let first = user_message("First question");
+        let second = user_message("Second question");
+        let budget = message_tokens(&Summarizer::new(None, 0).prompt)
+            + message_tokens(&first)
+            + message_tokens(&second);
+
+        let mut summarizer = Summarizer::new(None, budget);
+        assert!(summarizer.push_newer(MessageId::new(11), first));
+        assert!(summarizer.push_newer(MessageId::new(12), second));
+        assert!(!summarizer.push_newer(MessageId::new(13), user_message("Third question")));
+        assert!(!summarizer.push_newer(MessageId::new(14), user_message("Hi")));
+
+        let summary = summarizer.summarize(&backend, "test-model").await.unwrap().unwrap();
+        // The overflowing messages are left for the next summary, rather than being claimed by this one
+        assert_eq!(summary.last_message_id, MessageId::new(12));
+
+        let request = &backend.requests()[0];
+        assert_eq!(request.len(), 4);
+        assert_eq!(request[1].content.as_deref(), Some("First question"));
+        assert_eq!(request[2].content.as_deref(), Some("Second question"));
+    }
+}
@@ -0,0 +1,473 @@
+use std::fmt::Write as _;
+use poise::serenity_prelude as serenity;
+use serenity::{ChannelId, ChannelType, Guild, GuildChannel, GuildId, Member, Message, MessageId, Permissions, UserId};
+use serde::Deserialize;
+use crate::Error;
+use crate::error::UserError;
+use crate::gpt::tools::{parse_arguments, Tool, ToolContext};
+
+/// Discord doesn't allow fetching more than 100 messages at a time
+const MAX_SEARCH_MESSAGES: u8 = 100;
+const DEFAULT_SEARCH_MESSAGES: u8 = 50;
+/// Keeps tool results from eating the whole context window in large servers
+const MAX_LISTED_MEMBERS: usize = 50;
+
+/// Parse a Discord ID, also accepting mentions such as `<#123>` or `<@!123>`
+fn parse_id<T: From<u64>>(id: &str, kind: &str) -> Result<T, Error> {
+    let parsed = id.trim()
+        .trim_start_matches(['<', '@', '!', '#', '&'])
+        .trim_end_matches('>')
+        .parse::<u64>()
+        .ok()
+        .filter(|id| *id != 0)
+        .map(T::from)
+        .ok_or_else(|| UserError::invalid_input(format!("`{}` is not a valid {} ID", id, kind)))?;
+
+    Ok(parsed)
+}
+
+fn require_guild(ctx: &ToolContext<'_>) -> Result<GuildId, Error> {
+    let guild_id = ctx.guild_id
+        .ok_or_else(|| UserError::invalid_input("This tool can only be used in a server"))?;
+
+    Ok(guild_id)
+}
+
+/// Find a channel or thread in `guild`, resolving threads to their parent since that's where permissions are set.
+/// Also returns the kind of thread, if `channel_id` is one
+fn permission_channel(guild: &Guild, channel_id: ChannelId) -> Option<(&GuildChannel, Option<ChannelType>)> {
+    let channel = guild.channels
+        .get(&channel_id)
+        .or_else(|| guild.threads.iter().find(|t| t.id == channel_id))?;
+
+    match channel.thread_metadata {
+        Some(_) => channel.parent_id
+            .and_then(|parent| guild.channels.get(&parent))
+            .map(|parent| (parent, Some(channel.kind))),
+        None => Some((channel, None)),
+    }
+}
+
+/// Whether reading a thread also requires being a member of it, given the permissions in its parent channel.
+/// Private threads are only visible to their members and those who can manage threads
+fn requires_thread_membership(thread_kind: Option<ChannelType>, permissions: Permissions) -> bool {
+    thread_kind == Some(ChannelType::PrivateThread) && !permissions.manage_threads()
+}
+
+/// Ensure the user who triggered the tool call can read the message history of `channel_id`,
+/// so tools never reveal anything the user couldn't see for themselves
+async fn ensure_readable(ctx: &ToolContext<'_>, channel_id: ChannelId) -> Result<(), Error> {
+    let not_found = || UserError::not_found(format!("Channel {} does not exist", channel_id));
+
+    let Some(guild_id) = ctx.guild_id else {
+        // The only channel visible from a DM is the DM itself
+        return if channel_id == ctx.message_channel_id {
+            Ok(())
+        } else {
+            Err(not_found().into())
+        };
+    };
+
+    let serenity_ctx = ctx.framework.serenity_context;
+    let member = guild_id.member(serenity_ctx, ctx.user_id).await?;
+
+    let (permissions, thread_kind) = {
+        let guild = serenity_ctx.cache
+            .guild(guild_id)
+            .ok_or_else(not_found)?;
+        let (channel, thread_kind) = permission_channel(&guild, channel_id).ok_or_else(not_found)?;
+        (guild.user_permissions_in(channel, &member), thread_kind)
+    };
+
+    let access_denied = || UserError::access_denied(format!(
+        "{} can't read messages in that channel",
+        member.display_name()
+    ));
+
+    if !permissions.view_channel() || !permissions.read_message_history() {
+        return Err(access_denied().into());
+    }
+
+    if requires_thread_membership(thread_kind, permissions) {
+        let is_member = channel_id
+            .get_thread_members(&serenity_ctx.http)
+            .await?
+            .iter()
+            .any(|thread_member| thread_member.user_id == ctx.user_id);
+        if !is_member {
+            return Err(access_denied().into());
+        }
+    }
+
+    Ok(())
+}
+
+fn format_message(message: &Message) -> String {
+    format!(
+        "[{}] {} (message {} in channel {}): {}",
+        message.timestamp,
+        message.author.name,
+        message.id,
+        message.channel_id,
+        message.content,
+    )
+}
+
+/// Look up a member's display name and roles
+pub struct GetMember;
+
+#[derive(Debug, Deserialize)]
+struct GetMemberArgs {
+    user: String,
+}
+
+impl GetMember {
+    async fn find_member(ctx: &ToolContext<'_>, guild_id: GuildId, user: &str) -> Result<Member, Error> {
+        let serenity_ctx = ctx.framework.serenity_context;
+        if let Ok(user_id) = parse_id::<UserId>(user, "user") {
+            return Ok(guild_id.member(serenity_ctx, user_id).await?);
+        }
+
+        let candidates = guild_id
+            .search_members(&serenity_ctx.http, user, None)
+            .await?;
+
+        // Prefer an exact match since search only matches on prefixes
+        let index = candidates
+            .iter()
+            .position(|m| m.display_name().eq_ignore_ascii_case(user) || m.user.name.eq_ignore_ascii_case(user))
+            .unwrap_or(0);
+
+        let member = candidates
+            .into_iter()
+            .nth(index)
+            .ok_or_else(|| UserError::not_found(format!("No member named `{}`", user)))?;
+
+        Ok(member)
+    }
+}
+
+#[poise::async_trait]
+impl Tool for GetMember {
+    fn name(&self) -> &str {
+        "get_member"
+    }
+
+    fn description(&self) -> &str {
+        "Look up a member of this server, including their display name, username and roles"
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "user": {
+                    "type": "string",
+                    "description": "Display name, username, mention or ID of the member",
+                },
+            },
+            "required": ["user"],
+        })
+    }
+
+    async fn execute(&self, ctx: &ToolContext<'_>, arguments: serde_json::Value) -> Result<String, Error> {
+        let args: GetMemberArgs = parse_arguments(arguments)?;
+        let guild_id = require_guild(ctx)?;
+        let member = Self::find_member(ctx, guild_id, &args.user).await?;
+
+        let roles = ctx.framework.serenity_context.cache
+            .guild(guild_id)
+            .map(|guild| {
+                let mut roles = member.roles
+                    .iter()
+                    .filter_map(|id| guild.roles.get(id))
+                    .collect::<Vec<_>>();
+                // Highest role first, as shown in Discord
+                roles.sort_by(|lhs, rhs| rhs.cmp(lhs));
+                roles.into_iter().map(|r| r.name.to_string()).collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+
+        let mut result = format!(
+            "Display name: {}\nUsername: {}\nID: {}",
+            member.display_name(),
+            member.user.name,
+            member.user.id,
+        );
+        if let Some(joined_at) = member.joined_at {
+            write!(result, "\nJoined: {}", joined_at)?;
+        }
+        if roles.is_empty() {
+            write!(result, "\nRoles: none")?;
+        } else {
+            write!(result, "\nRoles: {}", roles.join(", "))?;
+        }
+
+        Ok(result)
+    }
+}
+
+/// List the members with a given role
+pub struct GetRoleMembers;
+
+#[derive(Debug, Deserialize)]
+struct GetRoleMembersArgs {
+    role: String,
+}
+
+#[poise::async_trait]
+impl Tool for GetRoleMembers {
+    fn name(&self) -> &str {
+        "get_role_members"
+    }
+
+    fn description(&self) -> &str {
+        "List the members of this server who have a role. Only includes members who have been active recently"
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "role": {
+                    "type": "string",
+                    "description": "Name, mention or ID of the role",
+                },
+            },
+            "required": ["role"],
+        })
+    }
+
+    async fn execute(&self, ctx: &ToolContext<'_>, arguments: serde_json::Value) -> Result<String, Error> {
+        let args: GetRoleMembersArgs = parse_arguments(arguments)?;
+        let guild_id = require_guild(ctx)?;
+
+        let guild = ctx.framework.serenity_context.cache
+            .guild(guild_id)
+            .ok_or_else(|| UserError::not_found("Server is unavailable"))?;
+
+        let role_id = parse_id(&args.role, "role").ok();
+        let role = guild.roles
+            .iter()
+            .find(|r| Some(r.id) == role_id || r.name.eq_ignore_ascii_case(&args.role))
+            .ok_or_else(|| UserError::not_found(format!("No role named `{}`", args.role)))?;
+
+        let members = guild.members
+            .iter()
+            .filter(|m| m.roles.contains(&role.id))
+            .map(|m| m.display_name().to_string())
+            .take(MAX_LISTED_MEMBERS)
+            .collect::<Vec<_>>();
+
+        if members.is_empty() {
+            return Ok(format!("No known members have the {} role", role.name));
+        }
+
+        Ok(format!("Members with the {} role: {}", role.name, members.join(", ")))
+    }
+}
+
+/// List the channels visible to the user
+pub struct ListChannels;
+
+#[poise::async_trait]
+impl Tool for ListChannels {
+    fn name(&self) -> &str {
+        "list_channels"
+    }
+
+    fn description(&self) -> &str {
+        "List the text channels in this server, with their IDs and topics"
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {},
+        })
+    }
+
+    async fn execute(&self, ctx: &ToolContext<'_>, _arguments: serde_json::Value) -> Result<String, Error> {
+        let guild_id = require_guild(ctx)?;
+        let serenity_ctx = ctx.framework.serenity_context;
+        let member = guild_id.member(serenity_ctx, ctx.user_id).await?;
+
+        let guild = serenity_ctx.cache
+            .guild(guild_id)
+            .ok_or_else(|| UserError::not_found("Server is unavailable"))?;
+
+        let mut channels = guild.channels
+            .iter()
+            .filter(|c| matches!(c.kind, ChannelType::Text | ChannelType::News | ChannelType::Forum))
+            .filter(|c| guild.user_permissions_in(c, &member).view_channel())
+            .collect::<Vec<_>>();
+        channels.sort_by_key(|c| c.position);
+
+        let mut result = String::new();
+        for channel in channels {
+            write!(result, "#{} ({})", channel.name, channel.id)?;
+            if let Some(topic) = &channel.topic {
+                write!(result, ": {}", topic)?;
+            }
+            writeln!(result)?;
+        }
+
+        Ok(result)
+    }
+}
+
+/// Fetch a single message, eg one that was linked or replied to
+pub struct GetMessage;
+
+#[derive(Debug, Deserialize)]
+struct GetMessageArgs {
+    message_id: String,
+    channel_id: Option<String>,
+}
+
+#[poise::async_trait]
+impl Tool for GetMessage {
+    fn name(&self) -> &str {
+        "get_message"
+    }
+
+    fn description(&self) -> &str {
+        "Fetch a message by its ID"
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "message_id": {
+                    "type": "string",
+                    "description": "ID of the message",
+                },
+                "channel_id": {
+                    "type": "string",
+                    "description": "ID of the channel containing the message. Defaults to the current channel",
+                },
+            },
+            "required": ["message_id"],
+        })
+    }
+
+    async fn execute(&self, ctx: &ToolContext<'_>, arguments: serde_json::Value) -> Result<String, Error> {
+        let args: GetMessageArgs = parse_arguments(arguments)?;
+        let message_id: MessageId = parse_id(&args.message_id, "message")?;
+        let channel_id = match &args.channel_id {
+            Some(channel_id) => parse_id(channel_id, "channel")?,
+            None => ctx.message_channel_id,
+        };
+
+        ensure_readable(ctx, channel_id).await?;
+
+        let message = ctx.framework.serenity_context.http
+            .get_message(channel_id, message_id)
+            .await
+            .map_err(|_| UserError::not_found(format!("Message {} does not exist", message_id)))?;
+
+        Ok(format_message(&message))
+    }
+}
+
+/// Search recent messages in a channel for a keyword
+pub struct SearchMessages;
+
+#[derive(Debug, Deserialize)]
+struct SearchMessagesArgs {
+    keyword: String,
+    channel_id: Option<String>,
+    limit: Option<u8>,
+}
+
+#[poise::async_trait]
+impl Tool for SearchMessages {
+    fn name(&self) -> &str {
+        "search_messages"
+    }
+
+    fn description(&self) -> &str {
+        "Search the most recent messages in a channel for a keyword (case insensitive)"
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "keyword": {
+                    "type": "string",
+                    "description": "Text to look for",
+                },
+                "channel_id": {
+                    "type": "string",
+                    "description": "ID of the channel to search. Defaults to the current channel",
+                },
+                "limit": {
+                    "type": "integer",
+                    "description": "How many recent messages to search",
+                    "minimum": 1,
+                    "maximum": MAX_SEARCH_MESSAGES,
+                    "default": DEFAULT_SEARCH_MESSAGES,
+                },
+            },
+            "required": ["keyword"],
+        })
+    }
+
+    async fn execute(&self, ctx: &ToolContext<'_>, arguments: serde_json::Value) -> Result<String, Error> {
+        let args: SearchMessagesArgs = parse_arguments(arguments)?;
+        let channel_id: ChannelId = match &args.channel_id {
+            Some(channel_id) => parse_id(channel_id, "channel")?,
+            None => ctx.message_channel_id,
+        };
+        let limit = args.limit
+            .unwrap_or(DEFAULT_SEARCH_MESSAGES)
+            .clamp(1, MAX_SEARCH_MESSAGES);
+
+        ensure_readable(ctx, channel_id).await?;
+
+        let keyword = args.keyword.to_lowercase();
+        let matches = channel_id
+            .messages(ctx.framework.serenity_context, serenity::GetMessages::default().limit(limit))
+            .await?
+            .iter()
+            .rev() // oldest first
+            .filter(|m| m.content.to_lowercase().contains(&keyword))
+            .map(format_message)
+            .collect::<Vec<_>>();
+
+        if matches.is_empty() {
+            return Ok(format!("No messages containing `{}` in the last {} messages", args.keyword, limit));
+        }
+
+        Ok(matches.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_id_accepts_mentions() {
+        assert_eq!(parse_id::<ChannelId>("<#1234>", "channel").unwrap(), ChannelId::new(1234));
+        assert_eq!(parse_id::<UserId>("<@!42>", "user").unwrap(), UserId::new(42));
+        assert_eq!(parse_id::<UserId>(" 42 ", "user").unwrap(), UserId::new(42));
+        assert!(parse_id::<UserId>("Alice", "user").is_err());
+        assert!(parse_id::<UserId>("0", "user").is_err());
+    }
+
+    #[test]
+    fn private_threads_require_membership() {
+        let readable = Permissions::VIEW_CHANNEL | Permissions::READ_MESSAGE_HISTORY;
+
+        assert!(requires_thread_membership(Some(ChannelType::PrivateThread), readable));
+        assert!(!requires_thread_membership(
+            Some(ChannelType::PrivateThread),
+            readable | Permissions::MANAGE_THREADS,
+        ));
+        assert!(!requires_thread_membership(Some(ChannelType::PublicThread), readable));
+        assert!(!requires_thread_membership(None, readable));
+    }
+}
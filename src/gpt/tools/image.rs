@@ -0,0 +1,66 @@
+use poise::serenity_prelude as serenity;
+use serde::Deserialize;
+use crate::Error;
+use crate::error::UserError;
+use crate::gpt::tools::{parse_arguments, Tool, ToolContext};
+use crate::permissions::Permission;
+
+/// Generates an image and posts it in the channel the conversation is happening in
+pub struct GenerateImage;
+
+#[derive(Deserialize)]
+struct GenerateImageArgs {
+    prompt: String,
+}
+
+#[poise::async_trait]
+impl Tool for GenerateImage {
+    fn name(&self) -> &str {
+        "generate_image"
+    }
+
+    fn description(&self) -> &str {
+        "Generate an image from a detailed description and post it in the current channel"
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "prompt": {
+                    "type": "string",
+                    "description": "Detailed description of the image to generate",
+                },
+            },
+            "required": ["prompt"],
+        })
+    }
+
+    async fn execute(&self, ctx: &ToolContext<'_>, arguments: serde_json::Value) -> Result<String, Error> {
+        let args: GenerateImageArgs = parse_arguments(arguments)?;
+        let data = ctx.framework.user_data();
+
+        let generator = data.image_generator
+            .as_ref()
+            .ok_or_else(|| UserError::not_found("Image generation is not available"))?;
+
+        data.permissions_manager
+            .enforce(ctx.framework, ctx.user_id, ctx.channel_id, ctx.guild_id, Permission::GenerateImage)
+            .await?;
+
+        let image = generator
+            .generate(&data, ctx.user_id, ctx.channel_id, ctx.guild_id, &args.prompt)
+            .await?;
+
+        ctx.message_channel_id
+            .send_message(
+                &ctx.framework.serenity_context.http,
+                serenity::CreateMessage::default()
+                    .add_file(serenity::CreateAttachment::bytes(image.data, "image.png")),
+            )
+            .await?;
+
+        let prompt = image.revised_prompt.unwrap_or(args.prompt);
+        Ok(format!("The image has been posted in the channel. It was generated from: {}", prompt))
+    }
+}
@@ -0,0 +1,218 @@
+mod discord;
+mod image;
+mod time;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use openai::chat::ChatCompletionFunctionCall;
+use poise::serenity_prelude::{ChannelId, GuildId, UserId};
+use serde::de::DeserializeOwned;
+use tracing::error;
+use crate::{Data, Error};
+use crate::error::{FaultyBotError, UserError};
+use crate::gpt::backend::ToolDefinition;
+use crate::gpt::Persona;
+use crate::permissions::Permission;
+
+pub use discord::{GetMember, GetMessage, GetRoleMembers, ListChannels, SearchMessages};
+pub use image::GenerateImage;
+pub use time::CurrentTime;
+
+/// A function which models can call while generating a response
+#[poise::async_trait]
+pub trait Tool: Send + Sync {
+    /// Unique name the model uses to call this tool
+    fn name(&self) -> &str;
+
+    /// Explains to the model what this tool does and when to use it
+    fn description(&self) -> &str;
+
+    /// JSON schema of the arguments passed to [Tool::execute]
+    fn parameters(&self) -> serde_json::Value;
+
+    /// Run the tool, returning the result to send back to the model.
+    ///
+    /// [UserError]s are shown to the model so it can explain or correct its mistake.
+    async fn execute(&self, ctx: &ToolContext<'_>, arguments: serde_json::Value) -> Result<String, Error>;
+
+    fn definition(&self) -> ToolDefinition {
+        ToolDefinition {
+            name: self.name().to_string(),
+            description: self.description().to_string(),
+            parameters: self.parameters(),
+        }
+    }
+}
+
+/// Who triggered a tool call and where
+pub struct ToolContext<'a> {
+    pub framework: poise::FrameworkContext<'a, Data, Error>,
+    pub user_id: UserId,
+    /// Channel used for permission checks. For threads this is the parent channel
+    pub channel_id: ChannelId,
+    /// Channel the triggering message was actually sent in
+    pub message_channel_id: ChannelId,
+    pub guild_id: Option<GuildId>,
+}
+
+/// Parse the arguments of a tool call, reporting mistakes back to the model
+pub(crate) fn parse_arguments<T: DeserializeOwned>(arguments: serde_json::Value) -> Result<T, Error> {
+    let arguments = serde_json::from_value(arguments)
+        .map_err(|err| UserError::invalid_input(format!("Invalid arguments: {}", err)))?;
+
+    Ok(arguments)
+}
+
+/// Executes the tool calls made by a model during a [Chat](crate::gpt::Chat)
+#[poise::async_trait]
+pub trait ToolRunner: Send + Sync {
+    /// Definitions of every tool the model may call
+    fn definitions(&self) -> Vec<ToolDefinition>;
+
+    /// Run a tool call and produce the result for the model. Failures are reported in the result.
+    async fn call(&self, call: &ChatCompletionFunctionCall) -> String;
+}
+
+/// The tools enabled for a persona, with access checked against the user who triggered the chat
+pub struct EnabledTools<'a> {
+    ctx: ToolContext<'a>,
+    tools: Vec<Arc<dyn Tool>>,
+}
+
+impl<'a> EnabledTools<'a> {
+    pub fn new(ctx: ToolContext<'a>, tools: Vec<Arc<dyn Tool>>) -> Self {
+        Self { ctx, tools }
+    }
+
+    async fn try_call(&self, tool: &dyn Tool, arguments: &str) -> Result<String, Error> {
+        self.ctx.framework
+            .user_data()
+            .permissions_manager
+            .enforce(
+                self.ctx.framework,
+                self.ctx.user_id,
+                self.ctx.channel_id,
+                self.ctx.guild_id,
+                Permission::UseTool(Some(tool.name().to_string())),
+            )
+            .await?;
+
+        let arguments = match arguments.trim() {
+            "" => serde_json::Value::Object(Default::default()),
+            arguments => serde_json::from_str(arguments)
+                .map_err(|err| UserError::invalid_input(format!("Arguments are not valid JSON: {}", err)))?,
+        };
+
+        tool.execute(&self.ctx, arguments).await
+    }
+}
+
+#[poise::async_trait]
+impl ToolRunner for EnabledTools<'_> {
+    fn definitions(&self) -> Vec<ToolDefinition> {
+        self.tools.iter().map(|t| t.definition()).collect()
+    }
+
+    async fn call(&self, call: &ChatCompletionFunctionCall) -> String {
+        let Some(tool) = self.tools.iter().find(|t| t.name() == call.name) else {
+            return format!("Error: tool `{}` does not exist", call.name);
+        };
+
+        match self.try_call(tool.as_ref(), &call.arguments).await {
+            Ok(result) => result,
+            Err(FaultyBotError::User(err)) => format!("Error: {}", err),
+            Err(err) => {
+                error!("Tool `{}` failed: {}", call.name, err);
+                "Error: the tool failed unexpectedly".to_string()
+            }
+        }
+    }
+}
+
+/// All tools which personas can opt into
+pub struct ToolRegistry {
+    tools: BTreeMap<String, Arc<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    /// Create a registry with all the built-in tools
+    pub fn new() -> Self {
+        let mut registry = Self { tools: BTreeMap::new() };
+        registry.register(CurrentTime);
+        registry.register(GetMember);
+        registry.register(GetRoleMembers);
+        registry.register(ListChannels);
+        registry.register(GetMessage);
+        registry.register(SearchMessages);
+        registry.register(GenerateImage);
+        registry
+    }
+
+    pub fn register(&mut self, tool: impl Tool + 'static) {
+        self.tools.insert(tool.name().to_string(), Arc::new(tool));
+    }
+
+    pub fn get(&self, name: &str) -> Result<Arc<dyn Tool>, Error> {
+        let tool = self.tools
+            .get(name)
+            .cloned()
+            .ok_or_else(|| UserError::not_found(format!("Tool `{}` does not exist", name)))?;
+
+        Ok(tool)
+    }
+
+    /// Names of all the registered tools, sorted
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.tools.keys().map(String::as_str)
+    }
+
+    /// Tools the persona has opted into. Tools which no longer exist are skipped
+    pub fn for_persona(&self, persona: &Persona) -> Vec<Arc<dyn Tool>> {
+        persona.tools
+            .iter()
+            .filter_map(|name| self.tools.get(name).cloned())
+            .collect()
+    }
+}
+
+impl Default for ToolRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn registry_contains_builtin_tools() {
+        let registry = ToolRegistry::new();
+
+        assert!(registry.names().any(|n| n == "current_time"));
+        assert_eq!(registry.get("current_time").unwrap().name(), "current_time");
+        assert!(registry.get("does_not_exist").is_err());
+    }
+
+    #[test]
+    fn builtin_tools_have_object_schemas() {
+        let registry = ToolRegistry::new();
+
+        for name in registry.names() {
+            let definition = registry.get(name).unwrap().definition();
+            assert_eq!(definition.parameters["type"], "object", "{}", name);
+        }
+    }
+
+    #[test]
+    fn parse_arguments_reports_invalid_input() {
+        #[derive(Debug, serde::Deserialize)]
+        struct Args {
+            #[allow(dead_code)]
+            keyword: String,
+        }
+
+        let err = parse_arguments::<Args>(serde_json::json!({})).unwrap_err();
+        assert!(matches!(err, FaultyBotError::User(UserError::InvalidInput { .. })));
+    }
+}
@@ -0,0 +1,27 @@
+use crate::Error;
+use crate::gpt::tools::{Tool, ToolContext};
+
+/// Lets the model know the current date, which it otherwise can't know
+pub struct CurrentTime;
+
+#[poise::async_trait]
+impl Tool for CurrentTime {
+    fn name(&self) -> &str {
+        "current_time"
+    }
+
+    fn description(&self) -> &str {
+        "Get the current date and time in UTC"
+    }
+
+    fn parameters(&self) -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {},
+        })
+    }
+
+    async fn execute(&self, _ctx: &ToolContext<'_>, _arguments: serde_json::Value) -> Result<String, Error> {
+        Ok(chrono::Utc::now().to_rfc2822())
+    }
+}
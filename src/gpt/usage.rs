@@ -0,0 +1,408 @@
+use std::collections::HashMap;
+use chrono::{DateTime, Datelike, Months, NaiveDate, Utc};
+use poise::serenity_prelude::{ChannelId, GuildId, UserId};
+use sea_orm::sea_query::{Alias, Expr, Func, OnConflict, SimpleExpr};
+use sea_orm::{ColumnTrait, EntityTrait, FromQueryResult, IntoActiveValue, Order, QueryFilter, QueryOrder, QuerySelect, Select};
+use entities::{persona, token_usage};
+use crate::Error;
+use crate::error::UserError;
+use crate::gpt::ModelInfo;
+use crate::gpt::backend::Usage;
+use crate::settings::config::ModelPricing;
+use crate::settings::manager::SettingsManager;
+use crate::settings::{merge_strategies, SettingsContext, SettingsValue};
+use crate::util::{Fromi64, Toi64};
+
+/// Every quota that can be set. Each is read from its own setting, see [Quota::key]
+const QUOTAS: [Quota; 8] = [
+    Quota { scope: QuotaScope::User, period: QuotaPeriod::Daily, unit: QuotaUnit::Tokens },
+    Quota { scope: QuotaScope::User, period: QuotaPeriod::Daily, unit: QuotaUnit::Cost },
+    Quota { scope: QuotaScope::User, period: QuotaPeriod::Monthly, unit: QuotaUnit::Tokens },
+    Quota { scope: QuotaScope::User, period: QuotaPeriod::Monthly, unit: QuotaUnit::Cost },
+    Quota { scope: QuotaScope::Guild, period: QuotaPeriod::Daily, unit: QuotaUnit::Tokens },
+    Quota { scope: QuotaScope::Guild, period: QuotaPeriod::Daily, unit: QuotaUnit::Cost },
+    Quota { scope: QuotaScope::Guild, period: QuotaPeriod::Monthly, unit: QuotaUnit::Tokens },
+    Quota { scope: QuotaScope::Guild, period: QuotaPeriod::Monthly, unit: QuotaUnit::Cost },
+];
+
+/// Requests, tokens and cost added up over some period
+#[derive(Debug, Clone, Copy, Default, PartialEq, FromQueryResult)]
+pub struct UsageTotals {
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    /// Cost in USD
+    pub cost: f64,
+}
+
+impl UsageTotals {
+    pub fn tokens(&self) -> i64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+/// Usage of a model through one persona, or a persona which has since been deleted
+#[derive(Debug, Clone, PartialEq, FromQueryResult)]
+pub struct PersonaUsage {
+    pub persona: Option<String>,
+    pub model: String,
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub cost: f64,
+}
+
+impl PersonaUsage {
+    pub fn totals(&self) -> UsageTotals {
+        UsageTotals {
+            requests: self.requests,
+            prompt_tokens: self.prompt_tokens,
+            completion_tokens: self.completion_tokens,
+            cost: self.cost,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, FromQueryResult)]
+pub struct UserUsage {
+    pub user_id: i64,
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+    pub cost: f64,
+}
+
+impl UserUsage {
+    pub fn user_id(&self) -> UserId {
+        UserId::from_i64(self.user_id)
+    }
+
+    pub fn totals(&self) -> UsageTotals {
+        UsageTotals {
+            requests: self.requests,
+            prompt_tokens: self.prompt_tokens,
+            completion_tokens: self.completion_tokens,
+            cost: self.cost,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum QuotaPeriod {
+    Daily,
+    Monthly,
+}
+
+impl QuotaPeriod {
+    /// First day counted towards the quota on `today`
+    pub fn start(&self, today: NaiveDate) -> NaiveDate {
+        match self {
+            QuotaPeriod::Daily => today,
+            QuotaPeriod::Monthly => today.with_day(1).unwrap(),
+        }
+    }
+
+    /// When usage counted on `today` stops counting towards the quota
+    pub fn reset(&self, today: NaiveDate) -> DateTime<Utc> {
+        let next = match self {
+            QuotaPeriod::Daily => today.succ_opt().unwrap(),
+            QuotaPeriod::Monthly => self.start(today) + Months::new(1),
+        };
+        next.and_hms_opt(0, 0, 0).unwrap().and_utc()
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            QuotaPeriod::Daily => "daily",
+            QuotaPeriod::Monthly => "monthly",
+        }
+    }
+}
+
+/// Whose usage a quota limits
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+enum QuotaScope {
+    /// Each user, in a server or their DMs
+    User,
+    /// Everyone in a server combined
+    Guild,
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+enum QuotaUnit {
+    Tokens,
+    /// USD, priced using the model's configured pricing
+    Cost,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Quota {
+    scope: QuotaScope,
+    period: QuotaPeriod,
+    unit: QuotaUnit,
+}
+
+impl Quota {
+    /// Setting the quota's limit is read from, eg `quota.user_daily_tokens`
+    fn key(&self) -> String {
+        let scope = match self.scope {
+            QuotaScope::User => "user",
+            QuotaScope::Guild => "guild",
+        };
+        let unit = match self.unit {
+            QuotaUnit::Tokens => "tokens",
+            QuotaUnit::Cost => "cost",
+        };
+        format!("quota.{}_{}_{}", scope, self.period.name(), unit)
+    }
+
+    fn describe(&self) -> String {
+        let unit = match self.unit {
+            QuotaUnit::Tokens => "token",
+            QuotaUnit::Cost => "spending",
+        };
+        match self.scope {
+            QuotaScope::User => format!("{} {} quota", self.period.name(), unit),
+            QuotaScope::Guild => format!("{} {} quota for this server", self.period.name(), unit),
+        }
+    }
+
+    fn used(&self, totals: &UsageTotals) -> f64 {
+        match self.unit {
+            QuotaUnit::Tokens => totals.tokens() as f64,
+            QuotaUnit::Cost => totals.cost,
+        }
+    }
+}
+
+/// Cost in USD of a request to a model with `pricing`
+fn cost(pricing: &ModelPricing, usage: Usage) -> f64 {
+    let prompt = usage.prompt_tokens as f64 * pricing.prompt;
+    let completion = usage.completion_tokens as f64 * pricing.completion;
+    (prompt + completion) / 1_000_000.0
+}
+
+/// `SUM` of a column, cast back to its own type since Postgres widens sums of integers
+fn sum(column: token_usage::Column, sql_type: &str) -> SimpleExpr {
+    Expr::expr(Func::coalesce([Expr::col((token_usage::Entity, column)).sum(), Expr::val(0).into()]))
+        .cast_as(Alias::new(sql_type))
+}
+
+/// Select the [UsageTotals] columns from usage since `since` in a guild, or in DMs if `guild_id` is `None`
+fn select_totals(guild_id: Option<GuildId>, since: NaiveDate) -> Select<token_usage::Entity> {
+    token_usage::Entity::find()
+        .select_only()
+        .column_as(sum(token_usage::Column::Requests, "bigint"), "requests")
+        .column_as(sum(token_usage::Column::PromptTokens, "bigint"), "prompt_tokens")
+        .column_as(sum(token_usage::Column::CompletionTokens, "bigint"), "completion_tokens")
+        .column_as(sum(token_usage::Column::Cost, "double precision"), "cost")
+        .filter(token_usage::Column::Day.gte(since))
+        .filter(match guild_id {
+            Some(guild_id) => token_usage::Column::GuildId.eq(guild_id.to_i64()),
+            None => token_usage::Column::GuildId.is_null(),
+        })
+}
+
+/// Tracks how many tokens are used per guild, user and model, and enforces quotas on them
+pub struct UsageManager {
+    db: crate::Database,
+}
+
+impl UsageManager {
+    pub fn new(db: crate::Database) -> Self {
+        Self { db }
+    }
+
+    /// Add a request's usage to today's totals
+    pub async fn record(
+        &self,
+        guild_id: Option<GuildId>,
+        user_id: UserId,
+        persona_id: i32,
+        model: &ModelInfo,
+        usage: Usage,
+    ) -> Result<(), Error> {
+        let cost = cost(&model.pricing, usage);
+        let prompt_tokens = usage.prompt_tokens as i64;
+        let completion_tokens = usage.completion_tokens as i64;
+
+        let add = |column: token_usage::Column, value: SimpleExpr| {
+            Expr::col((token_usage::Entity, column)).add(value)
+        };
+
+        token_usage::Entity::insert(token_usage::ActiveModel {
+            guild_id: guild_id.map(|id| id.to_i64()).into_active_value(),
+            user_id: user_id.to_i64().into_active_value(),
+            persona_id: Some(persona_id).into_active_value(),
+            model: model.name.clone().into_active_value(),
+            day: Utc::now().date_naive().into_active_value(),
+            requests: 1.into_active_value(),
+            prompt_tokens: prompt_tokens.into_active_value(),
+            completion_tokens: completion_tokens.into_active_value(),
+            cost: cost.into_active_value(),
+            ..Default::default()
+        })
+        .on_conflict(
+            OnConflict::columns([
+                token_usage::Column::GuildId,
+                token_usage::Column::UserId,
+                token_usage::Column::PersonaId,
+                token_usage::Column::Model,
+                token_usage::Column::Day,
+            ])
+            .value(token_usage::Column::Requests, add(token_usage::Column::Requests, 1.into()))
+            .value(token_usage::Column::PromptTokens, add(token_usage::Column::PromptTokens, prompt_tokens.into()))
+            .value(token_usage::Column::CompletionTokens, add(token_usage::Column::CompletionTokens, completion_tokens.into()))
+            .value(token_usage::Column::Cost, add(token_usage::Column::Cost, cost.into()))
+            .to_owned(),
+        )
+        .exec(self.db.connection())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Usage since `since` in a guild, or in DMs if `guild_id` is `None`.
+    ///
+    /// Only counts `user_id`'s usage if given.
+    pub async fn totals(
+        &self,
+        guild_id: Option<GuildId>,
+        user_id: Option<UserId>,
+        since: NaiveDate,
+    ) -> Result<UsageTotals, Error> {
+        let mut query = select_totals(guild_id, since);
+        if let Some(user_id) = user_id {
+            query = query.filter(token_usage::Column::UserId.eq(user_id.to_i64()));
+        }
+
+        let totals = query
+            .into_model::<UsageTotals>()
+            .one(self.db.connection())
+            .await?
+            .unwrap_or_default();
+
+        Ok(totals)
+    }
+
+    /// A guild's usage since `since` split by persona and model, most expensive first
+    pub async fn by_persona(&self, guild_id: GuildId, since: NaiveDate) -> Result<Vec<PersonaUsage>, Error> {
+        let usage = select_totals(Some(guild_id), since)
+            .column_as(persona::Column::Name, "persona")
+            .column(token_usage::Column::Model)
+            .left_join(persona::Entity)
+            .group_by(persona::Column::Name)
+            .group_by(token_usage::Column::Model)
+            .order_by(Expr::col(Alias::new("cost")), Order::Desc)
+            .order_by(persona::Column::Name, Order::Asc)
+            .into_model::<PersonaUsage>()
+            .all(self.db.connection())
+            .await?;
+
+        Ok(usage)
+    }
+
+    /// The `limit` users in a guild who used the most since `since`, by cost and then tokens
+    pub async fn top_users(&self, guild_id: GuildId, since: NaiveDate, limit: u64) -> Result<Vec<UserUsage>, Error> {
+        let usage = select_totals(Some(guild_id), since)
+            .column(token_usage::Column::UserId)
+            .group_by(token_usage::Column::UserId)
+            .order_by(Expr::col(Alias::new("cost")), Order::Desc)
+            .order_by(
+                sum(token_usage::Column::PromptTokens, "bigint").add(sum(token_usage::Column::CompletionTokens, "bigint")),
+                Order::Desc,
+            )
+            .limit(limit)
+            .into_model::<UserUsage>()
+            .all(self.db.connection())
+            .await?;
+
+        Ok(usage)
+    }
+
+    /// Fail with [UserError::QuotaExceeded] if the user or guild has used up any of their quotas.
+    ///
+    /// Per-user quotas can be set in any scope and the smallest applies.
+    /// Guild quotas can only be set globally or per guild.
+    pub async fn enforce_quotas(
+        &self,
+        settings: &SettingsManager,
+        guild_id: Option<GuildId>,
+        channel_id: ChannelId,
+        user_id: UserId,
+    ) -> Result<(), Error> {
+        let today = Utc::now().date_naive();
+        let mut totals: HashMap<(QuotaScope, QuotaPeriod), UsageTotals> = HashMap::new();
+
+        for quota in QUOTAS {
+            let ctx = match quota.scope {
+                QuotaScope::User => SettingsContext {
+                    guild_id,
+                    channel_id: Some(channel_id),
+                    user_id: Some(user_id),
+                },
+                QuotaScope::Guild if guild_id.is_some() => SettingsContext {
+                    guild_id,
+                    channel_id: None,
+                    user_id: None,
+                },
+                QuotaScope::Guild => continue,
+            };
+            let limit: SettingsValue<f64> = settings
+                .get_with_merge(ctx, &quota.key(), merge_strategies::Smallest)
+                .await?;
+            let Some(limit) = *limit.value() else {
+                continue;
+            };
+
+            let used = match totals.get(&(quota.scope, quota.period)) {
+                Some(used) => *used,
+                None => {
+                    let user_id = (quota.scope == QuotaScope::User).then_some(user_id);
+                    let used = self.totals(guild_id, user_id, quota.period.start(today)).await?;
+                    totals.insert((quota.scope, quota.period), used);
+                    used
+                }
+            };
+
+            if quota.used(&used) >= limit {
+                return Err(UserError::quota_exceeded(quota.describe(), quota.period.reset(today)).into());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(y: i32, m: u32, d: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(y, m, d).unwrap()
+    }
+
+    #[test]
+    fn periods_reset_at_midnight_utc() {
+        let today = date(2024, 12, 31);
+        assert_eq!(QuotaPeriod::Daily.start(today), today);
+        assert_eq!(QuotaPeriod::Monthly.start(today), date(2024, 12, 1));
+        assert_eq!(QuotaPeriod::Daily.reset(today).to_rfc3339(), "2025-01-01T00:00:00+00:00");
+        assert_eq!(QuotaPeriod::Monthly.reset(date(2024, 1, 31)).to_rfc3339(), "2024-02-01T00:00:00+00:00");
+    }
+
+    #[test]
+    fn quota_keys_are_unique() {
+        let keys = QUOTAS.iter().map(Quota::key).collect::<std::collections::HashSet<_>>();
+        assert_eq!(keys.len(), QUOTAS.len());
+        assert!(keys.contains("quota.user_daily_tokens"));
+        assert!(keys.contains("quota.guild_monthly_cost"));
+    }
+
+    #[test]
+    fn cost_uses_price_per_million_tokens() {
+        let pricing = ModelPricing { prompt: 2.0, completion: 10.0 };
+        let usage = Usage { prompt_tokens: 500_000, completion_tokens: 100_000 };
+        assert!((cost(&pricing, usage) - 2.0).abs() < f64::EPSILON);
+    }
+}
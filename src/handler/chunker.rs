@@ -0,0 +1,526 @@
+/// Splits a streamed response into Discord-sized messages without breaking its markdown.
+///
+/// Code blocks cut across messages are closed at the end of one message and reopened
+/// at the start of the next. Splits prefer paragraph breaks, then line breaks, then
+/// other whitespace, and never happen inside an inline code span.
+pub struct MessageChunker {
+    max_size: usize,
+    buffer: String,
+}
+
+impl MessageChunker {
+    /// `max_size` is the maximum length of a chunk in bytes and must be big enough
+    /// to fit a code fence on either side of some text
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            max_size,
+            buffer: String::with_capacity(max_size),
+        }
+    }
+
+    /// Add more text, returning any chunks which are now complete
+    pub fn push(&mut self, text: &str) -> Vec<String> {
+        self.buffer.push_str(text);
+
+        let mut chunks = vec![];
+        while self.buffer.len() > self.max_size {
+            let (chunk, rest) = split(&self.buffer, self.max_size);
+            if !chunk.trim().is_empty() {
+                chunks.push(chunk);
+            }
+            self.buffer = rest;
+        }
+
+        chunks
+    }
+
+    /// Text which isn't part of a complete chunk yet
+    pub fn pending(&self) -> &str {
+        &self.buffer
+    }
+
+    /// The final chunk, if there is any text left
+    pub fn finish(self) -> Option<String> {
+        let chunk = self.buffer.trim_end();
+        if chunk.trim_start().is_empty() {
+            None
+        } else {
+            Some(chunk.to_string())
+        }
+    }
+}
+
+/// A fenced code block
+#[derive(Debug, Clone, PartialEq)]
+struct Fence {
+    /// Run of backticks or tildes which opened the block
+    marker: String,
+    /// Language of the block, if any
+    language: String,
+}
+
+impl Fence {
+    /// Parse a line which opens a code block
+    fn open(line: &str) -> Option<Self> {
+        let trimmed = line.trim();
+        let c = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+        let len = trimmed.len() - trimmed.trim_start_matches(c).len();
+        if len < 3 {
+            return None;
+        }
+
+        let info = &trimmed[len..];
+        // ```foo``` is inline code, not a fence
+        if c == '`' && info.contains('`') {
+            return None;
+        }
+
+        Some(Self {
+            marker: trimmed[..len].to_string(),
+            language: info.split_whitespace().next().unwrap_or_default().to_string(),
+        })
+    }
+
+    fn is_closed_by(&self, line: &str) -> bool {
+        let trimmed = line.trim();
+        let c = self.marker.as_bytes()[0] as char;
+        trimmed.len() >= self.marker.len() && trimmed.chars().all(|ch| ch == c)
+    }
+
+    fn opening_line(&self) -> String {
+        format!("{}{}", self.marker, self.language)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum LineKind {
+    Text,
+    Code,
+    Open,
+    Close,
+}
+
+#[derive(Debug)]
+struct Line {
+    start: usize,
+    /// Index just past the line's newline
+    end: usize,
+    kind: LineKind,
+    /// Code block open at the start of this line
+    fence: Option<Fence>,
+}
+
+/// Markdown structure of a piece of text, as far as splitting it is concerned
+struct Analysis {
+    lines: Vec<Line>,
+    /// Code block still open at the end of the text
+    fence: Option<Fence>,
+    /// Byte ranges of inline code spans, including their backticks
+    spans: Vec<(usize, usize)>,
+}
+
+impl Analysis {
+    fn new(text: &str) -> Self {
+        let mut lines = vec![];
+        let mut fence: Option<Fence> = None;
+        let mut start = 0;
+        for line in text.split_inclusive('\n') {
+            let kind = match &fence {
+                Some(open) if open.is_closed_by(line) => LineKind::Close,
+                Some(_) => LineKind::Code,
+                None if Fence::open(line).is_some() => LineKind::Open,
+                None => LineKind::Text,
+            };
+            lines.push(Line {
+                start,
+                end: start + line.len(),
+                kind,
+                fence: fence.clone(),
+            });
+
+            match kind {
+                LineKind::Open => fence = Fence::open(line),
+                LineKind::Close => fence = None,
+                _ => {}
+            }
+            start += line.len();
+        }
+
+        // Inline code can span lines, but not paragraphs or code blocks
+        let mut spans = vec![];
+        let mut paragraph: Option<(usize, usize)> = None;
+        for line in &lines {
+            if line.kind == LineKind::Text && !text[line.start..line.end].trim().is_empty() {
+                let start = paragraph.map_or(line.start, |(start, _)| start);
+                paragraph = Some((start, line.end));
+            } else if let Some((start, end)) = paragraph.take() {
+                spans.extend(code_spans(text, start, end));
+            }
+        }
+        if let Some((start, end)) = paragraph {
+            spans.extend(code_spans(text, start, end));
+        }
+
+        Self { lines, fence, spans }
+    }
+
+    fn line_at(&self, index: usize) -> Option<&Line> {
+        let i = self.lines.partition_point(|line| line.end <= index);
+        self.lines.get(i)
+    }
+
+    /// Code block which would need to be closed when splitting at `index`
+    fn fence_at(&self, index: usize) -> Option<&Fence> {
+        match self.line_at(index) {
+            Some(line) => line.fence.as_ref(),
+            None => self.fence.as_ref(),
+        }
+    }
+
+    /// Whether splitting at `index` would leave the markdown intact
+    fn can_split_at(&self, index: usize) -> bool {
+        if self.spans.iter().any(|&(start, end)| start < index && index < end) {
+            return false;
+        }
+
+        let Some(line) = self.line_at(index) else {
+            return true;
+        };
+        match line.kind {
+            // Never split a fence, or leave an empty code block behind
+            LineKind::Open => index == line.start,
+            LineKind::Close => false,
+            LineKind::Code | LineKind::Text => {
+                let previous = self.line_at(index.saturating_sub(1));
+                !(index == line.start && previous.is_some_and(|prev| prev.kind == LineKind::Open))
+            }
+        }
+    }
+}
+
+/// Find the inline code spans within a paragraph
+fn code_spans(text: &str, start: usize, end: usize) -> Vec<(usize, usize)> {
+    let bytes = text.as_bytes();
+
+    let mut runs = vec![];
+    let mut i = start;
+    while i < end {
+        if bytes[i] == b'`' {
+            let run_start = i;
+            while i < end && bytes[i] == b'`' {
+                i += 1;
+            }
+            runs.push((run_start, i - run_start));
+        } else {
+            i += 1;
+        }
+    }
+
+    // A span is closed by the next run of the same length. Unmatched runs are literal backticks
+    let mut spans = vec![];
+    let mut i = 0;
+    while i < runs.len() {
+        let (open, len) = runs[i];
+        match runs[i + 1..].iter().position(|&(_, l)| l == len) {
+            Some(offset) => {
+                let (close, _) = runs[i + 1 + offset];
+                spans.push((open, close + len));
+                i += offset + 2;
+            }
+            None => i += 1,
+        }
+    }
+
+    spans
+}
+
+/// Split `text` into a chunk of at most `max_size` bytes and the remaining text
+fn split(text: &str, max_size: usize) -> (String, String) {
+    let analysis = Analysis::new(text);
+
+    // Higher priority splits win, then later ones
+    let mut best: Option<(u8, usize)> = None;
+    for index in (1..=text.len().min(max_size)).filter(|i| text.is_char_boundary(*i)) {
+        if !analysis.can_split_at(index) {
+            continue;
+        }
+
+        let head = &text[..index];
+        if let Some(fence) = analysis.fence_at(index) {
+            let closing = fence.marker.len() + usize::from(!head.ends_with('\n'));
+            // The remaining text has to get shorter after reopening the block
+            if index + closing > max_size || index <= fence.opening_line().len() + 1 {
+                continue;
+            }
+        }
+
+        // Tiny chunks aren't worth splitting at a nicer spot
+        let long_enough = index >= max_size / 2;
+        let priority = if long_enough && head.ends_with("\n\n") {
+            3
+        } else if long_enough && head.ends_with('\n') {
+            2
+        } else if head.ends_with(char::is_whitespace) {
+            1
+        } else {
+            0
+        };
+
+        if Some((priority, index)) >= best {
+            best = Some((priority, index));
+        }
+    }
+
+    let Some((_, index)) = best else {
+        // Nowhere to split cleanly (eg a huge inline code span), so cut it off
+        let mut index = max_size.min(text.len());
+        while !text.is_char_boundary(index) {
+            index -= 1;
+        }
+        return (text[..index].to_string(), text[index..].to_string());
+    };
+
+    let (head, tail) = text.split_at(index);
+    match analysis.fence_at(index) {
+        Some(fence) => {
+            let mut chunk = head.to_string();
+            if !chunk.ends_with('\n') {
+                chunk.push('\n');
+            }
+            chunk.push_str(&fence.marker);
+
+            (chunk, format!("{}\n{}", fence.opening_line(), tail))
+        }
+        None => (head.trim_end().to_string(), tail.trim_start_matches('\n').to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(text: &str, max_size: usize) -> Vec<String> {
+        let mut chunker = MessageChunker::new(max_size);
+        let mut chunks = chunker.push(text);
+        chunks.extend(chunker.finish());
+        chunks
+    }
+
+    fn chunk_streamed(text: &str, max_size: usize) -> Vec<String> {
+        let mut chunker = MessageChunker::new(max_size);
+        let mut chunks = vec![];
+        for c in text.chars() {
+            chunks.extend(chunker.push(&c.to_string()));
+        }
+        chunks.extend(chunker.finish());
+        chunks
+    }
+
+    fn assert_fits(chunks: &[String], max_size: usize) {
+        for chunk in chunks {
+            assert!(chunk.len() <= max_size, "chunk is {} bytes: {:?}", chunk.len(), chunk);
+            assert!(!chunk.trim().is_empty());
+        }
+    }
+
+    #[test]
+    fn short_text_is_one_chunk() {
+        assert_eq!(chunk("hello world\n", 100), vec!["hello world"]);
+    }
+
+    #[test]
+    fn empty_text_has_no_chunks() {
+        assert!(chunk("", 100).is_empty());
+        assert!(chunk(" \n\n ", 100).is_empty());
+    }
+
+    #[test]
+    fn prefers_paragraph_breaks() {
+        let text = "aaaa aaaa aaaa\n\nbbbb\nbbbb bbbb";
+        assert_eq!(chunk(text, 25), vec!["aaaa aaaa aaaa", "bbbb\nbbbb bbbb"]);
+    }
+
+    #[test]
+    fn prefers_line_breaks_over_spaces() {
+        let text = "- item one\n- item two\n- item three";
+        let chunks = chunk(text, 25);
+        assert_eq!(chunks, vec!["- item one\n- item two", "- item three"]);
+    }
+
+    #[test]
+    fn ignores_early_paragraph_breaks() {
+        let text = "a\n\nbbbb bbbb bbbb bbbb bbbb";
+        let chunks = chunk(text, 20);
+        assert_eq!(chunks, vec!["a\n\nbbbb bbbb bbbb", "bbbb bbbb"]);
+    }
+
+    #[test]
+    fn splits_long_words() {
+        let chunks = chunk("abcdefghijklmnopqrstuvwxyz", 10);
+        assert_eq!(chunks, vec!["abcdefghij", "klmnopqrst", "uvwxyz"]);
+    }
+
+    #[test]
+    fn does_not_split_multibyte_characters() {
+        let chunks = chunk("ééééééé", 5);
+        assert_eq!(chunks, vec!["éé", "éé", "éé", "é"]);
+    }
+
+    #[test]
+    fn closes_and_reopens_code_blocks() {
+        let text = "Here:\n```rust\nlet a = 1;\nlet b = 2;\nlet c = 3;\n```\nDone";
+        let chunks = chunk(text, 40);
+
+        assert_fits(&chunks, 40);
+        assert_eq!(chunks, vec![
+            "Here:\n```rust\nlet a = 1;\nlet b = 2;\n```",
+            "```rust\nlet c = 3;\n```\nDone",
+        ]);
+    }
+
+    #[test]
+    fn reopens_tilde_fences() {
+        let text = "~~~~\nline one\nline two\nline three\n~~~~";
+        let chunks = chunk(text, 28);
+
+        assert_fits(&chunks, 28);
+        assert_eq!(chunks[0], "~~~~\nline one\nline two\n~~~~");
+        assert_eq!(chunks[1], "~~~~\nline three\n~~~~");
+    }
+
+    #[test]
+    fn keeps_code_blocks_open_across_several_chunks() {
+        let code = (0..20).map(|i| format!("line {}\n", i)).collect::<String>();
+        let text = format!("```py\n{}```", code);
+        let chunks = chunk(&text, 40);
+
+        assert_fits(&chunks, 40);
+        for chunk in &chunks {
+            assert!(chunk.starts_with("```py\n"), "{:?}", chunk);
+            assert!(chunk.ends_with("\n```"), "{:?}", chunk);
+        }
+
+        let joined = chunks
+            .iter()
+            .map(|c| c.trim_start_matches("```py\n").trim_end_matches("```"))
+            .collect::<String>();
+        assert_eq!(joined, code);
+    }
+
+    #[test]
+    fn splits_long_code_lines_inside_block() {
+        let text = format!("```\n{}\n```", "x".repeat(30));
+        let chunks = chunk(&text, 20);
+
+        assert_fits(&chunks, 20);
+        assert!(chunks.iter().all(|c| c.starts_with("```\n") && c.ends_with("\n```")));
+    }
+
+    #[test]
+    fn does_not_leave_empty_code_blocks() {
+        let text = "intro text\n```\ncode\n```";
+        let chunks = chunk(text, 18);
+
+        assert_fits(&chunks, 18);
+        assert_eq!(chunks, vec!["intro text", "```\ncode\n```"]);
+    }
+
+    #[test]
+    fn text_after_code_block_is_not_fenced() {
+        let text = "```\na\n```\nsome text after the block";
+        let chunks = chunk(text, 20);
+
+        assert_fits(&chunks, 20);
+        assert_eq!(chunks[0], "```\na\n```");
+        assert!(!chunks[1].contains("```"));
+    }
+
+    #[test]
+    fn never_splits_inline_code() {
+        let text = "run `cargo build --release` now please";
+        let chunks = chunk(text, 24);
+
+        assert_fits(&chunks, 24);
+        assert_eq!(chunks, vec!["run", "`cargo build --release`", "now please"]);
+    }
+
+    #[test]
+    fn inline_code_with_double_backticks() {
+        let text = "see ``a ` b c`` and more words";
+        let chunks = chunk(text, 16);
+
+        assert_fits(&chunks, 16);
+        assert_eq!(chunks, vec!["see ``a ` b c``", "and more words"]);
+    }
+
+    #[test]
+    fn unmatched_backticks_are_literal() {
+        let text = "it's a ` lonely backtick here";
+        let chunks = chunk(text, 16);
+
+        assert_fits(&chunks, 16);
+        assert_eq!(chunks, vec!["it's a ` lonely", "backtick here"]);
+    }
+
+    #[test]
+    fn huge_inline_code_is_cut() {
+        let text = format!("`{}`", "a".repeat(30));
+        let chunks = chunk(&text, 16);
+
+        assert_fits(&chunks, 16);
+        assert_eq!(chunks.concat(), text);
+    }
+
+    #[test]
+    fn triple_backticks_inline_are_not_fences() {
+        let text = "use ```x``` here\nmore text here ok";
+        let chunks = chunk(text, 20);
+
+        assert_eq!(chunks, vec!["use ```x``` here", "more text here ok"]);
+    }
+
+    #[test]
+    fn keeps_table_rows_whole() {
+        let text = "| a | b |\n|---|---|\n| 1 | 2 |\n| 3 | 4 |";
+        let chunks = chunk(text, 30);
+
+        assert_fits(&chunks, 30);
+        assert_eq!(chunks, vec!["| a | b |\n|---|---|\n| 1 | 2 |", "| 3 | 4 |"]);
+    }
+
+    #[test]
+    fn streaming_matches_whole_text() {
+        let text = "Intro paragraph here.\n\n```js\nconsole.log(1);\nconsole.log(2);\n```\n\nUse `npm test` to check.";
+        let streamed = chunk_streamed(text, 40);
+
+        assert_fits(&streamed, 40);
+        for chunk in &streamed {
+            // Every chunk has balanced fences
+            assert_eq!(chunk.matches("```").count() % 2, 0, "{:?}", chunk);
+        }
+        assert!(streamed.iter().any(|c| c.contains("`npm test`")));
+    }
+
+    #[test]
+    fn pending_holds_incomplete_chunk() {
+        let mut chunker = MessageChunker::new(20);
+        assert!(chunker.push("```\nsome code\nmore").is_empty());
+        assert_eq!(chunker.pending(), "```\nsome code\nmore");
+
+        assert_eq!(chunker.push(" code\n"), vec!["```\nsome code\n```"]);
+        assert_eq!(chunker.pending(), "```\nmore code\n");
+    }
+
+    #[test]
+    fn detects_fences() {
+        assert_eq!(Fence::open("```rust\n"), Some(Fence { marker: "```".to_string(), language: "rust".to_string() }));
+        assert_eq!(Fence::open("  ~~~~ python extra"), Some(Fence { marker: "~~~~".to_string(), language: "python".to_string() }));
+        assert_eq!(Fence::open("``not a fence"), None);
+        assert_eq!(Fence::open("```inline```"), None);
+
+        let fence = Fence::open("````").unwrap();
+        assert!(fence.is_closed_by("`````\n"));
+        assert!(!fence.is_closed_by("```"));
+        assert!(!fence.is_closed_by("```` text"));
+    }
+}
@@ -1,5 +1,9 @@
-use std::fmt::Write;
-use crate::gpt::Chat;
+mod chunker;
+mod queue;
+
+use crate::gpt::{AttachmentCache, AttachmentOptions, Chat, ChatMessage, ChatOptions, ConversationRecord, ModelInfo, ResponseLog, SummaryManager, VisionOptions};
+use crate::gpt::backend::{ChatBackend, CompletionEvent, CompletionStream, Usage};
+use crate::gpt::tools::{EnabledTools, ToolContext, ToolRunner};
 use metrics::{histogram, counter};
 use std::future::Future;
 use std::sync::Arc;
@@ -9,14 +13,145 @@ use tracing::{debug, error, info};
 
 use crate::error::{FaultyBotError, UserError};
 use crate::permissions::Permission;
+use crate::permissions::policy::cmp_roles;
+use crate::rate_limit::{Bucket, RateLimit, RateLimiter};
+use crate::settings::{SettingsContext, SettingsScopeKind, SettingsValue};
 use crate::{Data, Error};
 use poise::serenity_prelude as serenity;
 use poise::serenity_prelude::{CacheHttp, Context, Message};
-use tokio::sync::RwLock;
+use serde::Deserialize;
 use crate::util::{AuditInfo, say_ephemeral};
+use chunker::MessageChunker;
+use queue::ChatQueue;
 
 const COOLDOWN_KEY: &str = "chat.cooldown";
+const BACKEND_KEY: &str = "chat.backend";
+const QUEUE_SIZE_KEY: &str = "chat.queue_size";
+const CONTEXT_TOKENS_KEY: &str = "chat.context_tokens";
+const VISION_KEY: &str = "chat.vision";
+const ATTACHMENTS_KEY: &str = "chat.attachments";
+const STREAM_MODE_KEY: &str = "chat.stream_mode";
+const OVERFLOW_KEY: &str = "chat.overflow";
+const RETENTION_KEY: &str = "conversation.retention_days";
+/// Days conversations are kept for when `conversation.retention_days` is unset
+const DEFAULT_RETENTION_DAYS: u64 = 30;
 const MAX_MESSAGE_SIZE: usize = 1950;
+/// Discord allows around 5 edits per 5 seconds in a channel
+const EDIT_INTERVAL: Duration = Duration::from_millis(1200);
+/// Discord's limit on the length of a thread name
+const MAX_THREAD_NAME_LENGTH: usize = 100;
+/// Added to messages waiting for a cooldown to expire
+const QUEUED_REACTION: char = '⏳';
+
+/// How a streamed response is posted, set by the `chat.stream_mode` setting
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum StreamMode {
+    /// Send a new message each time enough of the response has arrived to fill one
+    #[default]
+    Chunks,
+    /// Send a message as soon as the response starts and keep editing it as more arrives
+    Edit,
+}
+
+/// Value of the `chat.overflow` setting
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+struct OverflowOptions {
+    mode: OverflowMode,
+    /// Responses longer than this many bytes are posted according to `mode`
+    max_length: usize,
+}
+
+impl Default for OverflowOptions {
+    fn default() -> Self {
+        Self {
+            mode: OverflowMode::Messages,
+            max_length: 2 * MAX_MESSAGE_SIZE,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum OverflowMode {
+    /// Post the response as several messages
+    #[default]
+    Messages,
+    /// Attach the whole response as a markdown file
+    File,
+    /// Create a thread from the triggering message and post the response there
+    Thread,
+}
+
+/// How the response to a message is posted
+#[derive(Debug, Clone, Default)]
+struct ReplyOptions {
+    stream_mode: StreamMode,
+    overflow: OverflowOptions,
+}
+
+/// Everything about a completed reply that's recorded once it has been posted
+struct CompletedReply {
+    /// Messages sent with the final request to the model
+    messages: Vec<ChatMessage>,
+    response: ResponseLog,
+    /// Tokens used by every request made while replying
+    usage: Usage,
+}
+
+/// Content of a streamed delta, ignoring anything not meant for the assistant's reply
+fn assistant_content(event: CompletionEvent) -> Option<String> {
+    let CompletionEvent::Delta(delta) = event else {
+        return None;
+    };
+
+    match delta.role {
+        None => (),
+        Some(openai::chat::ChatCompletionMessageRole::Assistant) => (),
+        _ => return None,
+    }
+
+    if delta.content.is_none() {
+        tracing::warn!("Stream delta with no content detected: {:?}", delta);
+    }
+    delta.content
+}
+
+/// Whether `message` continues a conversation in a thread the bot created
+fn in_own_thread(ctx: &serenity::Context, message: &Message, bot_id: serenity::UserId) -> bool {
+    // Other bots and system messages, eg pins or members being added, aren't part of the conversation
+    let conversational = matches!(message.kind, serenity::MessageType::Regular | serenity::MessageType::InlineReply);
+    if message.author.bot || !conversational {
+        return false;
+    }
+
+    let Some(guild_id) = message.guild_id else {
+        return false;
+    };
+
+    ctx.cache
+        .guild(guild_id)
+        .is_some_and(|guild| guild.threads
+            .iter()
+            .any(|thread| thread.id == message.channel_id && thread.owner_id == Some(bot_id)))
+}
+
+/// Name for a thread started from a message, without any mentions
+fn thread_name(content: &str) -> String {
+    let name = content
+        .split_whitespace()
+        .filter(|word| !(word.starts_with("<@") && word.ends_with('>')))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let name = name.chars().take(MAX_THREAD_NAME_LENGTH).collect::<String>();
+
+    if name.is_empty() {
+        "Conversation".to_string()
+    } else {
+        name
+    }
+}
 
 pub async fn on_error(error: poise::FrameworkError<'_, Data, Error>) -> Result<(), Error> {
     match error {
@@ -62,13 +197,15 @@ where
 }
 
 pub(crate) struct Handler {
-    cooldowns: RwLock<poise::Cooldowns>,
+    rate_limiter: RateLimiter,
+    queue: ChatQueue,
 }
 
 impl Handler {
-    pub fn new() -> Self {
+    pub fn new(rate_limiter: RateLimiter) -> Self {
         Self {
-            cooldowns: RwLock::new(poise::Cooldowns::new()),
+            rate_limiter,
+            queue: ChatQueue::default(),
         }
     }
 
@@ -97,6 +234,14 @@ impl Handler {
                     .await?;
                 }
             }
+            serenity::FullEvent::MessageDelete { deleted_message_id, .. } => {
+                self.queue.cancel(*deleted_message_id);
+            }
+            serenity::FullEvent::MessageDeleteBulk { multiple_deleted_messages_ids, .. } => {
+                for message_id in multiple_deleted_messages_ids {
+                    self.queue.cancel(*message_id);
+                }
+            }
             _ => {}
         }
 
@@ -115,11 +260,35 @@ impl Handler {
 
         tracing::trace!("Received message: {:?}", new_message);
 
-        // Only reply to DMs and direct mentions
-        if new_message.guild_id.is_some() && !new_message.mentions_user_id(ctx.bot_id()) {
+        // Only reply to DMs, direct mentions and threads created to continue a conversation
+        let addressed = new_message.guild_id.is_none() || new_message.mentions_user_id(ctx.bot_id());
+        if !addressed && !in_own_thread(ctx.serenity_context, &new_message, ctx.bot_id()) {
             return Ok(());
         }
 
+        let message_id = new_message.id;
+        let result = self.handle_chat_message(ctx, new_message).await;
+        if addressed {
+            return result;
+        }
+
+        // Every message in the bot's threads is answered, so don't reply to each one just to refuse it
+        match result {
+            Err(FaultyBotError::User(err @ (UserError::AccessDenied { .. } | UserError::CooldownHit { .. } | UserError::QueueFull))) => {
+                debug!("Not replying to message {} in own thread: {}", message_id, err);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Reply to a message addressed to the bot
+    async fn handle_chat_message<'a>(
+        &self,
+        ctx: poise::FrameworkContext<'a, Data, Error>,
+        new_message: serenity::Message,
+    ) -> Result<(), Error> {
+
         let channel_id = new_message
             .channel(ctx.serenity_context)
             .await?
@@ -151,16 +320,33 @@ impl Handler {
             channel_id,
         };
 
-        {
-            let config = self.get_config(cd_ctx.clone(), ctx.user_data()).await?;
+        // Before taking a cooldown token or a place in the queue, which a rejected message would waste
+        ctx.user_data()
+            .usage_manager
+            .enforce_quotas(
+                &ctx.user_data().settings_manager,
+                new_message.guild_id,
+                channel_id,
+                new_message.author.id,
+            )
+            .await?;
 
-            let time_remaining = self
-                .cooldowns
-                .read()
-                .await
-                .remaining_cooldown(cd_ctx.clone(), &config);
-            if let Some(time_remaining) = time_remaining {
-                return Err(UserError::cooldown_hit(time_remaining).into());
+        let rate_limits = Self::get_rate_limits(&cd_ctx, ctx.serenity_context, &ctx.user_data()).await?;
+        // Messages already waiting on the same buckets get their turn first, rather than being overtaken by this one
+        let buckets = rate_limits.iter().map(|bucket| bucket.key.clone()).collect::<Vec<_>>();
+        let queued = self.queue.is_waiting(channel_id, &buckets);
+        let remaining = if queued {
+            None
+        } else {
+            self.rate_limiter.try_acquire(&rate_limits).await?
+        };
+        if queued || remaining.is_some() {
+            let acquired = self
+                .wait_in_queue(ctx, &new_message, &cd_ctx, &rate_limits, &buckets, remaining)
+                .await?;
+            if !acquired {
+                debug!("Message {} was deleted while queued", new_message.id);
+                return Ok(());
             }
         }
 
@@ -180,13 +366,78 @@ impl Handler {
             author, &new_message.content
         );
 
-        let result = Self::reply_with_gpt_completion(ctx.serenity_context, persona, new_message).await;
-
-        if let Err(err) = result {
-            counter!("gpt_errors_total", &metric_labels).increment(1);
-            error!("Failed to send reply: {}", err);
+        let user_data = ctx.user_data();
+        let persona_id = persona.id();
+        let builtin_prompt = persona.is_builtin();
+        let model_name = persona.model();
+        let model = user_data.model_registry.get(&model_name)?;
+        let backend = Self::get_backend(&cd_ctx, model, &user_data).await?;
+        let options = Self::get_chat_options(&cd_ctx, model, &user_data).await?;
+        let reply = Self::get_reply_options(&cd_ctx, &user_data).await?;
+
+        let tools = if model.capabilities.tools {
+            user_data.tools.for_persona(&persona)
         } else {
-            counter!("gpt_responses_total", &metric_labels).increment(1);
+            vec![]
+        };
+        let tools = EnabledTools::new(
+            ToolContext {
+                framework: ctx,
+                user_id: new_message.author.id,
+                channel_id,
+                message_channel_id: new_message.channel_id,
+                guild_id: new_message.guild_id,
+            },
+            tools,
+        );
+
+        let (guild_id, message_channel_id, message_id) = (new_message.guild_id, new_message.channel_id, new_message.id);
+        let result = Self::reply_with_gpt_completion(
+            ctx.serenity_context,
+            backend,
+            persona,
+            new_message,
+            &user_data.summary_manager,
+            &user_data.attachment_cache,
+            &tools,
+            options,
+            reply,
+        ).await;
+
+        match result {
+            Err(err) => {
+                counter!("gpt_errors_total", &metric_labels).increment(1);
+                error!("Failed to send reply: {}", err);
+            }
+            Ok(CompletedReply { messages, response, usage }) => {
+                counter!("gpt_responses_total", &metric_labels).increment(1);
+
+                if usage != Usage::default() {
+                    let result = user_data.usage_manager
+                        .record(guild_id, cd_ctx.user_id, persona_id, model, usage)
+                        .await;
+                    if let Err(err) = result {
+                        error!("Failed to record token usage: {}", err);
+                    }
+                }
+
+                let record = ConversationRecord {
+                    guild_id,
+                    channel_id: message_channel_id,
+                    user_id: cd_ctx.user_id,
+                    message_id,
+                    persona_id,
+                    model: &model_name,
+                    builtin_prompt,
+                    messages: &messages,
+                    response,
+                    latency: start.elapsed(),
+                };
+                // The reply has already been sent, so failing to record it shouldn't bother the user
+                if let Err(err) = Self::record_conversation(&user_data, record).await {
+                    error!("Failed to record conversation: {}", err);
+                }
+            }
         }
 
         let delay =
@@ -196,134 +447,522 @@ impl Handler {
         histogram!("gpt_response_seconds", &metric_labels).record(duration.as_secs_f64());
         histogram!("gpt_response_delay_seconds", &metric_labels).record(delay.as_secs_f64());
 
-        {
-            self.cooldowns.write().await.start_cooldown(cd_ctx);
+        Ok(())
+    }
+
+    /// Hold a message which hit a cooldown, or arrived while others limited by the same `buckets` were queued,
+    /// in its channel's queue until it's its turn and the cooldown expires, if `chat.queue_size` leaves room for it.
+    /// Otherwise the cooldown is returned as an error, if `remaining` is known.
+    ///
+    /// Returns `false` if the message was deleted while it was queued.
+    async fn wait_in_queue(
+        &self,
+        ctx: poise::FrameworkContext<'_, Data, Error>,
+        message: &Message,
+        cd_ctx: &poise::CooldownContext,
+        rate_limits: &[Bucket],
+        buckets: &[String],
+        remaining: Option<Duration>,
+    ) -> Result<bool, Error> {
+        // The queue is shared by the whole channel, so its size can't depend on who joins it
+        let queue_size: SettingsValue<usize> = ctx.user_data()
+            .settings_manager
+            .get_value(SettingsContext {
+                guild_id: cd_ctx.guild_id,
+                channel_id: Some(cd_ctx.channel_id),
+                user_id: None,
+            }, QUEUE_SIZE_KEY)
+            .await?;
+
+        let max_size = queue_size.value().unwrap_or(0);
+        let Some(ticket) = self.queue.join(cd_ctx.channel_id, buckets, message.id, max_size) else {
+            let error = match remaining {
+                Some(remaining) => UserError::cooldown_hit(remaining),
+                None => UserError::queue_full(),
+            };
+            return Err(error.into());
+        };
+        debug!(
+            "Queued message {}, {} waiting in channel {}",
+            message.id,
+            self.queue.len(cd_ctx.channel_id),
+            cd_ctx.channel_id
+        );
+
+        let http = ctx.serenity_context.http();
+        if let Err(err) = message.react(http, QUEUED_REACTION).await {
+            debug!("Failed to mark message {} as queued: {}", message.id, err);
         }
 
-        Ok(())
+        let acquired = ticket.wait(|| self.rate_limiter.try_acquire(rate_limits)).await;
+        if !matches!(acquired, Ok(false)) {
+            // The message is still around, so stop showing it as queued
+            if let Err(err) = message.delete_reaction(http, Some(ctx.bot_id()), QUEUED_REACTION).await {
+                debug!("Failed to remove queued reaction: {}", err);
+            }
+        }
+
+        acquired
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn reply_with_gpt_completion(
         ctx: &serenity::Context,
+        backend: Arc<dyn ChatBackend>,
         persona: crate::gpt::Persona,
         message: serenity::Message,
-    ) -> Result<serenity::Message, Error> {
+        summaries: &SummaryManager,
+        attachments: &AttachmentCache,
+        tools: &dyn ToolRunner,
+        options: ChatOptions,
+        reply: ReplyOptions,
+    ) -> Result<CompletedReply, Error> {
         let _typing = serenity::Typing::start(ctx.http.clone(), message.channel_id);
 
-        let chat = Chat::from(ctx, persona, &message).await?;
-        let stream = chat.stream_completion().await?;
+        let mut chat = Chat::from(ctx, backend, persona, &message, summaries, attachments, options).await?;
+        let stream = chat.stream_with_tools(tools).await?;
+        let (stream, log) = ResponseLog::track(stream);
+
+        Self::post_completion(ctx, message, stream, reply).await?;
+
+        let response = std::mem::take(&mut *log.lock().unwrap());
+        Ok(CompletedReply {
+            messages: chat.messages().to_vec(),
+            response,
+            usage: chat.usage(),
+        })
+    }
+
+    /// Post the response to `message` according to `reply`
+    async fn post_completion(
+        ctx: &serenity::Context,
+        message: serenity::Message,
+        mut stream: CompletionStream,
+        reply: ReplyOptions,
+    ) -> Result<(), Error> {
+        use tokio_stream::StreamExt as _;
+
+        if reply.overflow.mode != OverflowMode::Messages {
+            // Hold back the start of the response until it's clear whether it overflows
+            let (head, overflowed) = Self::buffer_response(&mut stream, reply.overflow.max_length).await;
+            stream = Box::pin(tokio_stream::iter(head).chain(stream));
+
+            if overflowed {
+                match reply.overflow.mode {
+                    OverflowMode::File => return Self::reply_with_file(ctx, &message, stream).await,
+                    OverflowMode::Thread => {
+                        if let Some(thread) = Self::create_thread(ctx, &message).await? {
+                            let _typing = serenity::Typing::start(ctx.http.clone(), thread.id);
+                            return Self::post_response(ctx, thread.id, None, stream, reply.stream_mode).await;
+                        }
+                    }
+                    OverflowMode::Messages => {}
+                }
+            }
+        }
+
+        Self::post_response(ctx, message.channel_id, Some(message), stream, reply.stream_mode).await
+    }
+
+    /// Read from `stream` until the response is longer than `max_length` or ends.
+    ///
+    /// Returns the events read and whether the response was too long.
+    async fn buffer_response(
+        stream: &mut CompletionStream,
+        max_length: usize,
+    ) -> (Vec<CompletionEvent>, bool) {
+        use tokio_stream::StreamExt as _;
+
+        let mut events = vec![];
+        let mut length = 0;
+        while let Some(event) = stream.next().await {
+            if let CompletionEvent::Delta(delta) = &event {
+                length += delta.content.as_ref().map_or(0, String::len);
+            }
+            events.push(event);
+            if length > max_length {
+                return (events, true);
+            }
+        }
+
+        (events, false)
+    }
+
+    /// Reply with the whole response attached as a markdown file
+    async fn reply_with_file(
+        ctx: &serenity::Context,
+        message: &serenity::Message,
+        mut stream: CompletionStream,
+    ) -> Result<(), Error> {
+        use tokio_stream::StreamExt as _;
+
+        let mut response = String::new();
+        while let Some(event) = stream.next().await {
+            if let Some(content) = assistant_content(event) {
+                response.push_str(&content);
+            }
+        }
+
+        let mut builder = serenity::CreateMessage::default()
+            .add_file(serenity::CreateAttachment::bytes(response.into_bytes(), "response.md"))
+            .allowed_mentions(serenity::CreateAllowedMentions::default());
+        if message.guild_id.is_some() {
+            builder = builder.reference_message(message);
+        }
+        message
+            .channel_id
+            .send_message(ctx.http(), builder)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Start a thread from `message` to continue the conversation in.
+    ///
+    /// Returns `None` if threads can't be created where the message was sent (eg DMs or another thread)
+    async fn create_thread(
+        ctx: &serenity::Context,
+        message: &serenity::Message,
+    ) -> Result<Option<serenity::GuildChannel>, Error> {
+        let Some(channel) = message.channel(ctx).await?.guild() else {
+            return Ok(None);
+        };
+        if !matches!(channel.kind, serenity::ChannelType::Text | serenity::ChannelType::News) {
+            return Ok(None);
+        }
+
+        let thread = message
+            .channel_id
+            .create_thread_from_message(
+                &ctx.http,
+                message.id,
+                serenity::CreateThread::new(thread_name(&message.content)),
+            )
+            .await?;
+
+        Ok(Some(thread))
+    }
+
+    /// Post the response in `channel_id`, starting with a reply to `reply_to` if given
+    async fn post_response(
+        ctx: &serenity::Context,
+        channel_id: serenity::ChannelId,
+        reply_to: Option<serenity::Message>,
+        stream: CompletionStream,
+        stream_mode: StreamMode,
+    ) -> Result<(), Error> {
+        if stream_mode == StreamMode::Edit {
+            return Self::reply_with_edits(ctx, channel_id, reply_to, stream).await;
+        }
 
         let (tx, mut rx) = tokio::sync::mpsc::channel(4);
         tokio::spawn(Self::produce_message_chunks(stream, tx));
 
-        let mut last_msg: serenity::Message = message;
+        let mut last_msg = reply_to;
         while let Some(content) = rx.recv().await {
-            last_msg = Self::send_reply(ctx, &last_msg, content).await?;
+            last_msg = Some(Self::send_reply(ctx, channel_id, last_msg.as_ref(), content).await?);
         }
 
-        // Ok(last_msg.expect("ChatGPT API didn't return any response"))
-        Ok(last_msg)
+        Ok(())
     }
 
     async fn produce_message_chunks(
-        mut stream: impl tokio_stream::Stream<Item=openai::chat::ChatCompletionMessageDelta> + Unpin,
+        mut stream: impl tokio_stream::Stream<Item=CompletionEvent> + Unpin,
         tx: tokio::sync::mpsc::Sender<String>
     ) -> Result<(), Error> {
         use tokio_stream::StreamExt as _;
 
-        let mut buffer = String::with_capacity(MAX_MESSAGE_SIZE);
+        let mut chunker = MessageChunker::new(MAX_MESSAGE_SIZE);
+
+        while let Some(event) = stream.next().await {
+            let Some(content) = assistant_content(event) else {
+                continue;
+            };
 
-        while let Some(delta) = stream.next().await {
-            match delta.role {
-                None => (),
-                Some(openai::chat::ChatCompletionMessageRole::Assistant) => (),
-                _ => continue // ignore everything not for the assistant
+            for chunk in chunker.push(&content) {
+                tx.send(chunk)
+                    .await
+                    .map_err(Error::boxed)?;
             }
+        }
 
-            if let Some(content) = delta.content {
-                buffer.write_str(content.as_str())?;
+        if let Some(chunk) = chunker.finish() {
+            tx.send(chunk).await.map_err(Error::boxed)?;
+        }
 
-                while buffer.len() > MAX_MESSAGE_SIZE {
-                    let chars_to_take = find_last_whitespace_before_index(&buffer, MAX_MESSAGE_SIZE).unwrap_or(MAX_MESSAGE_SIZE - 2 ) + 1;
-                    tx.send(buffer[..chars_to_take].to_owned())
-                        .await
-                        .map_err(Error::boxed)?;
-                    buffer = buffer[chars_to_take..].to_owned();
-                }
-            } else {
-                tracing::warn!("Stream delta with no content detected: {:?}", delta);
+        Ok(())
+    }
+
+    async fn reply_with_edits(
+        ctx: &serenity::Context,
+        channel_id: serenity::ChannelId,
+        reply_to: Option<serenity::Message>,
+        mut stream: impl tokio_stream::Stream<Item=CompletionEvent> + Unpin,
+    ) -> Result<(), Error> {
+        use tokio_stream::StreamExt as _;
+
+        let mut chunker = MessageChunker::new(MAX_MESSAGE_SIZE);
+        let mut last_msg = reply_to;
+        // Message still being edited and the content it currently shows
+        let mut current: Option<serenity::Message> = None;
+        let mut shown = String::new();
+        let mut last_edit = Instant::now();
+
+        while let Some(event) = stream.next().await {
+            let Some(content) = assistant_content(event) else {
+                continue;
+            };
+
+            // Once a message is full, finish it off and roll over to a new one
+            for chunk in chunker.push(&content) {
+                last_msg = Some(Self::show_reply(ctx, channel_id, last_msg.as_ref(), current.take(), chunk).await?);
+                shown.clear();
+            }
+
+            let pending = chunker.pending().trim_end();
+            let due = current.is_none() || last_edit.elapsed() >= EDIT_INTERVAL;
+            if due && !pending.trim_start().is_empty() && pending != shown {
+                shown = pending.to_string();
+                current = Some(Self::show_reply(ctx, channel_id, last_msg.as_ref(), current.take(), shown.clone()).await?);
+                last_edit = Instant::now();
             }
         }
 
-        tx.send(buffer).await.map_err(Error::boxed)?;
+        match (chunker.finish(), current) {
+            (Some(chunk), Some(_)) if chunk == shown => {}
+            (Some(chunk), current) => {
+                Self::show_reply(ctx, channel_id, last_msg.as_ref(), current, chunk).await?;
+            }
+            (None, _) => {}
+        }
 
         Ok(())
     }
 
-    async fn send_reply(ctx: &Context, message: &Message, content: impl Into<String>) -> Result<Message, Error> {
+    /// Edit `current` to show `content`, or send it as a new message if there's nothing to edit
+    async fn show_reply(
+        ctx: &Context,
+        channel_id: serenity::ChannelId,
+        reply_to: Option<&Message>,
+        current: Option<Message>,
+        content: String,
+    ) -> Result<Message, Error> {
+        let Some(mut current) = current else {
+            return Self::send_reply(ctx, channel_id, reply_to, content).await;
+        };
+
+        tracing::debug!("Editing GPT response message: {}", content);
+        current
+            .edit(ctx, serenity::EditMessage::default().content(content))
+            .await?;
+
+        Ok(current)
+    }
+
+    async fn send_reply(
+        ctx: &Context,
+        channel_id: serenity::ChannelId,
+        reply_to: Option<&Message>,
+        content: impl Into<String>,
+    ) -> Result<Message, Error> {
         let content = content.into();
         tracing::debug!("Sending GPT response message: {}", content);
 
         let mut builder = serenity::CreateMessage::default()
-            .content(content);
-        if message.guild_id.is_some() {
-            builder = builder
-                .reference_message(message)
-                // Disallow mentions
-                .allowed_mentions(serenity::CreateAllowedMentions::default());
+            .content(content)
+            // Disallow mentions
+            .allowed_mentions(serenity::CreateAllowedMentions::default());
+        if let Some(reply_to) = reply_to.filter(|m| m.guild_id.is_some()) {
+            builder = builder.reference_message(reply_to);
         }
-        let result = message
-            .channel_id
+        let result = channel_id
             .send_message(ctx.http(), builder)
             .await?;
         Ok(result)
     }
 
-    async fn get_config(
-        &self,
-        ctx: poise::CooldownContext,
-        user_data: Arc<Data>,
-    ) -> Result<poise::CooldownConfig, Error> {
-        let config = poise::CooldownConfig {
-            global: user_data
-                .settings_manager
-                .get_global(COOLDOWN_KEY)?
-                .map(Duration::from_secs_f32),
-            // don't support bot-wide per-user settings. You can have settings unique to DMs by channel though
-            user: None,
-            guild: match ctx.guild_id {
-                Some(guild_id) => user_data
-                    .settings_manager
-                    .get_guild(guild_id, COOLDOWN_KEY)
-                    .await?
-                    .map(Duration::from_secs_f32),
-                None => None,
-            },
-            channel: user_data
+    /// Save a conversation unless recording is disabled, and delete any that are past retention
+    async fn record_conversation(
+        user_data: &Data,
+        record: ConversationRecord<'_>,
+    ) -> Result<(), Error> {
+        let retention_days: Option<u64> = match record.guild_id {
+            Some(guild_id) => user_data.settings_manager.get_guild(guild_id, RETENTION_KEY).await?,
+            None => user_data.settings_manager.get_channel(record.channel_id, RETENTION_KEY).await?,
+        };
+        let retention_days = retention_days.unwrap_or(DEFAULT_RETENTION_DAYS);
+
+        let (guild_id, channel_id) = (record.guild_id, record.channel_id);
+        let manager = &user_data.conversation_manager;
+        // Without retention nothing is kept, including anything recorded before it was turned off
+        if retention_days > 0 {
+            manager.record(record).await?;
+        }
+
+        let Some(retention_secs) = retention_days.checked_mul(24 * 60 * 60) else {
+            return Ok(()); // Too long to ever expire
+        };
+        let pruned = manager.prune(guild_id, channel_id, Duration::from_secs(retention_secs)).await?;
+        if pruned > 0 {
+            debug!("Pruned {} conversations past retention", pruned);
+        }
+
+        Ok(())
+    }
+
+    /// Backend set by `chat.backend`, otherwise the one serving the model's `provider`
+    async fn get_backend(
+        ctx: &poise::CooldownContext,
+        model: &ModelInfo,
+        user_data: &Data,
+    ) -> Result<Arc<dyn ChatBackend>, Error> {
+        let settings_ctx = SettingsContext {
+            guild_id: ctx.guild_id,
+            channel_id: Some(ctx.channel_id),
+            user_id: Some(ctx.user_id),
+        };
+        let name: SettingsValue<String> = user_data
+            .settings_manager
+            .get_value(settings_ctx, BACKEND_KEY)
+            .await?;
+
+        user_data.backends.get(name.value().as_deref().unwrap_or(&model.provider))
+    }
+
+    async fn get_reply_options(
+        ctx: &poise::CooldownContext,
+        user_data: &Data,
+    ) -> Result<ReplyOptions, Error> {
+        let settings_ctx = || SettingsContext {
+            guild_id: ctx.guild_id,
+            channel_id: Some(ctx.channel_id),
+            user_id: Some(ctx.user_id),
+        };
+        let stream_mode: SettingsValue<StreamMode> = user_data
+            .settings_manager
+            .get_value(settings_ctx(), STREAM_MODE_KEY)
+            .await?;
+        let overflow: SettingsValue<OverflowOptions> = user_data
+            .settings_manager
+            .get_value(settings_ctx(), OVERFLOW_KEY)
+            .await?;
+
+        Ok(ReplyOptions {
+            stream_mode: stream_mode.value().unwrap_or_default(),
+            overflow: overflow.value().clone().unwrap_or_default(),
+        })
+    }
+
+    async fn get_chat_options(
+        ctx: &poise::CooldownContext,
+        model: &ModelInfo,
+        user_data: &Data,
+    ) -> Result<ChatOptions, Error> {
+        let settings_ctx = || SettingsContext {
+            guild_id: ctx.guild_id,
+            channel_id: Some(ctx.channel_id),
+            user_id: Some(ctx.user_id),
+        };
+        let context_tokens: SettingsValue<usize> = user_data
+            .settings_manager
+            .get_value(settings_ctx(), CONTEXT_TOKENS_KEY)
+            .await?;
+
+        // The setting can only lower the budget, never exceed what the model supports
+        let context_tokens = context_tokens
+            .value()
+            .map_or(model.history_budget(), |tokens| tokens.min(model.history_budget()));
+
+        let vision = if model.capabilities.vision {
+            let vision: SettingsValue<VisionOptions> = user_data
                 .settings_manager
+                .get_value(settings_ctx(), VISION_KEY)
+                .await?;
+            vision.value().clone()
+        } else {
+            None
+        };
+
+        let attachments: SettingsValue<AttachmentOptions> = user_data
+            .settings_manager
+            .get_value(settings_ctx(), ATTACHMENTS_KEY)
+            .await?;
+        let attachments = attachments.value().clone().unwrap_or_default();
+
+        Ok(ChatOptions { context_tokens, vision, attachments })
+    }
+
+    /// Buckets for the `chat.cooldown` rate limit set in each scope the message was sent in.
+    ///
+    /// Members without their own limit get the limit of their highest role which has one.
+    /// A member or role limit of `0` exempts them from the server and channel limits entirely.
+    async fn get_rate_limits(
+        ctx: &poise::CooldownContext,
+        serenity_context: &serenity::Context,
+        user_data: &Data,
+    ) -> Result<Vec<Bucket>, Error> {
+        let settings = &user_data.settings_manager;
+        // don't support bot-wide per-user settings. You can have settings unique to DMs by channel though
+        let global = settings
+            .get_global::<RateLimit>(COOLDOWN_KEY)?
+            .map(|limit| Bucket::new("chat", SettingsScopeKind::Global, limit));
+
+        let Some(guild_id) = ctx.guild_id else {
+            let channel = settings
                 .get_channel(ctx.channel_id, COOLDOWN_KEY)
                 .await?
-                .map(Duration::from_secs_f32),
-            member: match ctx.guild_id {
-                Some(guild_id) => user_data
-                    .settings_manager
-                    .get_member(guild_id, ctx.user_id, COOLDOWN_KEY)
-                    .await?
-                    .map(Duration::from_secs_f32),
-                None => None,
-            },
-            __non_exhaustive: (),
+                .map(|limit| Bucket::new("chat", SettingsScopeKind::Channel(ctx.channel_id), limit));
+            return Ok(global.into_iter().chain(channel).collect());
+        };
+
+        let member_limit: Option<RateLimit> = match settings.get_member(guild_id, ctx.user_id, COOLDOWN_KEY).await? {
+            Some(limit) => Some(limit),
+            None => {
+                let roles = guild_id.member(serenity_context, ctx.user_id).await?.roles.into_vec();
+                let tier: SettingsValue<RateLimit> = settings
+                    .get_highest_role(&roles, COOLDOWN_KEY, |lhs, rhs| {
+                        cmp_roles(&serenity_context.cache, Some(guild_id), lhs, rhs)
+                    })
+                    .await?;
+                *tier.value()
+            }
         };
+        if member_limit.is_some_and(|limit| !limit.is_limited()) {
+            return Ok(global.into_iter().collect());
+        }
 
-        Ok(config)
+        let limits = [
+            (SettingsScopeKind::Guild(guild_id), settings.get_guild(guild_id, COOLDOWN_KEY).await?),
+            (SettingsScopeKind::Channel(ctx.channel_id), settings.get_channel(ctx.channel_id, COOLDOWN_KEY).await?),
+            (SettingsScopeKind::Member(guild_id, ctx.user_id), member_limit),
+        ];
+        let buckets = global
+            .into_iter()
+            .chain(limits.into_iter().filter_map(|(scope, limit)| Some(Bucket::new("chat", scope, limit?))))
+            .collect();
+
+        Ok(buckets)
     }
 }
 
-fn find_last_whitespace_before_index(input_str: &str, index: usize) -> Option<usize> {
-    for (i, c) in input_str.char_indices().rev() {
-        if i < index && c.is_whitespace() {
-            return Some(i);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thread_name_strips_mentions() {
+        assert_eq!(thread_name("<@123> how do   I <@!456> bake bread?"), "how do I bake bread?");
+        assert_eq!(thread_name("<@123>"), "Conversation");
+        assert_eq!(thread_name(&"a".repeat(150)).len(), MAX_THREAD_NAME_LENGTH);
+    }
+
+    #[test]
+    fn overflow_options_default_missing_fields() {
+        let options: OverflowOptions = serde_json::from_value(serde_json::json!({ "mode": "thread" })).unwrap();
+        assert_eq!(options.mode, OverflowMode::Thread);
+        assert_eq!(options.max_length, 2 * MAX_MESSAGE_SIZE);
+
+        let mode: StreamMode = serde_json::from_value(serde_json::json!("edit")).unwrap();
+        assert_eq!(mode, StreamMode::Edit);
     }
-    None
 }
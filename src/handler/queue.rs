@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use poise::serenity_prelude::{ChannelId, MessageId};
+use tokio::sync::Notify;
+use crate::Error;
+
+/// Messages waiting in each channel for a chat cooldown to expire, instead of being rejected.
+///
+/// Messages limited by the same rate limit buckets wait in line behind each other.
+/// Messages limited by other buckets, eg another member's cooldown, have a line of their own
+/// so they aren't held up waiting for buckets they don't use.
+#[derive(Default)]
+pub(crate) struct ChatQueue {
+    channels: Mutex<HashMap<ChannelId, ChannelQueue>>,
+    /// Signalled to cancel a queued message, eg because it was deleted
+    cancellations: Mutex<HashMap<MessageId, Arc<Notify>>>,
+}
+
+#[derive(Default)]
+struct ChannelQueue {
+    /// Messages waiting in every line of the channel
+    len: usize,
+    /// Lines keyed by the rate limit buckets of the messages in them
+    lines: HashMap<Vec<String>, Line>,
+}
+
+#[derive(Default)]
+struct Line {
+    len: usize,
+    /// Held by the message at the front of the line.
+    /// Tokio's mutex is fair, so messages get their turn in the order they were queued
+    front: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl ChatQueue {
+    /// Add a message to the back of the line for its `buckets`, unless `max_size` messages are already waiting in the channel
+    pub fn join(
+        &self,
+        channel_id: ChannelId,
+        buckets: &[String],
+        message_id: MessageId,
+        max_size: usize,
+    ) -> Option<QueueTicket<'_>> {
+        let front = {
+            let mut channels = self.channels.lock().unwrap();
+            let queue = channels.entry(channel_id).or_default();
+            if queue.len >= max_size {
+                return None;
+            }
+            queue.len += 1;
+            let line = queue.lines.entry(buckets.to_vec()).or_default();
+            line.len += 1;
+            line.front.clone()
+        };
+
+        let cancelled = Arc::new(Notify::new());
+        self.cancellations.lock().unwrap().insert(message_id, cancelled.clone());
+
+        Some(QueueTicket {
+            queue: self,
+            channel_id,
+            buckets: buckets.to_vec(),
+            message_id,
+            front,
+            cancelled,
+        })
+    }
+
+    /// Cancel a queued message. Does nothing if it isn't queued
+    pub fn cancel(&self, message_id: MessageId) {
+        if let Some(cancelled) = self.cancellations.lock().unwrap().get(&message_id) {
+            cancelled.notify_one();
+        }
+    }
+
+    /// Number of messages waiting in a channel
+    pub fn len(&self, channel_id: ChannelId) -> usize {
+        self.channels.lock().unwrap().get(&channel_id).map_or(0, |queue| queue.len)
+    }
+
+    /// Whether any messages limited by `buckets` are waiting in a channel
+    pub fn is_waiting(&self, channel_id: ChannelId, buckets: &[String]) -> bool {
+        self.channels
+            .lock()
+            .unwrap()
+            .get(&channel_id)
+            .is_some_and(|queue| queue.lines.contains_key(buckets))
+    }
+}
+
+/// A message's place in a [ChatQueue]. It leaves the queue when dropped
+pub(crate) struct QueueTicket<'a> {
+    queue: &'a ChatQueue,
+    channel_id: ChannelId,
+    buckets: Vec<String>,
+    message_id: MessageId,
+    front: Arc<tokio::sync::Mutex<()>>,
+    cancelled: Arc<Notify>,
+}
+
+impl QueueTicket<'_> {
+    /// Wait for this message to reach the front of its line, then keep calling `try_acquire`
+    /// until it succeeds, sleeping for as long as it says is remaining in between.
+    ///
+    /// Returns `false` if the message was cancelled while waiting.
+    pub async fn wait<F, Fut>(&self, try_acquire: F) -> Result<bool, Error>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<Option<Duration>, Error>>,
+    {
+        let cancelled = self.cancelled.notified();
+        tokio::pin!(cancelled);
+
+        let _front = tokio::select! {
+            front = self.front.lock() => front,
+            _ = &mut cancelled => return Ok(false),
+        };
+
+        while let Some(remaining) = try_acquire().await? {
+            tokio::select! {
+                _ = tokio::time::sleep(remaining) => {}
+                _ = &mut cancelled => return Ok(false),
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+impl Drop for QueueTicket<'_> {
+    fn drop(&mut self) {
+        self.queue.cancellations.lock().unwrap().remove(&self.message_id);
+
+        let mut channels = self.queue.channels.lock().unwrap();
+        if let Some(queue) = channels.get_mut(&self.channel_id) {
+            queue.len -= 1;
+            if let Some(line) = queue.lines.get_mut(&self.buckets) {
+                line.len -= 1;
+                if line.len == 0 {
+                    queue.lines.remove(&self.buckets);
+                }
+            }
+            if queue.len == 0 {
+                channels.remove(&self.channel_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use super::*;
+
+    #[test]
+    fn join_is_bounded_per_channel() {
+        let queue = ChatQueue::default();
+        let channel = ChannelId::new(1);
+
+        let first = queue.join(channel, &[], MessageId::new(1), 2);
+        let second = queue.join(channel, &[], MessageId::new(2), 2);
+        assert!(first.is_some() && second.is_some());
+        assert!(queue.join(channel, &[], MessageId::new(3), 2).is_none());
+        assert!(queue.join(ChannelId::new(2), &[], MessageId::new(4), 2).is_some());
+
+        drop(first);
+        assert_eq!(queue.len(channel), 1);
+        assert!(queue.join(channel, &[], MessageId::new(5), 2).is_some());
+    }
+
+    #[tokio::test]
+    async fn wait_retries_until_acquired() {
+        let queue = ChatQueue::default();
+        let ticket = queue.join(ChannelId::new(1), &[], MessageId::new(1), 1).unwrap();
+        let attempts = AtomicU32::new(0);
+
+        let acquired = ticket
+            .wait(|| async {
+                match attempts.fetch_add(1, Ordering::SeqCst) {
+                    0 | 1 => Ok(Some(Duration::from_millis(5))),
+                    _ => Ok(None),
+                }
+            })
+            .await
+            .unwrap();
+
+        assert!(acquired);
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn lines_for_other_buckets_are_not_held_up() {
+        let queue = ChatQueue::default();
+        let channel = ChannelId::new(1);
+        let member = ["chat:member:1".to_string()];
+        let limited = queue.join(channel, &member, MessageId::new(1), 2).unwrap();
+        let other = queue.join(channel, &[], MessageId::new(2), 2).unwrap();
+        assert!(queue.is_waiting(channel, &member));
+        assert_eq!(queue.len(channel), 2);
+
+        let waiting = limited.wait(|| async { Ok(Some(Duration::from_secs(60))) });
+        let acquired = tokio::select! {
+            _ = waiting => unreachable!("the member's cooldown never expires"),
+            acquired = other.wait(|| async { Ok(None) }) => acquired.unwrap(),
+        };
+        assert!(acquired);
+
+        drop(limited);
+        assert!(!queue.is_waiting(channel, &member));
+    }
+
+    #[tokio::test]
+    async fn deleted_messages_are_cancelled() {
+        let queue = ChatQueue::default();
+        let ticket = queue.join(ChannelId::new(1), &[], MessageId::new(1), 1).unwrap();
+
+        queue.cancel(MessageId::new(1));
+        let acquired = ticket
+            .wait(|| async { Ok(Some(Duration::from_secs(60))) })
+            .await
+            .unwrap();
+
+        assert!(!acquired);
+        drop(ticket);
+        assert_eq!(queue.len(ChannelId::new(1)), 0);
+    }
+}
@@ -7,6 +7,7 @@ mod metrics;
 mod settings;
 
 mod permissions;
+mod rate_limit;
 #[cfg(test)]
 mod test_util;
 mod util;
@@ -22,13 +23,16 @@ use octocrab::Octocrab;
 use tracing::{error, info};
 
 use crate::permissions::PermissionsManager;
-use crate::settings::config::FaultybotConfig;
+use crate::rate_limit::{MemoryStore, PostgresStore, RateLimitStore, RateLimiter};
+use crate::settings::config::{FaultybotConfig, RateLimitStoreKind};
 use poise::serenity_prelude as serenity;
 use settings::manager::SettingsManager;
 use tracing_subscriber::EnvFilter;
 
 use database::Database;
-use crate::gpt::PersonaManager;
+use crate::gpt::{AttachmentCache, ConversationManager, ImageGenerator, ModelRegistry, PersonaManager, SummaryManager, UsageManager};
+use crate::gpt::backend::{Backends, OpenAiImageBackend};
+use crate::gpt::tools::ToolRegistry;
 
 type Error = error::FaultyBotError;
 type Context<'a> = poise::Context<'a, Data, Error>;
@@ -41,6 +45,14 @@ pub struct Data {
     config: FaultybotConfig,
     octocrab: Option<Octocrab>,
     persona_manager: PersonaManager,
+    summary_manager: SummaryManager,
+    attachment_cache: AttachmentCache,
+    conversation_manager: ConversationManager,
+    usage_manager: UsageManager,
+    backends: Backends,
+    model_registry: ModelRegistry,
+    tools: ToolRegistry,
+    image_generator: Option<ImageGenerator>,
 }
 
 #[derive(Debug, clap::Parser)]
@@ -74,8 +86,6 @@ async fn main() {
 
     init_metrics(&settings);
 
-    openai::set_key(settings.openai.key.clone());
-
     let octocrab = settings.github.as_ref().map(|gh| Octocrab::builder()
         .personal_token(gh.token.clone())
         .build()
@@ -90,6 +100,32 @@ async fn main() {
         .expect("Failed to update db to latest schema");
     info!("Database is connected and up-to-date");
 
+    let model_registry = ModelRegistry::load(&db, &settings.models)
+        .await
+        .expect("Failed to load models");
+
+    let backends = Backends::from_config(&settings);
+
+    let rate_limit_store: Arc<dyn RateLimitStore> = match settings.rate_limits.store {
+        RateLimitStoreKind::Database => Arc::new(PostgresStore::new(db.clone())),
+        RateLimitStoreKind::Memory => Arc::new(MemoryStore::new()),
+    };
+    let rate_limiter = RateLimiter::new(rate_limit_store);
+
+    let image_generator = settings.images.as_ref().map(|images| {
+        ImageGenerator::new(
+            Arc::new(OpenAiImageBackend::new(
+                images.base_url.clone().or_else(|| settings.openai.base_url.clone()),
+                images.key.clone().or_else(|| Some(settings.openai.key.clone())),
+                images.model.clone(),
+                images.size.clone(),
+            )),
+            rate_limiter.clone(),
+        )
+    });
+
+    let permissions_manager = PermissionsManager::new(db.clone(), &settings.policies);
+
     let options = poise::FrameworkOptions {
         commands: commands::commands_vec(&settings),
         event_handler: |ctx, event| {
@@ -128,11 +164,19 @@ async fn main() {
         .framework(poise::Framework::new(options))
         .data(Arc::new(Data {
             config: settings,
-            handler: handler::Handler::new(),
+            handler: handler::Handler::new(rate_limiter),
             settings_manager: SettingsManager::new(config, db.clone()),
-            permissions_manager: PermissionsManager::new(db.clone()),
+            permissions_manager,
             octocrab,
             persona_manager: PersonaManager::new(db.clone()),
+            summary_manager: SummaryManager::new(db.clone()),
+            attachment_cache: AttachmentCache::new(),
+            conversation_manager: ConversationManager::new(db.clone()),
+            usage_manager: UsageManager::new(db.clone()),
+            backends,
+            model_registry,
+            tools: ToolRegistry::new(),
+            image_generator,
         }))
         .await
         .expect("Failed to create Poise Framework");
@@ -1,9 +1,11 @@
+pub mod pattern;
 pub mod policy;
 pub mod policy_manager;
 
 use crate::error::UserError;
 use crate::permissions::policy::{Effect, PolicyProvider};
 use crate::permissions::policy_manager::PolicyManager;
+use crate::settings::config::GlobalPolicy;
 use crate::{Data, Error};
 use poise::serenity_prelude as serenity;
 use serenity::{ChannelId, GuildId, UserId};
@@ -23,6 +25,9 @@ pub enum Permission {
     UsePersona(Option<String>),
     DeletePersona(Option<String>),
     UseModel(Option<String>),
+    UseTool(Option<String>),
+    GenerateImage,
+    ViewUsage(Option<String>),
 }
 
 impl Permission {
@@ -40,6 +45,9 @@ impl Permission {
             Permission::UsePersona(_) => "persona.use",
             Permission::DeletePersona(_) => "persona.delete",
             Permission::UseModel(_) => "model.use",
+            Permission::UseTool(_) => "tool.use",
+            Permission::GenerateImage => "image.generate",
+            Permission::ViewUsage(_) => "usage.view",
         }
     }
 
@@ -55,6 +63,8 @@ impl Permission {
             Permission::UsePersona(specifier) => specifier,
             Permission::DeletePersona(specifier) => specifier,
             Permission::UseModel(specifier) => specifier,
+            Permission::UseTool(specifier) => specifier,
+            Permission::ViewUsage(specifier) => specifier,
             _ => &None,
         };
 
@@ -104,9 +114,10 @@ pub struct PermissionsManager {
 impl PermissionsManager {
     pub fn new(
         db: crate::database::Database,
+        policies: &[GlobalPolicy],
     ) -> Self {
         Self {
-            policy_manager: PolicyManager::new(db),
+            policy_manager: PolicyManager::new(db, policies),
         }
     }
 
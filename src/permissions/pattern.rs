@@ -0,0 +1,182 @@
+//! Patterns matching the actions a [Policy](super::policy::Policy) applies to.
+//!
+//! Actions look like `persona.use:Sassy`, ie `.` separated segments followed by an optional `:` specifier.
+//! Patterns use the same format, where:
+//! - a `*` segment matches exactly one segment, eg `persona.*` matches `persona.use` and `persona.edit`
+//! - a `**` segment matches any number of segments, eg `**` matches every action
+//! - `*` in the specifier matches any characters, eg `settings.set:chat.*`
+//! - no specifier matches any specifier or none, eg `chat` matches `chat` and `chat:assistant`
+//!
+//! The empty pattern used by the default policy matches everything, and is less specific than any other.
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ActionPattern<'a> {
+    explicit: bool,
+    segments: Vec<Segment<'a>>,
+    specifier: Option<&'a str>,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Segment<'a> {
+    Literal(&'a str),
+    /// `*`
+    One,
+    /// `**`
+    Many,
+}
+
+/// How specific an [ActionPattern] is. Compared field by field, in order
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Specificity {
+    /// `false` only for the default policy's empty pattern
+    explicit: bool,
+    literal_segments: usize,
+    wildcard_segments: usize,
+    /// Whether there's no `**`, which could match any number of segments
+    bounded: bool,
+    specifier_literals: usize,
+    has_specifier: bool,
+}
+
+impl<'a> ActionPattern<'a> {
+    pub fn new(pattern: &'a str) -> Self {
+        if pattern.is_empty() {
+            return Self {
+                explicit: false,
+                segments: vec![Segment::Many],
+                specifier: None,
+            };
+        }
+
+        let (action, specifier) = match pattern.split_once(':') {
+            Some((action, specifier)) => (action, Some(specifier)),
+            None => (pattern, None),
+        };
+
+        let segments = action
+            .split('.')
+            .map(|segment| match segment {
+                "*" => Segment::One,
+                "**" => Segment::Many,
+                literal => Segment::Literal(literal),
+            })
+            .collect();
+
+        Self {
+            explicit: true,
+            segments,
+            specifier,
+        }
+    }
+
+    /// Whether a concrete action, such as [Permission](super::Permission)'s display, matches this pattern
+    pub fn matches(&self, action: &str) -> bool {
+        let (action, specifier) = match action.split_once(':') {
+            Some((action, specifier)) => (action, Some(specifier)),
+            None => (action, None),
+        };
+
+        let segments = action.split('.').collect::<Vec<_>>();
+        if !matches_segments(&self.segments, &segments) {
+            return false;
+        }
+
+        match (self.specifier, specifier) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(pattern), Some(specifier)) => matches_glob(pattern.as_bytes(), specifier.as_bytes()),
+        }
+    }
+
+    pub fn specificity(&self) -> Specificity {
+        let count = |kind: fn(&Segment) -> bool| self.segments.iter().filter(|s| kind(s)).count();
+
+        Specificity {
+            explicit: self.explicit,
+            literal_segments: count(|s| matches!(s, Segment::Literal(_))),
+            wildcard_segments: count(|s| matches!(s, Segment::One)),
+            bounded: !self.segments.contains(&Segment::Many),
+            specifier_literals: self.specifier.map_or(0, |s| s.chars().filter(|c| *c != '*').count()),
+            has_specifier: self.specifier.is_some(),
+        }
+    }
+}
+
+fn matches_segments(pattern: &[Segment], action: &[&str]) -> bool {
+    match (pattern.first(), action.first()) {
+        (None, None) => true,
+        (None, Some(_)) => false,
+        (Some(Segment::Many), _) => {
+            // Try matching no segments, then consuming one more at a time
+            (0..=action.len()).any(|skip| matches_segments(&pattern[1..], &action[skip..]))
+        }
+        (Some(_), None) => false,
+        (Some(Segment::One), Some(_)) => matches_segments(&pattern[1..], &action[1..]),
+        (Some(Segment::Literal(literal)), Some(segment)) => {
+            literal == segment && matches_segments(&pattern[1..], &action[1..])
+        }
+    }
+}
+
+fn matches_glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|skip| matches_glob(rest, &text[skip..])),
+        Some((c, rest)) => text.first() == Some(c) && matches_glob(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, action: &str) -> bool {
+        ActionPattern::new(pattern).matches(action)
+    }
+
+    #[test]
+    fn segments_match_whole_words() {
+        assert!(matches("chat", "chat"));
+        assert!(matches("chat", "chat:assistant"));
+        assert!(!matches("chat", "chatlog"));
+        assert!(!matches("persona", "persona.use"));
+        assert!(matches("persona.*", "persona.use:Sassy"));
+        assert!(!matches("persona.*", "persona"));
+        assert!(matches("**", "settings.set:chat.cooldown"));
+        assert!(matches("settings.**", "settings"));
+        assert!(matches("", "chat"));
+    }
+
+    #[test]
+    fn specifier_globs() {
+        assert!(matches("persona.*:Sassy", "persona.edit:Sassy"));
+        assert!(!matches("persona.*:Sassy", "persona.edit:Nice"));
+        assert!(!matches("persona.*:Sassy", "persona.edit"));
+        assert!(matches("**:Sassy", "persona.use:Sassy"));
+        assert!(matches("settings.set:chat.*", "settings.set:chat.cooldown"));
+        assert!(!matches("settings.set:chat.*", "settings.set:quota.user_daily_tokens"));
+        assert!(matches("chat:*", "chat:"));
+    }
+
+    #[test]
+    fn specificity_order() {
+        let ordered = [
+            "",
+            "**",
+            "**:Sassy",
+            "persona.**",
+            "persona.*",
+            "persona.*:Sassy",
+            "persona.use",
+            "persona.use:*",
+            "persona.use:Sass*",
+            "persona.use:Sassy",
+        ];
+
+        for pair in ordered.windows(2) {
+            let lhs = ActionPattern::new(pair[0]).specificity();
+            let rhs = ActionPattern::new(pair[1]).specificity();
+            assert!(lhs < rhs, "{} should be less specific than {}", pair[0], pair[1]);
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use crate::permissions::pattern::{ActionPattern, Specificity};
 use chrono::Utc;
 use poise::serenity_prelude::{ChannelId, GuildId, Mentionable, RoleId, UserId};
 use std::cmp::Ordering;
@@ -9,6 +10,8 @@ pub enum Principle {
     Guild(GuildId),
     Channel(ChannelId),
     Role(RoleId),
+    /// Members with a role, while in a specific channel
+    RoleInChannel(RoleId, ChannelId),
     Member(GuildId, UserId),
 }
 
@@ -19,12 +22,30 @@ impl Display for Principle {
             Principle::Guild(_) => write!(f, "This guild"),
             Principle::Channel(channel_id) => write!(f, "{}", channel_id.mention()),
             Principle::Role(role_id) => write!(f, "{}", role_id.mention()),
+            Principle::RoleInChannel(role_id, channel_id) => {
+                write!(f, "{} in {}", role_id.mention(), channel_id.mention())
+            }
             Principle::Member(_, user_id) => write!(f, "{}", user_id.mention()),
         }
     }
 }
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+impl Principle {
+    /// How specific policies for this kind of principle are, higher is more specific
+    fn rank(&self) -> u8 {
+        match self {
+            Principle::Global => 0,
+            Principle::Guild(_) => 1,
+            Principle::Channel(_) => 2,
+            Principle::Role(_) => 3,
+            Principle::RoleInChannel(_, _) => 4,
+            Principle::Member(_, _) => 5,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Effect {
     Allow,
     Deny,
@@ -39,6 +60,8 @@ pub struct Policy {
 }
 
 impl Policy {
+    /// The policy which takes precedence over all others, according to [`Policy::precedence`].
+    /// Expired policies are ignored. Defaults to [`Policy::default`]
     pub fn combined<I: Into<Self>>(
         policies: impl IntoIterator<Item = I>,
         cmp_role_id: impl Fn(RoleId, RoleId) -> Ordering,
@@ -47,57 +70,137 @@ impl Policy {
             .into_iter()
             .map(I::into)
             .reduce(|acc, e| acc.merge_with(e, &cmp_role_id))
+            .filter(Policy::is_valid)
             .unwrap_or_default()
     }
 
-    /// Merge this [`Policy`] with another by choosing the most "specific" [`Policy`].
-    /// Specificity is determined by, in-order:
-    /// 1. Whichever [`Policy.action`] is longer
-    /// 2. Whichever [`Policy.principle`] is greater according to [`PartialOrd`]
-    /// 3. Whichever [`Principle::Role`] is greater Discord's role hierarchy
-    /// 4. Otherwise, EXPLODE
+    /// Merge this [`Policy`] with another by choosing whichever takes precedence.
+    /// Equally specific policies with different effects are logged as a conflict
     pub fn merge_with<I: Into<Self>>(
         self,
         other: I,
-        cmp_role_id: impl Fn(RoleId, RoleId) -> std::cmp::Ordering,
+        cmp_role_id: impl Fn(RoleId, RoleId) -> Ordering,
     ) -> Self {
         let other = other.into();
 
         if !other.is_valid() {
-            self
-        } else if other.action.len() > self.action.len() || other.principle > self.principle {
-            other
-        } else if other.action.len() < self.action.len() || other.principle < self.principle {
-            self
-        } else if other.effect != self.effect {
-            match (self.principle, other.principle) {
-                (Principle::Role(self_role), Principle::Role(other_role)) => {
-                    match cmp_role_id(self_role, other_role) {
-                        Ordering::Less => other,
-                        Ordering::Equal => {
-                            tracing::warn!(
-                            "Found matching policies for same role.\nPolicy1: {:?}\nPolicy2: {:?}",
-                            self, other
-                        );
-                            // Just re-use exising role if they match (shouldn't be possible)}
-                            self
-                        }
-                        Ordering::Greater => self,
-                    }
-                }
-                _ => {
-                    tracing::error!("Conflicting effects for policy with match specificity. Defaulting to first seen");
-                    unreachable!();
-                }
-            }
-        } else {
-            tracing::error!(
-                "Found conflicting policies.\nPolicy1: {:?}\nPolicy2: {:?}",
+            return self;
+        } else if !self.is_valid() {
+            return other;
+        }
+
+        let (ordering, precedence) = self.precedence(&other, cmp_role_id);
+        if precedence.is_conflict() && self.effect != other.effect {
+            tracing::warn!(
+                "Found conflicting policies, resolved by {:?}.\nPolicy1: {:?}\nPolicy2: {:?}",
+                precedence,
                 self,
                 other
             );
-            unreachable!();
         }
+
+        match ordering {
+            Ordering::Less => other,
+            Ordering::Equal | Ordering::Greater => self,
+        }
+    }
+
+    /// Compare which of two policies takes precedence, and the rule which decided it.
+    /// [`Ordering::Greater`] means `self` takes precedence. Rules are applied in-order:
+    /// 1. Whichever [`Policy.action`] pattern is more specific, see [`Specificity`]
+    /// 2. Whichever [`Policy.principle`] is more specific,
+    ///    ie member over role in channel over role over channel over guild over global
+    /// 3. Whichever [`Principle::Role`] or [`Principle::RoleInChannel`] is higher in Discord's role hierarchy
+    /// 4. Otherwise the policies conflict, and [`Effect::Deny`] wins
+    /// 5. Then whichever lasts longest, without an [`Policy.until`] being forever
+    /// 6. Then whichever [`Policy.action`] and [`Policy.principle`] sort last, so the result never depends on order
+    ///
+    /// Only identical policies are equal.
+    pub fn precedence(
+        &self,
+        other: &Self,
+        cmp_role_id: impl Fn(RoleId, RoleId) -> Ordering,
+    ) -> (Ordering, Precedence) {
+        let specificity = self.specificity().cmp(&other.specificity());
+        if specificity.is_ne() {
+            return (specificity, Precedence::Action);
+        }
+
+        let principle = self.principle.rank().cmp(&other.principle.rank());
+        if principle.is_ne() {
+            return (principle, Precedence::Principle);
+        }
+
+        let roles = match (self.principle, other.principle) {
+            (Principle::Role(self_role), Principle::Role(other_role)) => Some((self_role, other_role)),
+            (Principle::RoleInChannel(self_role, _), Principle::RoleInChannel(other_role, _)) => {
+                Some((self_role, other_role))
+            }
+            _ => None,
+        };
+        if let Some((self_role, other_role)) = roles {
+            let role = cmp_role_id(self_role, other_role);
+            if role.is_ne() {
+                return (role, Precedence::Role);
+            }
+        }
+
+        let effect = match (self.effect, other.effect) {
+            (Effect::Deny, Effect::Allow) => Ordering::Greater,
+            (Effect::Allow, Effect::Deny) => Ordering::Less,
+            _ => Ordering::Equal,
+        };
+        if effect.is_ne() {
+            return (effect, Precedence::Deny);
+        }
+
+        let until = match (self.until, other.until) {
+            (None, None) => Ordering::Equal,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(_), None) => Ordering::Less,
+            (Some(lhs), Some(rhs)) => lhs.cmp(&rhs),
+        };
+        if until.is_ne() {
+            return (until, Precedence::Until);
+        }
+
+        let order = (&self.action, self.principle).cmp(&(&other.action, other.principle));
+        (order, Precedence::Order)
+    }
+
+    /// Combine `candidates` like [`Policy::combined`], and explain why each one did or didn't win.
+    pub fn explain(
+        candidates: Vec<Self>,
+        cmp_role_id: impl Fn(RoleId, RoleId) -> Ordering,
+    ) -> (Self, Vec<(Self, Outcome)>) {
+        let winner = Self::combined(candidates.clone(), &cmp_role_id);
+        let mut won = false;
+
+        let explained = candidates
+            .into_iter()
+            .map(|candidate| {
+                let outcome = if !candidate.is_valid() {
+                    Outcome::Expired
+                } else if !won && candidate == winner {
+                    won = true;
+                    Outcome::Won
+                } else {
+                    Outcome::Lost(winner.precedence(&candidate, &cmp_role_id).1)
+                };
+                (candidate, outcome)
+            })
+            .collect();
+
+        (winner, explained)
+    }
+
+    /// Whether this policy's action pattern matches a concrete action
+    pub fn applies_to(&self, action: &str) -> bool {
+        ActionPattern::new(&self.action).matches(action)
+    }
+
+    pub fn specificity(&self) -> Specificity {
+        ActionPattern::new(&self.action).specificity()
     }
 
     pub fn is_valid(&self) -> bool {
@@ -121,6 +224,71 @@ impl Default for Policy {
     }
 }
 
+/// The rule deciding which of two policies takes precedence. See [`Policy::precedence`]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Precedence {
+    Action,
+    Principle,
+    Role,
+    Deny,
+    Until,
+    Order,
+}
+
+impl Precedence {
+    /// Whether the policies were equally specific, so neither should really apply over the other
+    pub fn is_conflict(&self) -> bool {
+        matches!(self, Precedence::Deny | Precedence::Until | Precedence::Order)
+    }
+}
+
+/// Why a candidate policy did or didn't become the effective policy
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Outcome {
+    Won,
+    Expired,
+    /// Lost to the winning policy, by this rule
+    Lost(Precedence),
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Outcome::Won => write!(f, "won"),
+            Outcome::Expired => write!(f, "ignored, it has expired"),
+            Outcome::Lost(Precedence::Action) => write!(f, "lost to a policy for a more specific action"),
+            Outcome::Lost(Precedence::Principle) => write!(f, "lost to a policy for a more specific principle"),
+            Outcome::Lost(Precedence::Role) => write!(f, "lost to a policy for a higher role"),
+            Outcome::Lost(Precedence::Deny) => write!(f, "**conflicts** with an equally specific policy, deny wins"),
+            Outcome::Lost(Precedence::Until) => write!(f, "**conflicts** with an equally specific policy which lasts longer"),
+            Outcome::Lost(Precedence::Order) => write!(f, "**conflicts** with an equally specific policy"),
+        }
+    }
+}
+
+/// Compares two roles by their position in the guild's role hierarchy, according to the cache.
+///
+/// Roles missing from the cache are lower than any that are cached.
+pub fn cmp_roles(
+    cache: &poise::serenity_prelude::Cache,
+    guild_id: Option<GuildId>,
+    lhs: RoleId,
+    rhs: RoleId,
+) -> Ordering {
+    let Some(guild) = guild_id.and_then(|g| cache.guild(g)) else {
+        return Ordering::Equal;
+    };
+    let roles = &guild.roles;
+    let lhs = roles.get(&lhs);
+    let rhs = roles.get(&rhs);
+    match (lhs, rhs) {
+        (Some(_), None) => Ordering::Greater,
+        (None, Some(_)) => Ordering::Less,
+        (Some(lhs), Some(rhs)) => lhs.cmp(rhs),
+        (None, None) => Ordering::Equal, // Just assume equal I guess w/e
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct PolicyContext {
     pub guild_id: Option<GuildId>,
@@ -141,12 +309,37 @@ pub trait PolicyProvider<E> {
         ctx: PolicyContext,
         action: String,
     ) -> Result<Policy, E>
+    where
+        E: Send + Sync + 'static,
+    {
+        let guild_id = ctx.guild_id;
+        let policies = self.candidate_policies(ctx, action).await?;
+
+        let combined = Policy::combined(policies, |lhs, rhs| {
+            cmp_roles(&serenity_context.cache, guild_id, lhs, rhs)
+        });
+
+        if !combined.is_valid() {
+            tracing::error!(
+                "Combining policies preferred an expired policy. {:?}",
+                combined
+            );
+            return Ok(Policy::default());
+        }
+
+        Ok(combined)
+    }
+
+    /// Every policy considered by [`PolicyProvider::effective_policy`], starting with the default
+    async fn candidate_policies(&self, ctx: PolicyContext, action: String) -> Result<Vec<Policy>, E>
     where
         E: Send + Sync + 'static,
     {
         // Start with default policy which denies all actions
         let mut policies = vec![Policy::default()];
 
+        policies.extend(self.global_policies(action.clone()).await?);
+
         if let Some(channel_id) = ctx.channel_id {
             policies.extend(self.channel_policies(channel_id, action.clone()).await?);
         }
@@ -162,6 +355,15 @@ pub trait PolicyProvider<E> {
             }
         }
 
+        if let Some(channel_id) = ctx.channel_id {
+            if !ctx.roles.is_empty() {
+                policies.extend(
+                    self.role_channel_policies(channel_id, &ctx.roles, action.clone())
+                        .await?,
+                );
+            }
+        }
+
         let role_futs = ctx
             .roles
             .into_iter()
@@ -178,32 +380,10 @@ pub trait PolicyProvider<E> {
 
         policies.extend(role_policies);
 
-        let combined = Policy::combined(policies, |lhs, rhs| {
-            let Some(guild) = ctx.guild_id.and_then(|g| serenity_context.cache.guild(g)) else {
-                return Ordering::Equal;
-            };
-            let roles = &guild.roles;
-            let lhs = roles.get(&lhs);
-            let rhs = roles.get(&rhs);
-            match (lhs, rhs) {
-                (Some(_), None) => Ordering::Greater,
-                (None, Some(_)) => Ordering::Less,
-                (Some(lhs), Some(rhs)) => lhs.cmp(rhs),
-                (None, None) => Ordering::Equal, // Just assume equal I guess w/e
-            }
-        });
-
-        if !combined.is_valid() {
-            tracing::error!(
-                "Combining policies preferred an expired policy. {:?}",
-                combined
-            );
-            return Ok(Policy::default());
-        }
-
-        Ok(combined)
+        Ok(policies)
     }
 
+    async fn global_policies(&self, action: String) -> Result<Vec<Policy>, E>;
     async fn guild_policies(&self, guild_id: GuildId, action: String) -> Result<Vec<Policy>, E>;
     async fn channel_policies(
         &self,
@@ -211,6 +391,13 @@ pub trait PolicyProvider<E> {
         action: String,
     ) -> Result<Vec<Policy>, E>;
     async fn role_policies(&self, role_id: RoleId, action: String) -> Result<Vec<Policy>, E>;
+    /// Policies for any of `roles` in a channel
+    async fn role_channel_policies(
+        &self,
+        channel_id: ChannelId,
+        roles: &[RoleId],
+        action: String,
+    ) -> Result<Vec<Policy>, E>;
     async fn member_policies(
         &self,
         guild_id: GuildId,
@@ -250,7 +437,31 @@ mod tests {
     }
 
     #[test]
-    fn policy_merge_by_action_length() {
+    fn role_in_channel_is_between_role_and_member() {
+        let policy = |principle| Policy {
+            principle,
+            action: "chat".to_string(),
+            effect: Effect::Allow,
+            until: None,
+        };
+        let channel = policy(Principle::Channel(123.into()));
+        let role = policy(Principle::Role(9.into()));
+        let role_in_channel = policy(Principle::RoleInChannel(1.into(), 123.into()));
+        let member = policy(Principle::Member(5.into(), 7.into()));
+
+        assert_eq!(channel.merge_with(role_in_channel.clone(), role_cmp), role_in_channel);
+        assert_eq!(role.merge_with(role_in_channel.clone(), role_cmp), role_in_channel);
+        assert_eq!(role_in_channel.clone().merge_with(member.clone(), role_cmp), member);
+
+        let higher_role_in_channel = policy(Principle::RoleInChannel(2.into(), 123.into()));
+        assert_eq!(
+            role_in_channel.merge_with(higher_role_in_channel.clone(), role_cmp),
+            higher_role_in_channel
+        );
+    }
+
+    #[test]
+    fn policy_merge_by_action_specificity() {
         let global_deny = Policy::default();
 
         let global_action_allow = Policy {
@@ -263,4 +474,136 @@ mod tests {
             global_action_allow
         );
     }
+
+    #[test]
+    fn explain_gives_reason_for_each_candidate() {
+        let guild_allow = Policy {
+            principle: Principle::Guild(5.into()),
+            action: "chat".to_string(),
+            effect: Effect::Allow,
+            until: None,
+        };
+        let channel_deny = Policy {
+            principle: Principle::Channel(123.into()),
+            action: "chat".to_string(),
+            ..Default::default()
+        };
+        let expired = Policy {
+            principle: Principle::Member(5.into(), 7.into()),
+            action: "chat:assistant".to_string(),
+            effect: Effect::Allow,
+            until: Some(chrono::DateTime::UNIX_EPOCH.fixed_offset()),
+        };
+
+        let candidates = vec![
+            Policy::default(),
+            guild_allow.clone(),
+            channel_deny.clone(),
+            expired.clone(),
+        ];
+        let (winner, explained) = Policy::explain(candidates, role_cmp);
+
+        assert_eq!(winner, channel_deny);
+        assert_eq!(
+            explained,
+            vec![
+                (Policy::default(), Outcome::Lost(Precedence::Action)),
+                (guild_allow, Outcome::Lost(Precedence::Principle)),
+                (channel_deny, Outcome::Won),
+                (expired, Outcome::Expired),
+            ]
+        );
+    }
+
+    #[test]
+    fn conflicting_policies_prefer_deny() {
+        let allow = Policy {
+            principle: Principle::Member(5.into(), 7.into()),
+            action: "chat".to_string(),
+            effect: Effect::Allow,
+            until: None,
+        };
+        let deny = Policy {
+            action: "chat:*".to_string(),
+            effect: Effect::Deny,
+            ..allow.clone()
+        };
+        let allow_specifier = Policy {
+            action: "chat:*".to_string(),
+            ..allow.clone()
+        };
+
+        assert_eq!(allow.clone().merge_with(deny.clone(), role_cmp), deny);
+        assert_eq!(deny.clone().merge_with(allow_specifier.clone(), role_cmp), deny);
+        assert_eq!(allow_specifier.merge_with(deny.clone(), role_cmp), deny);
+    }
+
+    mod proptests {
+        use super::*;
+        use proptest::prelude::*;
+
+        fn principle() -> impl Strategy<Value = Principle> {
+            prop_oneof![
+                Just(Principle::Global),
+                (1..3u64).prop_map(|id| Principle::Guild(id.into())),
+                (1..3u64).prop_map(|id| Principle::Channel(id.into())),
+                (1..4u64).prop_map(|id| Principle::Role(id.into())),
+                (1..4u64, 1..3u64).prop_map(|(role, channel)| Principle::RoleInChannel(role.into(), channel.into())),
+                (1..3u64, 1..3u64).prop_map(|(guild, user)| Principle::Member(guild.into(), user.into())),
+            ]
+        }
+
+        fn policy() -> impl Strategy<Value = Policy> {
+            let action = prop::sample::select(vec!["", "**", "chat", "chat:*", "chat:a*", "chat:*a", "*:assistant"]);
+            let effect = prop_oneof![Just(Effect::Allow), Just(Effect::Deny)];
+            // Expired, forever, or expiring in one of a couple of days
+            let until = prop::option::of(-1..3i64).prop_map(|days| {
+                days.map(|days| (Utc::now() + chrono::Duration::days(days)).fixed_offset())
+            });
+
+            (principle(), action, effect, until).prop_map(|(principle, action, effect, until)| Policy {
+                principle,
+                action: action.to_string(),
+                effect,
+                until,
+            })
+        }
+
+        fn policies() -> impl Strategy<Value = Vec<Policy>> {
+            prop::collection::vec(policy(), 0..12)
+        }
+
+        proptest! {
+            #[test]
+            fn combined_is_order_independent(
+                (policies, shuffled) in policies().prop_flat_map(|policies| {
+                    (Just(policies.clone()), Just(policies).prop_shuffle())
+                })
+            ) {
+                prop_assert_eq!(
+                    Policy::combined(policies, role_cmp),
+                    Policy::combined(shuffled, role_cmp)
+                );
+            }
+
+            #[test]
+            fn combined_takes_precedence_over_every_valid_policy(policies in policies()) {
+                let combined = Policy::combined(policies.clone(), role_cmp);
+
+                prop_assert!(combined.is_valid());
+                prop_assert!(combined == Policy::default() || policies.contains(&combined));
+                for policy in policies.iter().filter(|policy| policy.is_valid()) {
+                    let (ordering, _) = combined.precedence(policy, role_cmp);
+                    prop_assert_ne!(ordering, Ordering::Less);
+                }
+            }
+
+            #[test]
+            fn precedence_is_antisymmetric(lhs in policy(), rhs in policy()) {
+                let (ordering, precedence) = lhs.precedence(&rhs, role_cmp);
+                prop_assert_eq!(rhs.precedence(&lhs, role_cmp), (ordering.reverse(), precedence));
+                prop_assert_eq!(ordering == Ordering::Equal, lhs == rhs);
+            }
+        }
+    }
 }
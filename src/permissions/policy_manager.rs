@@ -1,35 +1,64 @@
 use crate::error::UserError;
 use crate::permissions::policy::{Policy, PolicyProvider, Principle};
+use crate::settings::config::GlobalPolicy;
 use crate::util::{Fromi64, Toi64};
 use crate::Error;
 use entities::sea_orm_active_enums::Effect as DbEffect;
-use entities::{channel_policy, guild_policy, member_policy, role_policy};
+use entities::{
+    channel_policy, global_policy, guild_policy, member_policy, role_channel_policy, role_policy,
+};
 use poise::serenity_prelude::{ChannelId, GuildId, RoleId, UserId};
 use sea_orm::sea_query::OnConflict;
-use sea_orm::{
-    sea_query, ActiveValue, ColumnTrait, EntityName, EntityTrait, IntoActiveValue, QueryFilter,
-};
+use sea_orm::{ActiveValue, ColumnTrait, Condition, EntityTrait, IntoActiveValue, QueryFilter};
 
 pub struct PolicyManager {
     db: crate::database::Database,
+    /// Global policies from the `policies` config section
+    configured: Vec<Policy>,
 }
 
 impl PolicyManager {
-    pub fn new(db: crate::database::Database) -> Self {
-        Self { db }
+    pub fn new(db: crate::database::Database, policies: &[GlobalPolicy]) -> Self {
+        let configured = policies
+            .iter()
+            .filter(|policy| {
+                // Would be just as specific as the default policy, which it can't be merged with
+                let valid = !policy.action.is_empty();
+                if !valid {
+                    tracing::warn!("Ignoring configured policy without an action: {:?}", policy);
+                }
+                valid
+            })
+            .map(|policy| Policy {
+                principle: Principle::Global,
+                action: policy.action.clone(),
+                effect: policy.effect,
+                until: None,
+            })
+            .collect();
+
+        Self { db, configured }
     }
 
+    /// Save a policy, replacing any existing one for the same principle and action.
+    ///
+    /// Callers are responsible for only letting bot owners save [Principle::Global] policies.
     pub async fn save_policy(&self, policy: &Policy) -> Result<(), Error> {
+        if policy.action.is_empty() {
+            return Err(UserError::invalid_input("Policies must have an action").into());
+        }
+
         match policy.principle {
-            Principle::Global => {
-                let msg = "Changing global bot permissions not currently supported";
-                return Err(UserError::invalid_input(msg).into());
-            }
+            Principle::Global => self.save_global_policy(policy.clone()).await?,
             Principle::Guild(guild_id) => self.save_guild_policy(policy.clone(), guild_id).await?,
             Principle::Channel(channel_id) => {
                 self.save_channel_policy(policy.clone(), channel_id).await?
             }
             Principle::Role(role_id) => self.save_role_policy(policy.clone(), role_id).await?,
+            Principle::RoleInChannel(role_id, channel_id) => {
+                self.save_role_channel_policy(policy.clone(), role_id, channel_id)
+                    .await?
+            }
             Principle::Member(guild_id, user_id) => {
                 self.save_member_policy(policy.clone(), guild_id, user_id)
                     .await?
@@ -66,6 +95,14 @@ impl PolicyManager {
                     .exec(self.db.connection())
                     .await?
             }
+            Principle::RoleInChannel(role_id, channel_id) => {
+                role_channel_policy::Entity::delete_many()
+                    .filter(role_channel_policy::Column::RoleId.eq(role_id.to_i64()))
+                    .filter(role_channel_policy::Column::ChannelId.eq(channel_id.to_i64()))
+                    .filter(role_channel_policy::Column::Action.eq(action))
+                    .exec(self.db.connection())
+                    .await?
+            }
             Principle::Member(guild_id, user_id) => {
                 member_policy::Entity::delete_many()
                     .filter(member_policy::Column::GuildId.eq(guild_id.to_i64()))
@@ -75,9 +112,10 @@ impl PolicyManager {
                     .await?
             }
             Principle::Global => {
-                return Err(
-                    UserError::access_denied("Cannot modify global policies").into(),
-                )
+                global_policy::Entity::delete_many()
+                    .filter(global_policy::Column::Action.eq(action))
+                    .exec(self.db.connection())
+                    .await?
             }
         };
 
@@ -130,6 +168,37 @@ impl PolicyManager {
         Ok(())
     }
 
+    async fn save_role_channel_policy(
+        &self,
+        policy: Policy,
+        role_id: RoleId,
+        channel_id: ChannelId,
+    ) -> Result<(), Error> {
+        role_channel_policy::Entity::insert(role_channel_policy::ActiveModel {
+            role_id: role_id.to_i64().into_active_value(),
+            channel_id: channel_id.to_i64().into_active_value(),
+            action: policy.action.into_active_value(),
+            effect: ActiveValue::Set(DbEffect::from(policy.effect)),
+            until: policy.until.into_active_value(),
+            ..Default::default()
+        })
+        .on_conflict(
+            OnConflict::columns([
+                role_channel_policy::Column::RoleId,
+                role_channel_policy::Column::ChannelId,
+                role_channel_policy::Column::Action,
+            ])
+            .update_columns([
+                role_channel_policy::Column::Effect,
+                role_channel_policy::Column::Until,
+            ])
+            .to_owned(),
+        )
+        .exec(self.db.connection())
+        .await?;
+        Ok(())
+    }
+
     async fn save_channel_policy(
         &self,
         policy: Policy,
@@ -158,6 +227,23 @@ impl PolicyManager {
         Ok(())
     }
 
+    async fn save_global_policy(&self, policy: Policy) -> Result<(), Error> {
+        global_policy::Entity::insert(global_policy::ActiveModel {
+            action: policy.action.into_active_value(),
+            effect: ActiveValue::Set(DbEffect::from(policy.effect)),
+            until: policy.until.into_active_value(),
+            ..Default::default()
+        })
+        .on_conflict(
+            OnConflict::column(global_policy::Column::Action)
+                .update_columns([global_policy::Column::Effect, global_policy::Column::Until])
+                .to_owned(),
+        )
+        .exec(self.db.connection())
+        .await?;
+        Ok(())
+    }
+
     async fn save_guild_policy(&self, policy: Policy, guild_id: GuildId) -> Result<(), Error> {
         guild_policy::Entity::insert(guild_policy::ActiveModel {
             guild_id: guild_id.to_i64().into_active_value(),
@@ -179,18 +265,32 @@ impl PolicyManager {
 
 #[poise::async_trait]
 impl PolicyProvider<Error> for PolicyManager {
+    async fn global_policies(&self, action: String) -> Result<Vec<Policy>, Error> {
+        let saved = global_policy::Entity::find()
+            .filter(first_segment_filter(global_policy::Column::Action, &action))
+            .all(self.db.connection())
+            .await?
+            .into_iter()
+            .map(Policy::from)
+            .filter(|policy| policy.applies_to(&action))
+            .collect();
+
+        Ok(with_configured(saved, &self.configured, &action))
+    }
+
     async fn guild_policies(
         &self,
         guild_id: GuildId,
         action: String,
     ) -> Result<Vec<Policy>, Error> {
         let policies = guild_policy::Entity::find()
-            .filter(build_like(guild_policy::Entity, action))
+            .filter(first_segment_filter(guild_policy::Column::Action, &action))
             .filter(guild_policy::Column::GuildId.eq(guild_id.to_i64()))
             .all(self.db.connection())
             .await?
             .into_iter()
             .map(Policy::from)
+            .filter(|policy| policy.applies_to(&action))
             .collect();
 
         Ok(policies)
@@ -202,12 +302,13 @@ impl PolicyProvider<Error> for PolicyManager {
         action: String,
     ) -> Result<Vec<Policy>, Error> {
         let policies = channel_policy::Entity::find()
-            .filter(build_like(channel_policy::Entity, action))
+            .filter(first_segment_filter(channel_policy::Column::Action, &action))
             .filter(channel_policy::Column::ChannelId.eq(channel_id.to_i64()))
             .all(self.db.connection())
             .await?
             .into_iter()
             .map(Policy::from)
+            .filter(|policy| policy.applies_to(&action))
             .collect();
 
         Ok(policies)
@@ -215,12 +316,33 @@ impl PolicyProvider<Error> for PolicyManager {
 
     async fn role_policies(&self, role_id: RoleId, action: String) -> Result<Vec<Policy>, Error> {
         let policies = role_policy::Entity::find()
-            .filter(build_like(role_policy::Entity, action))
+            .filter(first_segment_filter(role_policy::Column::Action, &action))
             .filter(role_policy::Column::RoleId.eq(role_id.to_i64()))
             .all(self.db.connection())
             .await?
             .into_iter()
             .map(Policy::from)
+            .filter(|policy| policy.applies_to(&action))
+            .collect();
+
+        Ok(policies)
+    }
+
+    async fn role_channel_policies(
+        &self,
+        channel_id: ChannelId,
+        roles: &[RoleId],
+        action: String,
+    ) -> Result<Vec<Policy>, Error> {
+        let policies = role_channel_policy::Entity::find()
+            .filter(first_segment_filter(role_channel_policy::Column::Action, &action))
+            .filter(role_channel_policy::Column::ChannelId.eq(channel_id.to_i64()))
+            .filter(role_channel_policy::Column::RoleId.is_in(roles.iter().map(|role_id| role_id.to_i64())))
+            .all(self.db.connection())
+            .await?
+            .into_iter()
+            .map(Policy::from)
+            .filter(|policy| policy.applies_to(&action))
             .collect();
 
         Ok(policies)
@@ -233,23 +355,48 @@ impl PolicyProvider<Error> for PolicyManager {
         action: String,
     ) -> Result<Vec<Policy>, Error> {
         let policies = member_policy::Entity::find()
-            .filter(build_like(member_policy::Entity, action))
+            .filter(first_segment_filter(member_policy::Column::Action, &action))
             .filter(member_policy::Column::GuildId.eq(guild_id.to_i64()))
             .filter(member_policy::Column::UserId.eq(user_id.to_i64()))
             .all(self.db.connection())
             .await?
             .into_iter()
             .map(Policy::from)
+            .filter(|policy| policy.applies_to(&action))
             .collect();
 
         Ok(policies)
     }
 }
 
-fn build_like(entity: impl EntityName, action: String) -> sea_query::SimpleExpr {
-    let expr = format!(r#"$1 LIKE "{}"."action" || '%'"#, entity.table_name());
+/// Narrow down the policies which could apply to `action` to those starting with the same segment, or a wildcard.
+///
+/// Patterns are only fully matched by [Policy::applies_to] once loaded, so this only needs to let through
+/// a superset of them. `%` or `_` in the segment widening the `LIKE`s is fine.
+fn first_segment_filter(column: impl ColumnTrait, action: &str) -> Condition {
+    let first = action.split(['.', ':']).next().unwrap_or_default();
+
+    Condition::any()
+        .add(column.eq(first))
+        .add(column.starts_with(format!("{}.", first)))
+        .add(column.starts_with(format!("{}:", first)))
+        .add(column.starts_with("*"))
+}
+
+/// Add the configured global policies which apply to `action` to those `saved` in the database.
+///
+/// Saved policies take precedence over configured ones for the same action,
+/// so bot owners can override the config without a restart.
+fn with_configured(mut saved: Vec<Policy>, configured: &[Policy], action: &str) -> Vec<Policy> {
+    let configured = configured
+        .iter()
+        .filter(|policy| policy.applies_to(action))
+        .filter(|policy| !saved.iter().any(|saved| saved.action == policy.action))
+        .cloned()
+        .collect::<Vec<_>>();
 
-    sea_query::Expr::cust_with_values(expr, [action])
+    saved.extend(configured);
+    saved
 }
 
 impl From<super::policy::Effect> for entities::sea_orm_active_enums::Effect {
@@ -271,6 +418,17 @@ impl From<entities::sea_orm_active_enums::Effect> for super::policy::Effect {
     }
 }
 
+impl From<global_policy::Model> for Policy {
+    fn from(model: global_policy::Model) -> Self {
+        Self {
+            principle: super::policy::Principle::Global,
+            action: model.action,
+            effect: super::policy::Effect::from(model.effect),
+            until: model.until,
+        }
+    }
+}
+
 impl From<guild_policy::Model> for Policy {
     fn from(model: guild_policy::Model) -> Self {
         Self {
@@ -293,6 +451,20 @@ impl From<role_policy::Model> for Policy {
     }
 }
 
+impl From<role_channel_policy::Model> for Policy {
+    fn from(model: role_channel_policy::Model) -> Self {
+        Self {
+            principle: super::policy::Principle::RoleInChannel(
+                RoleId::from_i64(model.role_id),
+                ChannelId::from_i64(model.channel_id),
+            ),
+            action: model.action,
+            effect: super::policy::Effect::from(model.effect),
+            until: model.until,
+        }
+    }
+}
+
 impl From<channel_policy::Model> for Policy {
     fn from(model: channel_policy::Model) -> Self {
         Self {
@@ -317,3 +489,37 @@ impl From<member_policy::Model> for Policy {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::permissions::policy::Effect;
+
+    fn global(action: &str, effect: Effect) -> Policy {
+        Policy {
+            principle: Principle::Global,
+            action: action.to_string(),
+            effect,
+            until: None,
+        }
+    }
+
+    #[test]
+    fn saved_global_policies_override_configured() {
+        let configured = [
+            global("chat", Effect::Allow),
+            global("persona.use", Effect::Allow),
+            global("image.generate", Effect::Allow),
+        ];
+        let saved = vec![global("chat", Effect::Deny)];
+
+        assert_eq!(
+            with_configured(saved, &configured, "chat:assistant"),
+            vec![global("chat", Effect::Deny)],
+        );
+        assert_eq!(
+            with_configured(vec![], &configured, "persona.use:assistant"),
+            vec![global("persona.use", Effect::Allow)],
+        );
+    }
+}
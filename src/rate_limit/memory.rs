@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use tokio::sync::Mutex;
+use crate::Error;
+use super::{take, Bucket, BucketState, RateLimitStore};
+
+/// [RateLimitStore] which keeps buckets in memory.
+///
+/// Limits are reset when the bot restarts and aren't shared with any other process.
+#[derive(Default)]
+pub struct MemoryStore {
+    /// State of each bucket and when it expires
+    buckets: Mutex<HashMap<String, (BucketState, DateTime<Utc>)>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[poise::async_trait]
+impl RateLimitStore for MemoryStore {
+    async fn try_acquire(
+        &self,
+        buckets: &[Bucket],
+        now: DateTime<Utc>,
+    ) -> Result<Option<Duration>, Error> {
+        let mut states = self.buckets.lock().await;
+        // Buckets which have refilled are the same as ones which were never used
+        states.retain(|_, (_, expires_at)| *expires_at > now);
+
+        let current = buckets
+            .iter()
+            .map(|bucket| states.get(&bucket.key).map(|(state, _)| *state))
+            .collect::<Vec<_>>();
+
+        match take(buckets, &current, now) {
+            Ok(updated) => {
+                for (bucket, state) in buckets.iter().zip(updated) {
+                    states.insert(bucket.key.clone(), (state, state.expires_at(&bucket.limit)));
+                }
+                Ok(None)
+            }
+            Err(remaining) => Ok(Some(remaining)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rate_limit::RateLimit;
+    use crate::settings::SettingsScopeKind;
+
+    #[tokio::test]
+    async fn buckets_are_shared_by_key() {
+        let store = MemoryStore::new();
+        let limit = RateLimit { burst: 1, refill: Duration::from_secs(60) };
+        let now = Utc::now();
+        let chat = || Bucket::new("chat", SettingsScopeKind::Global, limit);
+        let other = Bucket::new("imagine", SettingsScopeKind::Global, limit);
+
+        assert_eq!(store.try_acquire(&[chat()], now).await.unwrap(), None);
+        assert_eq!(store.try_acquire(&[chat()], now).await.unwrap(), Some(Duration::from_secs(60)));
+        assert_eq!(store.try_acquire(&[other], now).await.unwrap(), None);
+        assert_eq!(store.try_acquire(&[chat()], now + chrono::Duration::seconds(60)).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,258 @@
+mod memory;
+mod postgres;
+
+use std::sync::Arc;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use crate::Error;
+use crate::error::UserError;
+use crate::settings::SettingsScopeKind;
+
+pub use memory::MemoryStore;
+pub use postgres::PostgresStore;
+
+/// A token bucket: up to `burst` requests can be made at once, after which one more is allowed
+/// every `refill`.
+///
+/// Settings such as `chat.cooldown` are either a number of seconds between requests, or an object
+/// like `{"burst": 3, "refill_seconds": 20}`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(from = "RateLimitSetting")]
+pub struct RateLimit {
+    pub burst: u32,
+    pub refill: Duration,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RateLimitSetting {
+    Cooldown(f32),
+    Bucket { burst: u32, refill_seconds: f32 },
+}
+
+impl From<RateLimitSetting> for RateLimit {
+    fn from(setting: RateLimitSetting) -> Self {
+        let (burst, refill_seconds) = match setting {
+            RateLimitSetting::Cooldown(seconds) => (1, seconds),
+            RateLimitSetting::Bucket { burst, refill_seconds } => (burst, refill_seconds),
+        };
+
+        Self {
+            burst: burst.max(1),
+            refill: Duration::try_from_secs_f32(refill_seconds).unwrap_or_default(),
+        }
+    }
+}
+
+impl RateLimit {
+    /// Whether this limits anything at all. A refill time of zero allows unlimited requests
+    pub fn is_limited(&self) -> bool {
+        !self.refill.is_zero()
+    }
+
+    /// Tokens in a bucket which had `state` once it's been refilled up to `now`.
+    /// Buckets without any state are full.
+    pub fn tokens_at(&self, state: Option<BucketState>, now: DateTime<Utc>) -> f64 {
+        let Some(state) = state else {
+            return self.burst as f64;
+        };
+
+        let elapsed = (now - state.updated_at).to_std().unwrap_or_default();
+        let refilled = elapsed.as_secs_f64() / self.refill.as_secs_f64();
+        (state.tokens + refilled).min(self.burst as f64)
+    }
+
+    /// Time until a bucket holding `tokens` has at least `target` tokens
+    fn time_until(&self, tokens: f64, target: f64) -> Duration {
+        let missing = (target - tokens).max(0.0);
+        Duration::try_from_secs_f64(missing * self.refill.as_secs_f64()).unwrap_or(Duration::MAX)
+    }
+}
+
+/// A single bucket of tokens which requests are taken from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bucket {
+    /// Identifies the bucket in a [RateLimitStore], eg `chat:guild:1234`
+    pub key: String,
+    pub limit: RateLimit,
+}
+
+impl Bucket {
+    /// Bucket for the `name` rate limit (eg `chat`) shared by everyone in `scope`
+    pub fn new(name: &str, scope: SettingsScopeKind, limit: RateLimit) -> Self {
+        let key = match scope {
+            SettingsScopeKind::Global => format!("{}:global", name),
+            SettingsScopeKind::Guild(guild_id) => format!("{}:guild:{}", name, guild_id),
+            SettingsScopeKind::Channel(channel_id) => format!("{}:channel:{}", name, channel_id),
+            SettingsScopeKind::Role(role_id) => format!("{}:role:{}", name, role_id),
+            SettingsScopeKind::Member(guild_id, user_id) => {
+                format!("{}:member:{}:{}", name, guild_id, user_id)
+            }
+        };
+
+        Self { key, limit }
+    }
+}
+
+/// Tokens left in a bucket as of `updated_at`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BucketState {
+    pub tokens: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl BucketState {
+    /// When this bucket will be full again, after which its state no longer needs to be kept
+    pub fn expires_at(&self, limit: &RateLimit) -> DateTime<Utc> {
+        let until_full = limit.time_until(self.tokens, limit.burst as f64);
+        chrono::Duration::from_std(until_full)
+            .ok()
+            .and_then(|until_full| self.updated_at.checked_add_signed(until_full))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+}
+
+/// Take one token from each of `buckets`, whose current state is `states` (in the same order).
+///
+/// Either every bucket has a token taken and their new states are returned,
+/// or none are touched and the time until all of them have a token is returned instead.
+pub fn take(
+    buckets: &[Bucket],
+    states: &[Option<BucketState>],
+    now: DateTime<Utc>,
+) -> Result<Vec<BucketState>, Duration> {
+    let tokens = buckets
+        .iter()
+        .zip(states)
+        .map(|(bucket, state)| bucket.limit.tokens_at(*state, now))
+        .collect::<Vec<_>>();
+
+    let remaining = buckets
+        .iter()
+        .zip(&tokens)
+        .map(|(bucket, tokens)| bucket.limit.time_until(*tokens, 1.0))
+        .max()
+        .unwrap_or_default();
+    if !remaining.is_zero() {
+        return Err(remaining);
+    }
+
+    Ok(tokens
+        .into_iter()
+        .map(|tokens| BucketState {
+            tokens: tokens - 1.0,
+            updated_at: now,
+        })
+        .collect())
+}
+
+/// Where the state of rate limit buckets is kept
+#[poise::async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Atomically [take] a token from each of `buckets`.
+    ///
+    /// Returns how long until a request would be allowed if any bucket is empty.
+    async fn try_acquire(
+        &self,
+        buckets: &[Bucket],
+        now: DateTime<Utc>,
+    ) -> Result<Option<Duration>, Error>;
+}
+
+/// Applies [RateLimit]s to requests, keeping the buckets in a [RateLimitStore]
+/// so they can be shared between processes and survive restarts.
+#[derive(Clone)]
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+}
+
+impl RateLimiter {
+    pub fn new(store: Arc<dyn RateLimitStore>) -> Self {
+        Self { store }
+    }
+
+    /// Take a token from every bucket, failing with [UserError::CooldownHit] if any of them are empty
+    pub async fn acquire(&self, buckets: &[Bucket]) -> Result<(), Error> {
+        if let Some(remaining) = self.try_acquire(buckets).await? {
+            return Err(UserError::cooldown_hit(remaining).into());
+        }
+
+        Ok(())
+    }
+
+    /// Take a token from every bucket, or return how long until that's possible if any of them are empty
+    pub async fn try_acquire(&self, buckets: &[Bucket]) -> Result<Option<Duration>, Error> {
+        let buckets = buckets
+            .iter()
+            .filter(|bucket| bucket.limit.is_limited())
+            .cloned()
+            .collect::<Vec<_>>();
+        if buckets.is_empty() {
+            return Ok(None);
+        }
+
+        self.store.try_acquire(&buckets, Utc::now()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use poise::serenity_prelude::ChannelId;
+    use super::*;
+
+    fn limit(burst: u32, refill_seconds: u64) -> RateLimit {
+        RateLimit {
+            burst,
+            refill: Duration::from_secs(refill_seconds),
+        }
+    }
+
+    #[test]
+    fn rate_limit_deserializes_cooldowns_and_buckets() {
+        let cooldown: RateLimit = serde_json::from_str("2.5").unwrap();
+        assert_eq!(cooldown, RateLimit { burst: 1, refill: Duration::from_millis(2500) });
+
+        let bucket: RateLimit = serde_json::from_str(r#"{"burst": 3, "refill_seconds": 20}"#).unwrap();
+        assert_eq!(bucket, limit(3, 20));
+
+        let disabled: RateLimit = serde_json::from_str("0").unwrap();
+        assert!(!disabled.is_limited());
+    }
+
+    #[test]
+    fn take_allows_bursts_then_refills() {
+        let start = Utc::now();
+        let buckets = [Bucket::new("chat", SettingsScopeKind::Global, limit(2, 10))];
+
+        let first = take(&buckets, &[None], start).unwrap();
+        let second = take(&buckets, &[Some(first[0])], start).unwrap();
+        assert_eq!(second[0].tokens, 0.0);
+
+        let remaining = take(&buckets, &[Some(second[0])], start + chrono::Duration::seconds(4));
+        assert_eq!(remaining, Err(Duration::from_secs(6)));
+
+        let refilled = take(&buckets, &[Some(second[0])], start + chrono::Duration::seconds(10)).unwrap();
+        assert_eq!(refilled[0].tokens, 0.0);
+    }
+
+    #[test]
+    fn take_is_all_or_nothing() {
+        let now = Utc::now();
+        let buckets = [
+            Bucket::new("chat", SettingsScopeKind::Global, limit(5, 1)),
+            Bucket::new("chat", SettingsScopeKind::Channel(ChannelId::new(1)), limit(1, 30)),
+        ];
+        let empty = BucketState { tokens: 0.0, updated_at: now };
+
+        assert_eq!(take(&buckets, &[None, Some(empty)], now), Err(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn state_expires_once_full() {
+        let now = Utc::now();
+        let state = BucketState { tokens: 1.0, updated_at: now };
+
+        assert_eq!(state.expires_at(&limit(3, 10)), now + chrono::Duration::seconds(20));
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ColumnTrait, EntityTrait, IntoActiveValue, QueryFilter, QueryOrder, QuerySelect, TransactionTrait};
+use entities::rate_limit_bucket;
+use crate::Error;
+use super::{take, Bucket, BucketState, RateLimitStore};
+
+/// [RateLimitStore] which keeps buckets in the database, so limits survive restarts
+/// and are shared by every process connected to it.
+pub struct PostgresStore {
+    db: crate::database::Database,
+}
+
+impl PostgresStore {
+    pub fn new(db: crate::database::Database) -> Self {
+        Self { db }
+    }
+}
+
+#[poise::async_trait]
+impl RateLimitStore for PostgresStore {
+    async fn try_acquire(
+        &self,
+        buckets: &[Bucket],
+        now: DateTime<Utc>,
+    ) -> Result<Option<Duration>, Error> {
+        // Buckets which have refilled are the same as ones which were never used
+        rate_limit_bucket::Entity::delete_many()
+            .filter(rate_limit_bucket::Column::ExpiresAt.lte(now))
+            .exec(self.db.connection())
+            .await?;
+
+        let mut sorted = buckets.iter().collect::<Vec<_>>();
+        sorted.sort_by(|a, b| a.key.cmp(&b.key));
+        sorted.dedup_by(|a, b| a.key == b.key);
+
+        let txn = self.db.connection().begin().await?;
+
+        // Create missing buckets first so that they can be locked like any other,
+        // always in the same order so concurrent requests can't deadlock
+        rate_limit_bucket::Entity::insert_many(sorted.iter().map(|bucket| rate_limit_bucket::ActiveModel {
+            key: bucket.key.clone().into_active_value(),
+            tokens: (bucket.limit.burst as f64).into_active_value(),
+            updated_at: now.fixed_offset().into_active_value(),
+            expires_at: now.fixed_offset().into_active_value(),
+        }))
+        .on_conflict(OnConflict::column(rate_limit_bucket::Column::Key).do_nothing().to_owned())
+        .exec_without_returning(&txn)
+        .await?;
+
+        let rows = rate_limit_bucket::Entity::find()
+            .filter(rate_limit_bucket::Column::Key.is_in(sorted.iter().map(|bucket| bucket.key.clone())))
+            .order_by_asc(rate_limit_bucket::Column::Key)
+            .lock_exclusive()
+            .all(&txn)
+            .await?;
+
+        let current = buckets
+            .iter()
+            .map(|bucket| {
+                rows.iter()
+                    .find(|row| row.key == bucket.key)
+                    .map(|row| BucketState {
+                        tokens: row.tokens,
+                        updated_at: row.updated_at.to_utc(),
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        let updated = match take(buckets, &current, now) {
+            Ok(updated) => updated,
+            Err(remaining) => {
+                txn.rollback().await?;
+                return Ok(Some(remaining));
+            }
+        };
+
+        for (bucket, state) in buckets.iter().zip(updated) {
+            // Upsert in case another process pruned the bucket before it was locked
+            rate_limit_bucket::Entity::insert(rate_limit_bucket::ActiveModel {
+                key: bucket.key.clone().into_active_value(),
+                tokens: state.tokens.into_active_value(),
+                updated_at: state.updated_at.fixed_offset().into_active_value(),
+                expires_at: state.expires_at(&bucket.limit).fixed_offset().into_active_value(),
+            })
+            .on_conflict(
+                OnConflict::column(rate_limit_bucket::Column::Key)
+                    .update_columns([
+                        rate_limit_bucket::Column::Tokens,
+                        rate_limit_bucket::Column::UpdatedAt,
+                        rate_limit_bucket::Column::ExpiresAt,
+                    ])
+                    .to_owned(),
+            )
+            .exec(&txn)
+            .await?;
+        }
+
+        txn.commit().await?;
+
+        Ok(None)
+    }
+}
@@ -35,7 +35,111 @@ pub(crate) struct GitHub {
 
 #[derive(Debug, Default, Deserialize)]
 pub(crate) struct OpenAI {
+    #[serde(default)]
     pub(crate) key: String,
+    /// Alternate OpenAI-compatible endpoint (ie a local Ollama or vLLM server)
+    pub(crate) base_url: Option<String>,
+}
+
+/// An additional LLM server that chats can be routed to
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct Backend {
+    #[serde(default)]
+    pub(crate) kind: BackendKind,
+    /// Defaults to the provider's official API
+    pub(crate) base_url: Option<String>,
+    pub(crate) key: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum BackendKind {
+    /// OpenAI Chat Completions API or any server compatible with it
+    #[default]
+    OpenAI,
+    /// Anthropic Messages API
+    Anthropic,
+}
+
+/// Image generation using the OpenAI Images API or any server compatible with it
+#[derive(Debug, Deserialize)]
+pub(crate) struct Images {
+    /// Defaults to the `openai` base URL
+    pub(crate) base_url: Option<String>,
+    /// Defaults to the `openai` key
+    pub(crate) key: Option<String>,
+    #[serde(default = "default_image_model")]
+    pub(crate) model: String,
+    #[serde(default = "default_image_size")]
+    pub(crate) size: String,
+}
+
+fn default_image_model() -> String {
+    "dall-e-3".to_string()
+}
+
+fn default_image_size() -> String {
+    "1024x1024".to_string()
+}
+
+/// An LLM model made available to personas
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Model {
+    pub(crate) name: String,
+    /// Name of the backend serving this model
+    #[serde(default = "default_provider")]
+    pub(crate) provider: String,
+    /// Maximum number of tokens the model accepts (prompt + completion)
+    pub(crate) context_window: u32,
+    #[serde(default)]
+    pub(crate) pricing: ModelPricing,
+    #[serde(default)]
+    pub(crate) capabilities: ModelCapabilities,
+}
+
+fn default_provider() -> String {
+    crate::gpt::backend::Backends::DEFAULT.to_string()
+}
+
+/// Cost in USD per million tokens
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub(crate) struct ModelPricing {
+    #[serde(default)]
+    pub(crate) prompt: f64,
+    #[serde(default)]
+    pub(crate) completion: f64,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Deserialize)]
+pub(crate) struct ModelCapabilities {
+    #[serde(default)]
+    pub(crate) vision: bool,
+    #[serde(default)]
+    pub(crate) tools: bool,
+}
+
+/// A policy applying to everyone everywhere, eg to allow everyone to chat by default
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct GlobalPolicy {
+    pub(crate) action: String,
+    pub(crate) effect: crate::permissions::policy::Effect,
+}
+
+/// How rate limits such as `chat.cooldown` are tracked
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct RateLimits {
+    #[serde(default)]
+    pub(crate) store: RateLimitStoreKind,
+}
+
+#[derive(Debug, Default, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum RateLimitStoreKind {
+    /// Kept in the database, shared by every process using it and across restarts
+    #[default]
+    Database,
+    /// Kept in memory, separately by each process
+    Memory,
 }
 
 #[derive(Debug, Default, Deserialize)]
@@ -56,6 +160,15 @@ pub struct FaultybotConfig {
     pub(crate) discord: Discord,
     pub(crate) github: Option<GitHub>,
     pub(crate) openai: OpenAI,
+    #[serde(default)]
+    pub(crate) backends: HashMap<String, Backend>,
+    #[serde(default)]
+    pub(crate) models: Vec<Model>,
+    pub(crate) images: Option<Images>,
+    #[serde(default)]
+    pub(crate) rate_limits: RateLimits,
+    #[serde(default)]
+    pub(crate) policies: Vec<GlobalPolicy>,
     pub(crate) prometheus: Option<Prometheus>,
     pub(crate) statsd: Option<Statsd>,
 }
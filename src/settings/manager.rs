@@ -1,13 +1,14 @@
 use crate::settings::{
     merge_strategies, MergeFn, SettingsContext, SettingsScopeKind, SettingsValue,
 };
-use crate::util::Toi64;
+use crate::util::{Fromi64, Toi64};
 use crate::{settings, Error};
-use entities::{channel_settings, guild_settings, member_settings};
-use poise::serenity_prelude::{ChannelId, GuildId, UserId};
+use entities::{channel_settings, guild_settings, member_settings, role_settings};
+use poise::serenity_prelude::{ChannelId, GuildId, RoleId, UserId};
 use sea_orm::sea_query::OnConflict;
 use sea_orm::{ColumnTrait, EntityTrait, IntoActiveValue, QueryFilter};
 use serde::de::DeserializeOwned;
+use std::cmp::Ordering;
 use std::sync::Arc;
 
 pub struct SettingsManager {
@@ -196,6 +197,98 @@ impl SettingsManager {
         Ok(())
     }
 
+    pub async fn get_role<T: DeserializeOwned>(
+        &self,
+        role_id: RoleId,
+        key: &str,
+    ) -> Result<Option<T>, Error> {
+        let role_id = role_id.to_i64();
+        let entry = role_settings::Entity::find()
+            .filter(role_settings::Column::RoleId.eq(role_id))
+            .filter(role_settings::Column::Key.eq(key))
+            .one(self.db.connection())
+            .await?;
+
+        let value = match entry {
+            Some(model) => Some(serde_json::from_value(model.value)?),
+            None => None,
+        };
+
+        Ok(value)
+    }
+
+    /// Value of `key` set for whichever of `roles` is highest, according to `cmp_role_id`.
+    ///
+    /// Roles without `key` set are skipped, so this is the highest role with a value.
+    pub async fn get_highest_role<T: DeserializeOwned>(
+        &self,
+        roles: &[RoleId],
+        key: &str,
+        cmp_role_id: impl Fn(RoleId, RoleId) -> Ordering,
+    ) -> Result<SettingsValue<T>, Error> {
+        if roles.is_empty() {
+            return Ok(SettingsValue::new(None, SettingsScopeKind::Global));
+        }
+
+        let entry = role_settings::Entity::find()
+            .filter(role_settings::Column::RoleId.is_in(roles.iter().map(|role_id| role_id.to_i64())))
+            .filter(role_settings::Column::Key.eq(key))
+            .all(self.db.connection())
+            .await?
+            .into_iter()
+            .max_by(|lhs, rhs| cmp_role_id(RoleId::from_i64(lhs.role_id), RoleId::from_i64(rhs.role_id)));
+
+        let value = match entry {
+            Some(model) => SettingsValue::new(
+                Some(serde_json::from_value(model.value)?),
+                SettingsScopeKind::Role(RoleId::from_i64(model.role_id)),
+            ),
+            None => SettingsValue::new(None, SettingsScopeKind::Global),
+        };
+
+        Ok(value)
+    }
+
+    pub async fn set_role<T: serde::Serialize>(
+        &self,
+        role_id: RoleId,
+        key: String,
+        value: Option<T>,
+    ) -> Result<(), Error> {
+        if let Some(value) = value {
+            let json = serde_json::to_value(value)?;
+            let model = role_settings::ActiveModel {
+                role_id: role_id.to_i64().into_active_value(),
+                key: key.into_active_value(),
+                value: json.into_active_value(),
+                ..Default::default()
+            };
+
+            role_settings::Entity::insert(model)
+                .on_conflict(
+                    OnConflict::columns(vec![
+                        role_settings::Column::RoleId,
+                        role_settings::Column::Key,
+                    ])
+                    .update_column(role_settings::Column::Value)
+                    .to_owned(),
+                )
+                .exec(self.db.connection())
+                .await?;
+        } else {
+            role_settings::Entity::delete_many()
+                .filter(
+                    sea_orm::Condition::all()
+                        .add(role_settings::Column::RoleId.eq(role_id.to_i64()))
+                        .add(role_settings::Column::Key.eq(key)),
+                )
+                .exec(self.db.connection())
+                .await?;
+        }
+
+        Ok(())
+    }
+
     pub async fn get_member<T: DeserializeOwned>(
         &self,
         guild_id: GuildId,
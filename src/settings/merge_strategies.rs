@@ -9,11 +9,12 @@ impl<V> MergeFn<V> for MostSpecific {
     }
 }
 
+/// Chooses the smallest value set in any scope, eg to apply the strictest limit
 pub struct Smallest;
 
 impl<V: PartialOrd> MergeFn<V> for Smallest {
     fn merge(&self, lhs: &V, rhs: &V) -> MergeDecision {
-        if rhs > lhs {
+        if rhs < lhs {
             MergeDecision::Right
         } else {
             MergeDecision::Left
@@ -21,6 +22,7 @@ impl<V: PartialOrd> MergeFn<V> for Smallest {
     }
 }
 
+/// Chooses the largest value set in any scope
 pub struct Largest;
 
 impl<V: PartialOrd> MergeFn<V> for Largest {
@@ -32,3 +34,23 @@ impl<V: PartialOrd> MergeFn<V> for Largest {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(strategy: impl MergeFn<u32>, lhs: u32, rhs: u32) -> u32 {
+        match strategy.merge(&lhs, &rhs) {
+            MergeDecision::Left => lhs,
+            MergeDecision::Right => rhs,
+        }
+    }
+
+    #[test]
+    fn smallest_and_largest() {
+        assert_eq!(merge(Smallest, 5, 3), 3);
+        assert_eq!(merge(Smallest, 3, 5), 3);
+        assert_eq!(merge(Largest, 5, 3), 5);
+        assert_eq!(merge(Largest, 3, 5), 5);
+    }
+}
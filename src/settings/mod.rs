@@ -3,7 +3,7 @@ pub(crate) mod config;
 pub(crate) mod manager;
 pub mod merge_strategies;
 
-use poise::serenity_prelude::{ChannelId, GuildId, Mentionable, UserId};
+use poise::serenity_prelude::{ChannelId, GuildId, Mentionable, RoleId, UserId};
 use serde::de::DeserializeOwned;
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
@@ -11,6 +11,7 @@ pub enum SettingsScopeKind {
     Global,
     Guild(GuildId),
     Channel(ChannelId),
+    Role(RoleId),
     Member(GuildId, UserId),
 }
 
@@ -20,6 +21,7 @@ impl std::fmt::Display for SettingsScopeKind {
             SettingsScopeKind::Global => write!(f, "Global"),
             SettingsScopeKind::Guild(_) => write!(f, "this server"),
             SettingsScopeKind::Channel(channel_id) => write!(f, "{}", channel_id.mention()),
+            SettingsScopeKind::Role(role_id) => write!(f, "{}", role_id.mention()),
             SettingsScopeKind::Member(_, user_id) => {
                 write!(f, "{} in this server", user_id.mention())
             }
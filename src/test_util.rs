@@ -18,3 +18,92 @@ pub async fn test_database() -> crate::database::Database {
 
     db
 }
+
+/// [ChatBackend](crate::gpt::backend::ChatBackend) which replies with pre-scripted responses
+/// and records every conversation it was asked to complete.
+///
+/// Usage is reported as one prompt token per message and one completion token per word streamed,
+/// or per response when not streaming.
+#[derive(Default)]
+pub struct ScriptedBackend {
+    responses: std::sync::Mutex<std::collections::VecDeque<openai::chat::ChatCompletionMessage>>,
+    requests: std::sync::Mutex<Vec<Vec<crate::gpt::ChatMessage>>>,
+}
+
+impl ScriptedBackend {
+    pub fn new<S: Into<String>>(responses: impl IntoIterator<Item = S>) -> Self {
+        Self::from_messages(responses.into_iter().map(|content| openai::chat::ChatCompletionMessage {
+            role: openai::chat::ChatCompletionMessageRole::Assistant,
+            content: Some(content.into()),
+            name: None,
+            function_call: None,
+        }))
+    }
+
+    /// Reply with complete messages, eg to script function calls
+    pub fn from_messages(responses: impl IntoIterator<Item = openai::chat::ChatCompletionMessage>) -> Self {
+        Self {
+            responses: std::sync::Mutex::new(responses.into_iter().collect()),
+            requests: Default::default(),
+        }
+    }
+
+    /// All conversations sent to this backend, in order
+    pub fn requests(&self) -> Vec<Vec<crate::gpt::ChatMessage>> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn next_response(&self, messages: &[crate::gpt::ChatMessage]) -> openai::chat::ChatCompletionMessage {
+        self.requests.lock().unwrap().push(messages.to_vec());
+        self.responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .expect("ScriptedBackend ran out of responses")
+    }
+}
+
+#[poise::async_trait]
+impl crate::gpt::backend::ChatBackend for ScriptedBackend {
+    async fn completion(
+        &self,
+        _model: &str,
+        messages: &[crate::gpt::ChatMessage],
+    ) -> Result<crate::gpt::backend::Completion, crate::Error> {
+        Ok(crate::gpt::backend::Completion {
+            message: self.next_response(messages),
+            usage: Some(crate::gpt::backend::Usage {
+                prompt_tokens: messages.len() as u32,
+                completion_tokens: 1,
+            }),
+        })
+    }
+
+    async fn stream_completion(
+        &self,
+        _model: &str,
+        messages: &[crate::gpt::ChatMessage],
+    ) -> Result<crate::gpt::backend::CompletionStream, crate::Error> {
+        // Emit one delta per word to mimic a real token stream
+        let mut deltas = self
+            .next_response(messages)
+            .content
+            .unwrap_or_default()
+            .split_inclusive(' ')
+            .map(|word| crate::gpt::backend::CompletionEvent::Delta(openai::chat::ChatCompletionMessageDelta {
+                role: Some(openai::chat::ChatCompletionMessageRole::Assistant),
+                content: Some(word.to_string()),
+                name: None,
+                function_call: None,
+            }))
+            .collect::<Vec<_>>();
+        let usage = crate::gpt::backend::Usage {
+            prompt_tokens: messages.len() as u32,
+            completion_tokens: deltas.len() as u32,
+        };
+        deltas.push(crate::gpt::backend::CompletionEvent::Finish { reason: "stop".to_string() });
+        deltas.push(crate::gpt::backend::CompletionEvent::Usage(usage));
+
+        Ok(Box::pin(tokio_stream::iter(deltas)))
+    }
+}